@@ -0,0 +1,4 @@
+ALTER TABLE records drop column forward_date;
+ALTER TABLE records drop column forward_origin;
+ALTER TABLE records drop column forward_source_record_id;
+ALTER TABLE records drop column forward_source_id;
@@ -0,0 +1,4 @@
+ALTER TABLE records add column forward_source_id int constraint records_forward_source_id_fk references sources;
+ALTER TABLE records add column forward_source_record_id text;
+ALTER TABLE records add column forward_origin text;
+ALTER TABLE records add column forward_date timestamp;
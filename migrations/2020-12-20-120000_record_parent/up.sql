@@ -0,0 +1,2 @@
+ALTER TABLE records add column parent_id int constraint records_parent_id_fk references records;
+create index records_parent_id on records (parent_id);
@@ -0,0 +1 @@
+ALTER TABLE records drop column raw_content;
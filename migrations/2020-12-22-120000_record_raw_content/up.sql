@@ -0,0 +1 @@
+ALTER TABLE records add column raw_content text;
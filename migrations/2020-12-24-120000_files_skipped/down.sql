@@ -0,0 +1 @@
+ALTER TABLE files drop column skipped;
@@ -0,0 +1 @@
+ALTER TABLE files add column skipped text;
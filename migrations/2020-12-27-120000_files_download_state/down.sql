@@ -0,0 +1,4 @@
+ALTER TABLE files drop column download_attempted_at;
+ALTER TABLE files drop column download_error;
+ALTER TABLE files drop column download_attempts;
+ALTER TABLE files drop column download_state;
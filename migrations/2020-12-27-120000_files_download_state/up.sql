@@ -0,0 +1,9 @@
+ALTER TABLE files add column download_state text not null default 'QUEUED';
+ALTER TABLE files add column download_attempts int not null default 0;
+ALTER TABLE files add column download_error text;
+ALTER TABLE files add column download_attempted_at timestamp;
+
+UPDATE files set download_state = 'DONE' where local_path is not null;
+UPDATE files set download_state = 'SKIPPED' where skipped is not null;
+
+create index files_download_state on files (download_state);
@@ -0,0 +1 @@
+ALTER TABLE files drop column original_id;
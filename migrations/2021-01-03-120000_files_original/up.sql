@@ -0,0 +1,3 @@
+ALTER TABLE files add column original_id int constraint files_original_id references files;
+
+create index files_original_id on files (original_id);
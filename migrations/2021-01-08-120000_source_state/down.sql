@@ -0,0 +1 @@
+ALTER TABLE sources drop column state;
@@ -0,0 +1 @@
+ALTER TABLE sources add column state text not null default 'ACTIVE';
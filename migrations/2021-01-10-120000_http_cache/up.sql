@@ -0,0 +1,7 @@
+CREATE TABLE http_cache (
+    source_id int primary key constraint http_cache_source_id references sources,
+    etag text,
+    last_modified text,
+    content_hash text,
+    updated_at timestamp not null default now()
+);
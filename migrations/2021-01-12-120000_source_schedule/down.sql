@@ -0,0 +1,3 @@
+ALTER TABLE sources drop column next_scrape_time;
+ALTER TABLE sources drop column adaptive_interval;
+ALTER TABLE sources drop column scrape_interval;
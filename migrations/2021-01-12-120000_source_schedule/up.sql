@@ -0,0 +1,5 @@
+ALTER TABLE sources add column scrape_interval int;
+ALTER TABLE sources add column adaptive_interval int;
+ALTER TABLE sources add column next_scrape_time timestamp not null default (now() at time zone 'utc');
+
+create index sources_next_scrape_time on sources (next_scrape_time);
@@ -0,0 +1,8 @@
+CREATE TABLE source_health (
+    source_id int primary key constraint source_health_source_id references sources,
+    last_success_time timestamp,
+    last_error_time timestamp,
+    last_error text,
+    consecutive_failures int not null default 0,
+    last_http_status int
+);
@@ -0,0 +1 @@
+ALTER TABLE sources add column feed_kind text;
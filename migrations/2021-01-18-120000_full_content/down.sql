@@ -0,0 +1,2 @@
+ALTER TABLE records drop column summary;
+ALTER TABLE sources drop column fetch_full_content;
@@ -0,0 +1,2 @@
+ALTER TABLE sources add column fetch_full_content boolean not null default false;
+ALTER TABLE records add column summary text;
@@ -0,0 +1,9 @@
+CREATE TABLE websub_subscriptions (
+    source_id int primary key constraint websub_subscriptions_source_id references sources,
+    hub text not null,
+    topic text not null,
+    secret text not null,
+    state text not null,
+    lease_expires timestamp,
+    updated_at timestamp not null default now()
+);
@@ -0,0 +1,5 @@
+CREATE TABLE webhook_tokens (
+    source_id int primary key constraint webhook_tokens_source_id references sources,
+    token_hash text not null,
+    created_at timestamp not null default now()
+);
@@ -0,0 +1 @@
+ALTER TABLE sources add column image_remote_id text;
@@ -0,0 +1 @@
+ALTER TABLE records drop column original_content;
@@ -1,13 +1,18 @@
 // TODO: no needs for aggregator, handler can be used directly
 use crate::models;
 use crate::result::Result;
+use crate::sanitize::Sanitizer;
 use crate::storage::Storage;
 use crate::updates::Source;
-use crate::{config, updates};
+use crate::{config, file_store, updates};
 use std::sync::Arc;
+use tokio::sync::watch;
+
+pub use crate::updates::tg::AuthState;
 
 pub struct Aggregator<S: Storage + Send + Sync + Clone + 'static> {
     handler: updates::SourcesAggregator<S>,
+    sanitizer: Sanitizer,
 }
 
 impl<S> Aggregator<S>
@@ -15,20 +20,151 @@ where
     S: Storage + Send + Sync + Clone + 'static,
 {
     pub fn new(handler: updates::SourcesAggregator<S>) -> Self {
-        Self { handler }
+        Self {
+            handler,
+            sanitizer: Sanitizer::default(),
+        }
     }
 
     pub async fn run(&self) {
         self.handler.run().await
     }
 
+    /// Searches saved sources
     pub async fn search_source(&self, query: &str) -> Result<Vec<models::Source>> {
         self.handler.search_source(query).await
     }
 
+    /// Searches new web feeds and Telegram channels; nothing is saved until `subscribe`
+    pub async fn discover_sources(&self, query: &str) -> Result<Vec<models::SourceCandidate>> {
+        self.handler.discover_sources(query).await
+    }
+
+    /// Saves the source found by `discover_sources` and starts receiving its updates;
+    /// Telegram chat is joined by the account which found it. Source subscribed by another
+    /// account isn't moved, use `join_source` for that
+    pub async fn subscribe(&self, candidate: &models::SourceCandidate) -> Result<models::Source> {
+        self.handler.subscribe(candidate).await
+    }
+
     pub async fn synchronize(&self, secs_depth: i32, source: Option<Source>) -> Result<()> {
         self.handler.synchronize(secs_depth, source).await
     }
+
+    /// Returns files which downloads failed
+    pub async fn failed_downloads(&self) -> Result<Vec<models::File>> {
+        self.handler.failed_downloads().await
+    }
+
+    /// Queues failed file for download again
+    pub async fn requeue_download(&self, file_id: i32) -> Result<()> {
+        self.handler.requeue_download(file_id).await
+    }
+
+    /// Joins the Telegram chat of the source with the account, e.g. to move the source
+    /// to another account; the main account is used if not specified
+    pub async fn join_source(&self, source: &models::Source, account: Option<&str>) -> Result<()> {
+        self.handler.join_source(source, account).await
+    }
+
+    /// Unsubscribes from the source: leaves Telegram chat or stops web scraping.
+    ///
+    /// Source is kept disabled unless `delete` is set, then it's deleted with its records
+    /// and files.
+    pub async fn remove_source(&self, source_id: i32, delete: bool) -> Result<()> {
+        self.handler.remove_source(source_id, delete).await
+    }
+
+    /// Skips source scraping and updates, data is kept
+    pub async fn pause_source(&self, source_id: i32) -> Result<()> {
+        self.handler.pause_source(source_id).await
+    }
+
+    /// Resumes paused source or subscribes to disabled one again
+    pub async fn resume_source(&self, source_id: i32) -> Result<()> {
+        self.handler.resume_source(source_id).await
+    }
+
+    /// Returns scrape results of the web source: last success and error, failures in a row
+    pub async fn source_health(&self, source_id: i32) -> Result<Option<models::SourceHealth>> {
+        self.handler.source_health(source_id).await
+    }
+
+    /// Returns health of the web sources which failed at least `min_failures` times in a row
+    pub async fn failing_sources(&self, min_failures: i32) -> Result<Vec<models::SourceHealth>> {
+        self.handler.failing_sources(min_failures).await
+    }
+
+    /// Sets scrape interval of the web source in seconds, `None` enables adaptive interval
+    pub async fn set_scrape_interval(&self, source_id: i32, interval: Option<i32>) -> Result<()> {
+        self.handler.set_scrape_interval(source_id, interval).await
+    }
+
+    /// Replaces content of new records of the web source with articles fetched from
+    /// the records links, feed content is kept as summary
+    pub async fn set_fetch_full_content(&self, source_id: i32, enabled: bool) -> Result<()> {
+        self.handler
+            .set_fetch_full_content(source_id, enabled)
+            .await
+    }
+
+    /// Adds source which records are pushed by other systems, returns it with the token
+    /// of its endpoint; the token can't be read later, only reset
+    pub async fn add_webhook_source(&self, name: &str) -> Result<(models::Source, String)> {
+        self.handler.add_webhook_source(name).await
+    }
+
+    pub async fn reset_webhook_token(&self, source_id: i32) -> Result<String> {
+        self.handler.reset_webhook_token(source_id).await
+    }
+
+    /// Returns authorization state of the Telegram account, the main account if not specified
+    pub fn auth_state(&self, account: Option<&str>) -> Result<AuthState> {
+        self.handler.auth_state(account)
+    }
+
+    /// Returns receiver of the account authorization state changes, so UI can drive
+    /// login and re-login after session expiry
+    pub fn auth_state_stream(&self, account: Option<&str>) -> Result<watch::Receiver<AuthState>> {
+        self.handler.auth_state_stream(account)
+    }
+
+    /// Submits login code in `AuthState::WaitCode` state
+    pub async fn submit_auth_code(&self, account: Option<&str>, code: &str) -> Result<()> {
+        self.handler.submit_auth_code(account, code).await
+    }
+
+    /// Submits 2FA password in `AuthState::WaitPassword` state
+    pub async fn submit_auth_password(&self, account: Option<&str>, password: &str) -> Result<()> {
+        self.handler.submit_auth_password(account, password).await
+    }
+
+    pub async fn log_out(&self, account: Option<&str>) -> Result<()> {
+        self.handler.log_out(account).await
+    }
+
+    /// Logs in again after the session is closed (`AuthState::Closed`): the client is rebuilt
+    /// and the login continues with `submit_auth_code`, as on the first start
+    pub async fn relogin(&self, account: Option<&str>) -> Result<()> {
+        self.handler.restart_client(account).await
+    }
+
+    /// Renders record content to the specified format.
+    ///
+    /// Records without raw content (e.g. web records) are returned as is.
+    pub fn render_content(
+        &self,
+        record: &models::Record,
+        format: config::TextFormat,
+    ) -> Result<String> {
+        match (&record.raw_content, format) {
+            (None, _) => Ok(record.content.clone()),
+            (Some(raw_content), config::TextFormat::Html) => Ok(self
+                .sanitizer
+                .clean(&updates::tg::render_raw_content(raw_content, format)?, None)),
+            (Some(raw_content), _) => updates::tg::render_raw_content(raw_content, format),
+        }
+    }
 }
 
 pub struct AggregatorBuilder<'a, S>
@@ -47,36 +183,91 @@ where
         Self { config, storage }
     }
 
-    pub fn build(&self) -> Aggregator<S> {
+    /// Builds aggregator, fails if a file store config is invalid,
+    /// e.g. S3 store is configured without `s3-store` feature
+    pub fn build(&self) -> Result<Aggregator<S>> {
         debug!("config for building: {:?}", self.config);
         let mut updates_builder =
             updates::SourcesAggregator::builder().with_storage(self.storage.clone());
+        let file_store = file_store::from_config(&self.config.file_store())?;
 
         if self.config.http().enabled() {
             let http_source = updates::http::HttpSource::builder()
                 .with_sleep_secs(self.config.http().sleep_secs())
+                .with_scrape_source_secs_interval(self.config.http().scrape_source_secs_interval())
+                .with_scrape_secs_bounds(
+                    self.config.http().min_scrape_secs_interval(),
+                    self.config.http().max_scrape_secs_interval(),
+                )
+                .with_max_failures(self.config.http().max_failures())
+                .with_full_content(self.config.http().full_content())
+                .with_sanitize(self.config.sanitize())
+                .with_websub(self.config.http().websub())
+                .with_media_cache(self.config.http().media_cache(), file_store)
                 .with_storage(self.storage.clone())
                 .build();
             let http_source = Arc::new(http_source);
             updates_builder = updates_builder.with_http_source(http_source);
         }
 
-        if self.config.telegram().enabled() {
+        for telegram in self.config.telegram_accounts() {
+            let tg_file_store =
+                file_store::from_config(&self.config.telegram_file_store(telegram))?;
             let tg_source = updates::tg::TelegramSource::builder(
-                self.config.telegram().api_id(),
-                self.config.telegram().api_hash(),
-                self.config.telegram().phone(),
-                self.config.telegram().max_download_queue_size(),
-                self.config.telegram().files_directory(),
-                self.config.telegram().log_download_state_secs_interval(),
+                telegram.api_id(),
+                telegram.api_hash(),
+                telegram.phone(),
+                telegram.max_download_queue_size(),
+                telegram.files_directory(),
+                telegram.log_download_state_secs_interval(),
+            )
+            .with_account(telegram.account())
+            .with_database_directory(telegram.database_directory())
+            .with_log_verbosity_level(telegram.log_verbosity_level())
+            .with_skip_known_forwards(telegram.skip_known_forwards())
+            .with_comments_channels(telegram.comments_channels())
+            .with_text_format(telegram.text_format())
+            .with_download_policy(
+                telegram.download_policy(),
+                telegram.source_download_policies(),
+            )
+            .with_download_retry(
+                telegram.download_retry_secs_interval(),
+                telegram.download_max_attempts(),
+                telegram.download_timeout_secs(),
             )
-            .with_database_directory(self.config.telegram().database_directory())
-            .with_log_verbosity_level(self.config.telegram().log_verbosity_level())
+            .with_thumbnails(telegram.thumbnails())
+            .with_sanitize(self.config.sanitize())
+            .with_file_store(tg_file_store)
             .with_storage(self.storage.clone())
             .build();
             let tg_source = Arc::new(tg_source);
             updates_builder = updates_builder.with_tg_source(tg_source);
         }
-        Aggregator::new(updates_builder.build())
+        if self.config.local().enabled {
+            let local_source = updates::local::LocalSource::builder()
+                .with_directories(&self.config.local().directories)
+                .with_poll_secs_interval(self.config.local().poll_secs_interval)
+                .with_sanitize(self.config.sanitize())
+                .with_storage(self.storage.clone())
+                .build();
+            updates_builder = updates_builder.with_local_source(Arc::new(local_source));
+        }
+        if self.config.webhook().enabled {
+            let webhook_source = updates::webhook::WebhookSource::builder()
+                .with_listen_address(
+                    &self.config.webhook().listen_address,
+                    &self.config.webhook().public_url,
+                )
+                .with_max_body_size(self.config.webhook().max_body_size)
+                .with_sanitize(self.config.sanitize())
+                .with_storage(self.storage.clone())
+                .build();
+            updates_builder = updates_builder.with_webhook_source(Arc::new(webhook_source));
+        }
+        Ok(Aggregator {
+            handler: updates_builder.build(),
+            sanitizer: Sanitizer::new(self.config.sanitize()),
+        })
     }
 }
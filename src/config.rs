@@ -1,8 +1,22 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
 #[derive(Clone, Debug, Builder)]
 #[builder(default)]
 pub struct AggregatorConfig {
     http: HttpConfig,
     telegram: TelegramConfig,
+    /// Additional telegram accounts
+    telegram_accounts: Vec<TelegramConfig>,
+    /// Where downloaded files are kept, sharded `telegram.files_directory` if not set
+    file_store: Option<FileStoreConfig>,
+    /// Sanitization of the records content of all sources
+    sanitize: SanitizeConfig,
+    /// Feeds and documents from the local directories
+    local: LocalConfig,
+    /// Records pushed by other systems
+    webhook: WebhookConfig,
 }
 
 impl AggregatorConfig {
@@ -13,6 +27,40 @@ impl AggregatorConfig {
     pub fn telegram(&self) -> &TelegramConfig {
         &self.telegram
     }
+
+    /// Enabled telegram accounts, the main one goes first
+    pub fn telegram_accounts(&self) -> Vec<&TelegramConfig> {
+        std::iter::once(&self.telegram)
+            .chain(self.telegram_accounts.iter())
+            .filter(|t| t.enabled)
+            .collect()
+    }
+
+    pub fn sanitize(&self) -> &SanitizeConfig {
+        &self.sanitize
+    }
+
+    pub fn local(&self) -> &LocalConfig {
+        &self.local
+    }
+
+    pub fn webhook(&self) -> &WebhookConfig {
+        &self.webhook
+    }
+
+    pub fn file_store(&self) -> FileStoreConfig {
+        self.telegram_file_store(&self.telegram)
+    }
+
+    /// File store of the account, account `files_directory` if file store isn't set
+    pub fn telegram_file_store(&self, telegram: &TelegramConfig) -> FileStoreConfig {
+        match &self.file_store {
+            Some(file_store) => file_store.clone(),
+            None => FileStoreConfig::Local {
+                directory: telegram.files_directory.clone(),
+            },
+        }
+    }
 }
 
 impl Default for AggregatorConfig {
@@ -20,6 +68,11 @@ impl Default for AggregatorConfig {
         Self {
             http: HttpConfig::default(),
             telegram: TelegramConfig::default(),
+            telegram_accounts: vec![],
+            file_store: None,
+            sanitize: SanitizeConfig::default(),
+            local: LocalConfig::default(),
+            webhook: WebhookConfig::default(),
         }
     }
 }
@@ -28,7 +81,22 @@ impl Default for AggregatorConfig {
 pub struct HttpConfig {
     enabled: bool,
     sleep_secs: u64,
+    /// Scrape interval of the sources which publishing frequency isn't known yet
     scrape_source_secs_interval: i32,
+    /// Bounds of the adaptive scrape interval
+    #[builder(default = "60")]
+    min_scrape_secs_interval: i32,
+    #[builder(default = "86400")]
+    max_scrape_secs_interval: i32,
+    /// Source is disabled after this number of failed scrapes in a row
+    #[builder(default = "10")]
+    max_failures: i32,
+    #[builder(default)]
+    media_cache: MediaCacheConfig,
+    #[builder(default)]
+    full_content: FullContentConfig,
+    #[builder(default)]
+    websub: WebSubConfig,
 }
 
 impl HttpConfig {
@@ -41,6 +109,24 @@ impl HttpConfig {
     pub fn scrape_source_secs_interval(&self) -> i32 {
         self.scrape_source_secs_interval
     }
+    pub fn min_scrape_secs_interval(&self) -> i32 {
+        self.min_scrape_secs_interval
+    }
+    pub fn max_scrape_secs_interval(&self) -> i32 {
+        self.max_scrape_secs_interval
+    }
+    pub fn max_failures(&self) -> i32 {
+        self.max_failures
+    }
+    pub fn media_cache(&self) -> &MediaCacheConfig {
+        &self.media_cache
+    }
+    pub fn full_content(&self) -> &FullContentConfig {
+        &self.full_content
+    }
+    pub fn websub(&self) -> &WebSubConfig {
+        &self.websub
+    }
 }
 
 impl Default for HttpConfig {
@@ -49,6 +135,160 @@ impl Default for HttpConfig {
             enabled: false,
             sleep_secs: 60,
             scrape_source_secs_interval: 60,
+            min_scrape_secs_interval: 60,
+            max_scrape_secs_interval: 86400,
+            max_failures: 10,
+            media_cache: MediaCacheConfig::default(),
+            full_content: FullContentConfig::default(),
+            websub: WebSubConfig::default(),
+        }
+    }
+}
+
+/// Caching of the web records images in the file store
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct MediaCacheConfig {
+    pub enabled: bool,
+    /// Replace image links in the record content and image with the cached files urls,
+    /// requires `public_url`
+    pub rewrite_content: bool,
+    /// Max file size in bytes
+    pub max_file_size: Option<i64>,
+    /// Urls the cached files are served at
+    pub public_url: Option<PublicUrlConfig>,
+}
+
+/// Maps file store locations to urls: `location_prefix` of the location is replaced with `url`,
+/// e.g. `s3://bucket/` with `https://cdn.example.com/`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PublicUrlConfig {
+    pub location_prefix: String,
+    pub url: String,
+}
+
+impl PublicUrlConfig {
+    /// Returns url of the file store location, `None` if the location isn't under the prefix
+    pub fn url(&self, location: &str) -> Option<String> {
+        location
+            .strip_prefix(&self.location_prefix)
+            .map(|path| format!("{}{}", self.url, path))
+    }
+}
+
+/// WebSub push subscriptions of the web feeds which advertise a hub
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WebSubConfig {
+    pub enabled: bool,
+    /// Address the callback listener binds to
+    pub listen_address: String,
+    /// Public url the listener is reachable by hubs at
+    pub callback_url: String,
+    /// Lease requested from hubs
+    pub lease_secs: i32,
+    /// Subscriptions are renewed this time before the lease expires
+    pub renew_before_secs: i32,
+    /// Failed subscriptions and feeds without a hub are retried after this interval
+    pub retry_secs_interval: i32,
+    /// Subscription requests not verified by the hub during this time are retried,
+    /// unsubscriptions are dropped
+    pub verify_timeout_secs: i32,
+    /// Max size of the notification body in bytes
+    pub max_body_size: usize,
+}
+
+impl Default for WebSubConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_address: "0.0.0.0:8090".to_string(),
+            callback_url: "http://localhost:8090".to_string(),
+            lease_secs: 10 * 24 * 60 * 60,
+            renew_before_secs: 24 * 60 * 60,
+            retry_secs_interval: 24 * 60 * 60,
+            verify_timeout_secs: 60 * 60,
+            max_body_size: 1024 * 1024,
+        }
+    }
+}
+
+/// Sanitization of the records html content, allow-lists are replaced if set
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SanitizeConfig {
+    pub enabled: bool,
+    pub tags: Option<HashSet<String>>,
+    /// Attributes allowed for all tags
+    pub generic_attributes: Option<HashSet<String>>,
+    /// Attributes allowed for the tag
+    pub tag_attributes: Option<HashMap<String, HashSet<String>>>,
+}
+
+impl Default for SanitizeConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            tags: None,
+            generic_attributes: None,
+            tag_attributes: None,
+        }
+    }
+}
+
+/// Fetching of the article pages for sources with full content enabled
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct FullContentConfig {
+    /// Min interval between requests to the same host
+    pub host_secs_interval: u64,
+    pub timeout_secs: u64,
+}
+
+impl Default for FullContentConfig {
+    fn default() -> Self {
+        Self {
+            host_secs_interval: 5,
+            timeout_secs: 30,
+        }
+    }
+}
+
+/// Watched directories: feed files (RSS, Atom, JSON Feed) are sources,
+/// Markdown and HTML documents are records of the directory source
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LocalConfig {
+    pub enabled: bool,
+    pub directories: Vec<String>,
+    /// Interval of checking the directories for new and modified files
+    pub poll_secs_interval: u64,
+}
+
+impl Default for LocalConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            directories: vec![],
+            poll_secs_interval: 10,
+        }
+    }
+}
+
+/// Endpoints records of the webhook sources are pushed to
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub enabled: bool,
+    /// Address the endpoints listener binds to
+    pub listen_address: String,
+    /// Public url the listener is reachable by the pushing systems at
+    pub public_url: String,
+    /// Max size of the request body in bytes
+    pub max_body_size: usize,
+}
+
+impl Default for WebhookConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen_address: "0.0.0.0:8091".to_string(),
+            public_url: "http://localhost:8091".to_string(),
+            max_body_size: 1024 * 1024,
         }
     }
 }
@@ -56,6 +296,9 @@ impl Default for HttpConfig {
 #[derive(Clone, Debug, Builder)]
 pub struct TelegramConfig {
     enabled: bool,
+    /// Name to tag sources of the account with, phone is used if not set
+    #[builder(default)]
+    account: Option<String>,
     database_directory: String,
     log_verbosity_level: i32,
     api_id: i64,
@@ -64,12 +307,33 @@ pub struct TelegramConfig {
     max_download_queue_size: usize,
     files_directory: String,
     log_download_state_secs_interval: u64,
+    #[builder(default)]
+    skip_known_forwards: bool,
+    #[builder(default)]
+    comments_channels: Vec<String>,
+    #[builder(default)]
+    text_format: TextFormat,
+    #[builder(default)]
+    download_policy: DownloadPolicy,
+    #[builder(default)]
+    source_download_policies: HashMap<String, DownloadPolicy>,
+    #[builder(default = "60")]
+    download_retry_secs_interval: i32,
+    #[builder(default = "5")]
+    download_max_attempts: i32,
+    #[builder(default = "3600")]
+    download_timeout_secs: i32,
+    #[builder(default)]
+    thumbnails: ThumbnailsConfig,
 }
 
 impl TelegramConfig {
     pub fn enabled(&self) -> bool {
         self.enabled
     }
+    pub fn account(&self) -> &str {
+        self.account.as_deref().unwrap_or(&self.phone)
+    }
     pub fn database_directory(&self) -> &str {
         &self.database_directory
     }
@@ -94,12 +358,42 @@ impl TelegramConfig {
     pub fn log_download_state_secs_interval(&self) -> u64 {
         self.log_download_state_secs_interval
     }
+    pub fn skip_known_forwards(&self) -> bool {
+        self.skip_known_forwards
+    }
+    /// Chat ids or usernames of channels to ingest discussion comments for
+    pub fn comments_channels(&self) -> &[String] {
+        &self.comments_channels
+    }
+    pub fn text_format(&self) -> TextFormat {
+        self.text_format
+    }
+    pub fn download_policy(&self) -> &DownloadPolicy {
+        &self.download_policy
+    }
+    /// Download policies overrides by chat id or username
+    pub fn source_download_policies(&self) -> &HashMap<String, DownloadPolicy> {
+        &self.source_download_policies
+    }
+    pub fn download_retry_secs_interval(&self) -> i32 {
+        self.download_retry_secs_interval
+    }
+    pub fn download_max_attempts(&self) -> i32 {
+        self.download_max_attempts
+    }
+    pub fn download_timeout_secs(&self) -> i32 {
+        self.download_timeout_secs
+    }
+    pub fn thumbnails(&self) -> &ThumbnailsConfig {
+        &self.thumbnails
+    }
 }
 
 impl Default for TelegramConfig {
     fn default() -> Self {
         Self {
             enabled: false,
+            account: None,
             database_directory: "tdlib".to_string(),
             log_verbosity_level: 0,
             api_id: 0,
@@ -108,6 +402,125 @@ impl Default for TelegramConfig {
             max_download_queue_size: 1,
             files_directory: "".to_string(),
             log_download_state_secs_interval: 0,
+            skip_known_forwards: false,
+            comments_channels: vec![],
+            text_format: TextFormat::default(),
+            download_policy: DownloadPolicy::default(),
+            source_download_policies: HashMap::new(),
+            download_retry_secs_interval: 60,
+            download_max_attempts: 5,
+            download_timeout_secs: 3600,
+            thumbnails: ThumbnailsConfig::default(),
+        }
+    }
+}
+
+/// Output format of the formatted texts, e.g. Telegram messages
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TextFormat {
+    Html,
+    Markdown,
+    Plain,
+}
+
+impl Default for TextFormat {
+    fn default() -> Self {
+        Self::Html
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DownloadFileType {
+    Document,
+    Animation,
+    Image,
+}
+
+/// Which sizes of the photo to download
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum PhotoSizesPolicy {
+    All,
+    LargestOnly,
+    /// Only the smallest size of the photo
+    ThumbnailsOnly,
+}
+
+impl Default for PhotoSizesPolicy {
+    fn default() -> Self {
+        Self::All
+    }
+}
+
+/// Rules for files downloading; files which are not downloaded are still saved
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DownloadPolicy {
+    /// Never download files
+    pub skip_all: bool,
+    /// Allowed file types, all types are allowed if empty
+    pub file_types: Vec<DownloadFileType>,
+    /// Max file size in bytes
+    pub max_file_size: Option<i64>,
+    pub photo_sizes: PhotoSizesPolicy,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ThumbnailFormat {
+    Jpeg,
+    Webp,
+}
+
+/// Thumbnails of downloaded images and previews of animations
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ThumbnailsConfig {
+    /// Max width and height of thumbnails, thumbnails are disabled if empty
+    pub sizes: Vec<u32>,
+    pub format: ThumbnailFormat,
+    /// Encoding quality, 1-100
+    pub quality: u8,
+}
+
+impl Default for ThumbnailsConfig {
+    fn default() -> Self {
+        Self {
+            sizes: vec![160, 640],
+            format: ThumbnailFormat::Jpeg,
+            quality: 80,
         }
     }
 }
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum FileStoreConfig {
+    /// Local directory, files are sharded to subdirectories
+    Local { directory: String },
+    /// Local directory, files are named by SHA-256 of the content, so duplicates are stored once
+    ContentAddressed { directory: String },
+    /// S3-compatible storage, requires `s3-store` feature
+    S3(S3Config),
+}
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct S3Config {
+    pub bucket: String,
+    pub region: String,
+    /// Custom endpoint, e.g. `http://localhost:9000` for MinIO
+    pub endpoint: Option<String>,
+    pub access_key: String,
+    pub secret_key: String,
+    /// Prefix of the object keys
+    pub prefix: String,
+}
+
+impl fmt::Debug for S3Config {
+    // config is logged, secret key is kept out of the logs
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("S3Config")
+            .field("bucket", &self.bucket)
+            .field("region", &self.region)
+            .field("endpoint", &self.endpoint)
+            .field("access_key", &self.access_key)
+            .field("secret_key", &"<redacted>")
+            .field("prefix", &self.prefix)
+            .finish()
+    }
+}
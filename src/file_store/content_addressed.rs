@@ -0,0 +1,69 @@
+use super::{file_sha256, move_file, shard, FileStore};
+use crate::result::Result;
+use async_trait::async_trait;
+use std::path::{Path, PathBuf};
+
+/// Keeps files in the local directory by the content hash, so identical files are stored once
+pub struct ContentAddressedFileStore {
+    directory: PathBuf,
+}
+
+impl ContentAddressedFileStore {
+    pub fn new(directory: &str) -> Self {
+        Self {
+            directory: PathBuf::from(directory),
+        }
+    }
+}
+
+#[async_trait]
+impl FileStore for ContentAddressedFileStore {
+    async fn put(&self, local_path: &Path, file_name: &str) -> Result<String> {
+        let hash = file_sha256(local_path).await?;
+        let directory = self.directory.join(shard(&hash));
+        tokio::fs::create_dir_all(&directory).await?;
+        let path = match Path::new(file_name).extension() {
+            Some(extension) => directory.join(format!("{}.{}", hash, extension.to_string_lossy())),
+            None => directory.join(&hash),
+        };
+        if path.exists() {
+            debug!("file {:?} is already stored as {:?}", local_path, path);
+            tokio::fs::remove_file(local_path).await?;
+        } else {
+            move_file(local_path, &path).await?;
+        }
+        Ok(path.to_string_lossy().to_string())
+    }
+
+    async fn remove(&self, location: &str) -> Result<()> {
+        // content is shared by the files of the same hash, callers check no files reference it
+        Ok(tokio::fs::remove_file(location).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ContentAddressedFileStore;
+    use crate::file_store::FileStore;
+
+    #[tokio::test]
+    async fn test_put_deduplicates() {
+        let directory = std::env::temp_dir().join("agg-r-content-addressed-file-store");
+        tokio::fs::create_dir_all(&directory).await.unwrap();
+        let store = ContentAddressedFileStore::new(directory.to_str().unwrap());
+        let mut locations = vec![];
+        for (name, content) in &[("a.jpg", "same"), ("b.jpg", "same"), ("c.jpg", "other")] {
+            let source = directory.join(name);
+            tokio::fs::write(&source, content).await.unwrap();
+            locations.push(store.put(&source, name).await.unwrap());
+            assert!(!source.exists());
+        }
+        assert_eq!(locations[0], locations[1]);
+        assert_ne!(locations[0], locations[2]);
+        assert!(locations[0].ends_with(".jpg"));
+        assert_eq!(tokio::fs::read(&locations[0]).await.unwrap(), b"same");
+        store.remove(&locations[2]).await.unwrap();
+        assert!(!std::path::Path::new(&locations[2]).exists());
+        tokio::fs::remove_dir_all(&directory).await.unwrap();
+    }
+}
@@ -0,0 +1,120 @@
+use super::{move_file, safe_file_name, shard, FileStore};
+use crate::result::Result;
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+
+/// Keeps files in the local directory.
+///
+/// Files are sharded to subdirectories by the file name hash; a suffix is added to the name
+/// if the file with the same name is already stored.
+pub struct LocalFileStore {
+    directory: PathBuf,
+}
+
+impl LocalFileStore {
+    pub fn new(directory: &str) -> Self {
+        Self {
+            directory: PathBuf::from(directory),
+        }
+    }
+
+    /// Creates empty file with unused name, so concurrent `put` can't take the same name
+    async fn reserve_path(&self, file_name: &str) -> Result<PathBuf> {
+        let file_name = safe_file_name(file_name);
+        let file_name = file_name.as_str();
+        let hash = format!("{:x}", Sha256::digest(file_name.as_bytes()));
+        let directory = self.directory.join(shard(&hash));
+        tokio::fs::create_dir_all(&directory).await?;
+        let name = Path::new(file_name);
+        let stem = name
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let extension = name.extension().map(|e| e.to_string_lossy().to_string());
+        let mut attempt = 0;
+        loop {
+            let candidate = match (attempt, &extension) {
+                (0, _) => file_name.to_string(),
+                (_, Some(extension)) => format!("{}_{}.{}", stem, attempt, extension),
+                (_, None) => format!("{}_{}", stem, attempt),
+            };
+            let path = directory.join(candidate);
+            match tokio::fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+                .await
+            {
+                Ok(_) => return Ok(path),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => attempt += 1,
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl FileStore for LocalFileStore {
+    async fn put(&self, local_path: &Path, file_name: &str) -> Result<String> {
+        let path = self.reserve_path(file_name).await?;
+        if let Err(e) = move_file(local_path, &path).await {
+            tokio::fs::remove_file(&path).await?;
+            return Err(e);
+        }
+        // TODO: cross-platform?
+        Ok(path.to_string_lossy().to_string())
+    }
+
+    async fn remove(&self, location: &str) -> Result<()> {
+        Ok(tokio::fs::remove_file(location).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LocalFileStore;
+    use crate::file_store::FileStore;
+    use std::path::Path;
+
+    #[tokio::test]
+    async fn test_put_same_name() {
+        let directory = std::env::temp_dir().join("agg-r-local-file-store");
+        let store = LocalFileStore::new(directory.to_str().unwrap());
+        let mut locations = vec![];
+        for content in &["first", "second"] {
+            let source = directory.join(format!("{}.tmp", content));
+            tokio::fs::create_dir_all(&directory).await.unwrap();
+            tokio::fs::write(&source, content).await.unwrap();
+            locations.push(store.put(&source, "image.jpg").await.unwrap());
+            assert!(!source.exists());
+        }
+        assert_ne!(locations[0], locations[1]);
+        assert_eq!(
+            Path::new(&locations[0]).parent(),
+            Path::new(&locations[1]).parent()
+        );
+        assert_eq!(tokio::fs::read(&locations[1]).await.unwrap(), b"second");
+        for location in locations {
+            store.remove(&location).await.unwrap();
+        }
+        tokio::fs::remove_dir_all(&directory).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_put_outside_names() {
+        let directory = std::env::temp_dir().join("agg-r-local-file-store-outside");
+        tokio::fs::create_dir_all(&directory).await.unwrap();
+        let root = directory.join("store");
+        let store = LocalFileStore::new(root.to_str().unwrap());
+        for name in &["../../etc/x", "/tmp/x", "..\\..\\x", ".."] {
+            let source = directory.join("file.tmp");
+            tokio::fs::write(&source, "content").await.unwrap();
+            let location = store.put(&source, name).await.unwrap();
+            let location = tokio::fs::canonicalize(&location).await.unwrap();
+            let root = tokio::fs::canonicalize(&root).await.unwrap();
+            assert!(location.starts_with(&root), "{} escaped", name);
+        }
+        tokio::fs::remove_dir_all(&directory).await.unwrap();
+    }
+}
@@ -0,0 +1,110 @@
+use crate::config::FileStoreConfig;
+use crate::result::Result;
+use crate::storage::Storage;
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::AsyncReadExt;
+
+mod content_addressed;
+mod local;
+#[cfg(feature = "s3-store")]
+mod s3;
+
+pub use content_addressed::ContentAddressedFileStore;
+pub use local::LocalFileStore;
+#[cfg(feature = "s3-store")]
+pub use s3::S3FileStore;
+
+/// Keeps downloaded files
+#[async_trait]
+pub trait FileStore {
+    /// Moves local file to the store, returns location of the stored file
+    async fn put(&self, local_path: &Path, file_name: &str) -> Result<String>;
+    /// Removes file by location returned from `put`, see `remove_unreferenced`
+    async fn remove(&self, location: &str) -> Result<()>;
+}
+
+/// Creates file store from config
+pub fn from_config(config: &FileStoreConfig) -> Result<Arc<dyn FileStore + Send + Sync>> {
+    Ok(match config {
+        FileStoreConfig::Local { directory } => Arc::new(LocalFileStore::new(directory)),
+        FileStoreConfig::ContentAddressed { directory } => {
+            Arc::new(ContentAddressedFileStore::new(directory))
+        }
+        #[cfg(feature = "s3-store")]
+        FileStoreConfig::S3(s3_config) => Arc::new(S3FileStore::new(s3_config)?),
+        #[cfg(not(feature = "s3-store"))]
+        FileStoreConfig::S3(_) => {
+            return Err(crate::result::Error::FileStoreError(
+                "s3 file store requires `s3-store` feature".to_string(),
+            ))
+        }
+    })
+}
+
+/// Removes stored files which aren't referenced by files or source images anymore:
+/// content-addressed location is shared by all files of the same content
+pub(crate) async fn remove_unreferenced<S: Storage + Send + Sync>(
+    storage: &S,
+    file_store: &(dyn FileStore + Send + Sync),
+    locations: Vec<String>,
+) -> Result<()> {
+    if locations.is_empty() {
+        return Ok(());
+    }
+    let referenced = storage.get_referenced_locations(locations.clone()).await?;
+    for location in locations.iter().filter(|l| !referenced.contains(l)) {
+        file_store.remove(location).await?;
+    }
+    Ok(())
+}
+
+/// Returns hex-encoded SHA-256 of the file content
+pub(crate) async fn file_sha256(path: &Path) -> Result<String> {
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let read = file.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buf[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// `rename` error code for paths on different filesystems
+const EXDEV: i32 = 18;
+
+/// Moves file, falls back to copy when `rename` isn't possible across filesystems
+pub(crate) async fn move_file(from: &Path, to: &Path) -> Result<()> {
+    match tokio::fs::rename(from, to).await {
+        Ok(_) => Ok(()),
+        Err(e) if e.raw_os_error() == Some(EXDEV) => {
+            tokio::fs::copy(from, to).await?;
+            tokio::fs::remove_file(from).await?;
+            Ok(())
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Last component of the file name, so names from the sources can't point out of the store;
+/// `file` if nothing is left
+pub(crate) fn safe_file_name(file_name: &str) -> String {
+    file_name
+        .split(|c| c == '/' || c == '\\')
+        .map(str::trim)
+        .filter(|c| !c.is_empty() && *c != "." && *c != "..")
+        .last()
+        .unwrap_or("file")
+        .to_string()
+}
+
+/// Two levels of directories from the hash, e.g. `ab/cd`
+fn shard(hash: &str) -> String {
+    format!("{}/{}", &hash[0..2], &hash[2..4])
+}
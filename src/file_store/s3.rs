@@ -0,0 +1,115 @@
+use super::{file_sha256, safe_file_name, FileStore};
+use crate::config::S3Config;
+use crate::result::{Error, Result};
+use async_trait::async_trait;
+use rusoto_core::credential::StaticProvider;
+use rusoto_core::{HttpClient, Region};
+use rusoto_s3::{DeleteObjectRequest, PutObjectRequest, S3Client, S3};
+use std::path::Path;
+
+/// Keeps files in S3-compatible storage.
+///
+/// Objects are keyed by the content hash and the file name, location is `s3://<bucket>/<key>`.
+pub struct S3FileStore {
+    client: S3Client,
+    bucket: String,
+    prefix: String,
+}
+
+impl S3FileStore {
+    pub fn new(config: &S3Config) -> Result<Self> {
+        let region = match &config.endpoint {
+            Some(endpoint) => Region::Custom {
+                name: config.region.clone(),
+                endpoint: endpoint.clone(),
+            },
+            None => config
+                .region
+                .parse()
+                .map_err(|e| Error::FileStoreError(format!("{}", e)))?,
+        };
+        let http_client = HttpClient::new().map_err(|e| Error::FileStoreError(e.to_string()))?;
+        let credentials =
+            StaticProvider::new_minimal(config.access_key.clone(), config.secret_key.clone());
+        Ok(Self {
+            client: S3Client::new_with(http_client, credentials, region),
+            bucket: config.bucket.clone(),
+            prefix: config.prefix.trim_end_matches('/').to_string(),
+        })
+    }
+
+    fn key(&self, hash: &str, file_name: &str) -> String {
+        if self.prefix.is_empty() {
+            format!("{}/{}", hash, safe_file_name(file_name))
+        } else {
+            format!("{}/{}/{}", self.prefix, hash, safe_file_name(file_name))
+        }
+    }
+}
+
+#[async_trait]
+impl FileStore for S3FileStore {
+    async fn put(&self, local_path: &Path, file_name: &str) -> Result<String> {
+        let hash = file_sha256(local_path).await?;
+        let key = self.key(&hash, file_name);
+        let body = tokio::fs::read(local_path).await?;
+        self.client
+            .put_object(PutObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.clone(),
+                content_length: Some(body.len() as i64),
+                body: Some(body.into()),
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| Error::FileStoreError(e.to_string()))?;
+        tokio::fs::remove_file(local_path).await?;
+        Ok(format!("s3://{}/{}", self.bucket, key))
+    }
+
+    async fn remove(&self, location: &str) -> Result<()> {
+        let key = location
+            .strip_prefix(&format!("s3://{}/", self.bucket))
+            .ok_or_else(|| Error::FileStoreError(format!("unknown location: {}", location)))?;
+        self.client
+            .delete_object(DeleteObjectRequest {
+                bucket: self.bucket.clone(),
+                key: key.to_string(),
+                ..Default::default()
+            })
+            .await
+            .map_err(|e| Error::FileStoreError(e.to_string()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::S3FileStore;
+    use crate::config::S3Config;
+    use crate::file_store::FileStore;
+
+    /// Runs against local MinIO, e.g.
+    /// `docker run -p 9000:9000 minio/minio server /data` with `agg-r` bucket created
+    #[tokio::test]
+    #[ignore]
+    async fn test_put_remove() {
+        let env = |name: &str, default: &str| std::env::var(name).unwrap_or(default.to_string());
+        let store = S3FileStore::new(&S3Config {
+            bucket: env("S3_BUCKET", "agg-r"),
+            region: "us-east-1".to_string(),
+            endpoint: Some(env("S3_ENDPOINT", "http://localhost:9000")),
+            access_key: env("S3_ACCESS_KEY", "minioadmin"),
+            secret_key: env("S3_SECRET_KEY", "minioadmin"),
+            prefix: "test".to_string(),
+        })
+        .unwrap();
+        let source = std::env::temp_dir().join("agg-r-s3-file-store.txt");
+        tokio::fs::write(&source, "content").await.unwrap();
+        let location = store.put(&source, "file.txt").await.unwrap();
+        assert!(location.starts_with("s3://"));
+        assert!(location.ends_with("/file.txt"));
+        assert!(!source.exists());
+        store.remove(&location).await.unwrap();
+    }
+}
@@ -2,6 +2,8 @@
 extern crate log;
 #[macro_use]
 extern crate derive_builder;
+#[macro_use]
+extern crate lazy_static;
 
 #[cfg(feature = "pg-storage")]
 #[macro_use]
@@ -16,8 +18,11 @@ extern crate serde;
 
 pub mod aggregator;
 pub mod config;
+pub mod file_store;
 pub mod models;
 pub mod result;
+mod sanitize;
 pub mod storage;
+mod thumbnails;
 mod tools;
 mod updates;
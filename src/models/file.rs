@@ -1,3 +1,4 @@
+use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 
 #[cfg(feature = "pg-storage")]
@@ -6,6 +7,15 @@ use {
     diesel::{Insertable, Queryable},
 };
 
+pub const DOWNLOAD_QUEUED: &str = "QUEUED";
+pub const DOWNLOAD_DOWNLOADING: &str = "DOWNLOADING";
+pub const DOWNLOAD_DONE: &str = "DONE";
+pub const DOWNLOAD_FAILED: &str = "FAILED";
+pub const DOWNLOAD_SKIPPED: &str = "SKIPPED";
+
+pub const FILE_THUMBNAIL: &str = "THUMBNAIL";
+pub const FILE_PREVIEW: &str = "PREVIEW";
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 #[cfg_attr(feature = "pg-storage", derive(Queryable))]
 pub struct File {
@@ -19,6 +29,12 @@ pub struct File {
     #[serde(rename(serialize = "type", deserialize = "type"))]
     pub type_: String,
     pub meta: Option<String>,
+    pub skipped: Option<String>,
+    pub download_state: String,
+    pub download_attempts: i32,
+    pub download_error: Option<String>,
+    pub download_attempted_at: Option<NaiveDateTime>,
+    pub original_id: Option<i32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -33,4 +49,9 @@ pub struct NewFile {
     pub file_name: Option<String>,
     pub type_: String,
     pub meta: Option<String>,
+    // reason why the file isn't downloaded
+    pub skipped: Option<String>,
+    pub download_state: String,
+    // file which this one is derived from, e.g. thumbnail of the image
+    pub original_id: Option<i32>,
 }
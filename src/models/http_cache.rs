@@ -0,0 +1,30 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "pg-storage")]
+use {
+    crate::storage::schema::http_cache,
+    diesel::{Insertable, Queryable},
+};
+
+/// Validators of the latest web source response, used for conditional requests
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "pg-storage", derive(Queryable))]
+pub struct HttpCache {
+    pub source_id: i32,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// Hash of the response body, used if server doesn't support conditional requests
+    pub content_hash: Option<String>,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "pg-storage", derive(Insertable))]
+#[cfg_attr(feature = "pg-storage", table_name = "http_cache")]
+pub struct NewHttpCache {
+    pub source_id: i32,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub content_hash: Option<String>,
+}
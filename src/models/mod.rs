@@ -1,7 +1,23 @@
 mod file;
+mod http_cache;
 mod record;
 mod source;
+mod source_health;
+mod webhook_token;
+mod websub;
 
-pub use file::{File, NewFile};
+pub use file::{
+    File, NewFile, DOWNLOAD_DONE, DOWNLOAD_DOWNLOADING, DOWNLOAD_FAILED, DOWNLOAD_QUEUED,
+    DOWNLOAD_SKIPPED, FILE_PREVIEW, FILE_THUMBNAIL,
+};
+pub use http_cache::{HttpCache, NewHttpCache};
 pub use record::{NewRecord, Record};
-pub use source::{NewSource, Source};
+pub use source::{
+    NewSource, SampleItem, Source, SourceCandidate, SOURCE_ACTIVE, SOURCE_DISABLED, SOURCE_PAUSED,
+};
+pub use source_health::SourceHealth;
+pub use webhook_token::{NewWebhookToken, WebhookToken};
+pub use websub::{
+    NewWebSubSubscription, WebSubSubscription, WEBSUB_ACTIVE, WEBSUB_FAILED, WEBSUB_PENDING,
+    WEBSUB_UNSUBSCRIBING, WEBSUB_UNSUPPORTED,
+};
@@ -18,9 +18,19 @@ pub struct Record {
     pub date: NaiveDateTime,
     pub image: Option<String>,
     pub external_link: String,
+    pub forward_source_id: Option<i32>,
+    pub forward_source_record_id: Option<String>,
+    pub forward_origin: Option<String>,
+    pub forward_date: Option<NaiveDateTime>,
+    pub parent_id: Option<i32>,
+    pub raw_content: Option<String>,
+    /// Content from the source if it's replaced with the full article
+    pub summary: Option<String>,
+    /// Content before its image links are replaced with the cached images
+    pub original_content: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 #[cfg_attr(feature = "pg-storage", derive(Insertable))]
 #[cfg_attr(feature = "pg-storage", table_name = "records")]
 pub struct NewRecord {
@@ -31,4 +41,16 @@ pub struct NewRecord {
     pub content: String,
     pub date: Option<NaiveDateTime>,
     pub image: Option<String>,
+    // link to the record on the source site, default is used if not set
+    pub external_link: Option<String>,
+    // source of the original record, if it's known
+    pub forward_source_id: Option<i32>,
+    pub forward_source_record_id: Option<String>,
+    // json-serialized origin of the forwarded record
+    pub forward_origin: Option<String>,
+    pub forward_date: Option<NaiveDateTime>,
+    // record this record replies to
+    pub parent_id: Option<i32>,
+    // source-specific content representation, used to re-render content
+    pub raw_content: Option<String>,
 }
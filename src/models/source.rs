@@ -7,6 +7,12 @@ use {
     diesel::{Insertable, Queryable},
 };
 
+pub const SOURCE_ACTIVE: &str = "ACTIVE";
+/// Data is kept, but source stops producing records: it isn't scraped and its updates are skipped
+pub const SOURCE_PAUSED: &str = "PAUSED";
+/// Source is unsubscribed, e.g. Telegram chat is left
+pub const SOURCE_DISABLED: &str = "DISABLED";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[cfg_attr(feature = "pg-storage", derive(Queryable))]
 pub struct Source {
@@ -17,6 +23,21 @@ pub struct Source {
     pub image: Option<String>,
     pub last_scrape_time: NaiveDateTime,
     pub external_link: String,
+    /// Telegram account which receives updates of the source
+    pub account: Option<String>,
+    pub state: String,
+    /// Scrape interval in seconds set manually, overrides the adaptive one
+    pub scrape_interval: Option<i32>,
+    /// Scrape interval in seconds learned from the feed publishing frequency
+    pub adaptive_interval: Option<i32>,
+    /// UTC time of the next web source scrape
+    pub next_scrape_time: NaiveDateTime,
+    /// Format of the web feed, detected again if not set
+    pub feed_kind: Option<String>,
+    /// Records content is replaced with the article fetched from the record link
+    pub fetch_full_content: bool,
+    /// Remote id of the Telegram chat photo the image is stored from
+    pub image_remote_id: Option<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,4 +49,28 @@ pub struct NewSource {
     pub kind: String,
     pub image: Option<String>,
     pub external_link: String,
+    pub account: Option<String>,
+    pub feed_kind: Option<String>,
+}
+
+/// Source found by `discover_sources`, it's saved only when subscribed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SourceCandidate {
+    pub kind: String,
+    pub origin: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub image: Option<String>,
+    pub external_link: String,
+    /// Telegram account which found the source
+    pub account: Option<String>,
+    /// Latest items of the source for preview
+    pub sample_items: Vec<SampleItem>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SampleItem {
+    pub title: Option<String>,
+    pub content: String,
+    pub date: Option<NaiveDateTime>,
 }
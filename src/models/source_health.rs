@@ -0,0 +1,20 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "pg-storage")]
+use diesel::Queryable;
+
+/// Scrape results of the web source
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "pg-storage", derive(Queryable))]
+pub struct SourceHealth {
+    pub source_id: i32,
+    pub last_success_time: Option<NaiveDateTime>,
+    pub last_error_time: Option<NaiveDateTime>,
+    pub last_error: Option<String>,
+    /// Failures since the last success, source is backed off exponentially while it fails
+    pub consecutive_failures: i32,
+    /// Status of the last response, including `304 Not Modified` and error statuses;
+    /// kept if the last request got no response
+    pub last_http_status: Option<i32>,
+}
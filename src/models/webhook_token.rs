@@ -0,0 +1,26 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "pg-storage")]
+use {
+    crate::storage::schema::webhook_tokens,
+    diesel::{Insertable, Queryable},
+};
+
+/// Token the webhook source items are pushed with, only its hash is kept
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "pg-storage", derive(Queryable))]
+pub struct WebhookToken {
+    pub source_id: i32,
+    /// Hex-encoded SHA-256 of the token
+    pub token_hash: String,
+    pub created_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "pg-storage", derive(Insertable))]
+#[cfg_attr(feature = "pg-storage", table_name = "webhook_tokens")]
+pub struct NewWebhookToken {
+    pub source_id: i32,
+    pub token_hash: String,
+}
@@ -0,0 +1,43 @@
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "pg-storage")]
+use {
+    crate::storage::schema::websub_subscriptions,
+    diesel::{Insertable, Queryable},
+};
+
+/// Subscription is requested, hub hasn't verified it yet
+pub const WEBSUB_PENDING: &str = "PENDING";
+pub const WEBSUB_ACTIVE: &str = "ACTIVE";
+/// Hub denied or failed the subscription, source is polled
+pub const WEBSUB_FAILED: &str = "FAILED";
+/// Feed doesn't advertise a hub, source is polled
+pub const WEBSUB_UNSUPPORTED: &str = "UNSUPPORTED";
+/// Unsubscription is requested, hub hasn't verified it yet
+pub const WEBSUB_UNSUBSCRIBING: &str = "UNSUBSCRIBING";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "pg-storage", derive(Queryable))]
+pub struct WebSubSubscription {
+    pub source_id: i32,
+    pub hub: String,
+    pub topic: String,
+    /// Secret of the notifications signatures
+    pub secret: String,
+    pub state: String,
+    /// UTC time the hub drops the subscription at
+    pub lease_expires: Option<NaiveDateTime>,
+    pub updated_at: NaiveDateTime,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "pg-storage", derive(Insertable))]
+#[cfg_attr(feature = "pg-storage", table_name = "websub_subscriptions")]
+pub struct NewWebSubSubscription {
+    pub source_id: i32,
+    pub hub: String,
+    pub topic: String,
+    pub secret: String,
+    pub state: String,
+}
@@ -10,6 +10,12 @@ pub enum Error {
     SourceNotFound,
     SourceCreationError,
     IOError(std::io::Error),
+    InvalidContent(String),
+    FileStoreError(String),
+    WebSubError(String),
+    AuthStateConflict(String),
+    InvalidArgument(String),
+    SourceAccountConflict(String),
 }
 
 impl fmt::Display for Error {
@@ -0,0 +1,142 @@
+use crate::config::SanitizeConfig;
+use ammonia::{Builder, UrlRelative};
+use regex::Regex;
+use std::collections::HashSet;
+use url::Url;
+
+/// Tags which are never allowed, content of them is removed as well
+const FORBIDDEN_TAGS: &[&str] = &["script", "style", "iframe", "object", "embed", "form"];
+
+lazy_static! {
+    /// Images sized 1x1 or 0x0 are tracking pixels
+    static ref TRACKING_PIXEL: Regex = Regex::new(
+        r#"(?i)<img\s[^>]*?\b(?:width|height)\s*=\s*["']?[01](?:px)?["']?(?:[\s/][^>]*)?>"#
+    )
+    .unwrap();
+}
+
+/// Cleans html content of the records, so it's safe to render.
+///
+/// Tags and attributes out of the allow-list are removed, relative links are resolved
+/// against the record base url and links get `rel="noopener noreferrer"`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Sanitizer {
+    config: SanitizeConfig,
+}
+
+impl Sanitizer {
+    pub fn new(config: &SanitizeConfig) -> Self {
+        Self {
+            config: config.clone(),
+        }
+    }
+
+    pub fn clean(&self, html: &str, base_url: Option<&str>) -> String {
+        if !self.config.enabled {
+            return html.to_string();
+        }
+        let mut builder = Builder::default();
+        if let Some(tags) = &self.config.tags {
+            builder.tags(
+                tags.iter()
+                    .map(String::as_str)
+                    .filter(|t| !FORBIDDEN_TAGS.contains(t))
+                    .collect(),
+            );
+        }
+        // rel of the links is set by the sanitizer, ammonia panics if it's allowed as well
+        if let Some(attributes) = &self.config.generic_attributes {
+            builder.generic_attributes(
+                attributes
+                    .iter()
+                    .map(String::as_str)
+                    .filter(|a| *a != "rel")
+                    .collect(),
+            );
+        }
+        if let Some(tag_attributes) = &self.config.tag_attributes {
+            builder.tag_attributes(
+                tag_attributes
+                    .iter()
+                    .map(|(tag, attributes)| {
+                        let attributes = attributes
+                            .iter()
+                            .map(String::as_str)
+                            .filter(|a| *a != "rel")
+                            .collect::<HashSet<&str>>();
+                        (tag.as_str(), attributes)
+                    })
+                    .collect(),
+            );
+        }
+        builder
+            .clean_content_tags(FORBIDDEN_TAGS.iter().copied().collect())
+            .link_rel(Some("noopener noreferrer"));
+        match base_url.and_then(|u| Url::parse(u).ok()) {
+            Some(base) => builder.url_relative(UrlRelative::RewriteWithBase(base)),
+            None => builder.url_relative(UrlRelative::PassThrough),
+        };
+        builder
+            .clean(&TRACKING_PIXEL.replace_all(html, ""))
+            .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sanitizer;
+    use crate::config::SanitizeConfig;
+
+    #[test]
+    fn test_clean() {
+        let sanitizer = Sanitizer::default();
+        let html = r#"<p onclick="x()">Text <a href="/post">link</a></p>
+            <script>alert(1)</script><iframe src="https://example.com"></iframe>
+            <img src="/a.png" alt="a"><img src="https://t.example.com/p.gif" width="1" height="1">"#;
+        let cleaned = sanitizer.clean(html, Some("https://example.com/blog/feed.xml"));
+        assert!(cleaned.starts_with(
+            r#"<p>Text <a href="https://example.com/post" rel="noopener noreferrer">link</a></p>"#
+        ));
+        assert!(cleaned.ends_with(r#"<img src="https://example.com/a.png" alt="a">"#));
+        assert!(!cleaned.contains("alert"));
+        assert!(!cleaned.contains("iframe"));
+        assert!(!cleaned.contains("t.example.com"));
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let sanitizer = Sanitizer::new(&SanitizeConfig {
+            tags: Some(
+                vec!["b".to_string(), "script".to_string()]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..Default::default()
+        });
+        assert_eq!(
+            sanitizer.clean("<p><b>bold</b> <i>italic</i></p><script>x</script>", None),
+            "<b>bold</b> italic"
+        );
+    }
+
+    #[test]
+    fn test_allowed_rel() {
+        let set = |attributes: &[&str]| attributes.iter().map(|a| a.to_string()).collect();
+        let sanitizer = Sanitizer::new(&SanitizeConfig {
+            generic_attributes: Some(set(&["rel", "title"])),
+            tag_attributes: Some(
+                vec![("a".to_string(), set(&["href", "rel"]))]
+                    .into_iter()
+                    .collect(),
+            ),
+            ..Default::default()
+        });
+        assert_eq!(
+            sanitizer.clean(
+                r#"<a rel="nofollow" title="t" href="https://example.com">link</a>"#,
+                None
+            ),
+            r#"<a title="t" href="https://example.com" rel="noopener noreferrer">link</a>"#
+        );
+    }
+}
@@ -1,6 +1,7 @@
 use crate::models;
 use crate::result::Result;
 use async_trait::async_trait;
+use chrono::NaiveDateTime;
 
 #[cfg(feature = "pg-storage")]
 pub mod pg;
@@ -13,6 +14,34 @@ pub trait Storage {
     async fn save_file(&self, file: models::File) -> Result<()>;
     async fn get_file_by_remote_id(&self, remote_id: String) -> Result<Option<models::File>>;
     async fn save_files(&self, files: Vec<models::NewFile>) -> Result<()>;
+    async fn get_record_files(&self, record_id: i32) -> Result<Vec<models::File>>;
+    /// Claims file download: moves file to `DOWNLOADING` state and counts download attempt
+    /// if it's still in `state` after `attempts` attempts. Returns `false` if the file
+    /// was claimed by someone else
+    async fn set_file_downloading(
+        &self,
+        file_id: i32,
+        state: String,
+        attempts: i32,
+    ) -> Result<bool>;
+    async fn set_file_download_state(
+        &self,
+        file_id: i32,
+        state: String,
+        error: Option<String>,
+    ) -> Result<()>;
+    /// Resets download attempts of the file and queues it for download
+    async fn requeue_file(&self, file_id: i32) -> Result<()>;
+    /// Returns files in download state which were not attempted to download during `attempted_secs_ago`.
+    ///
+    /// If `account` is set, only files of the account sources are returned.
+    async fn get_files_by_download_state(
+        &self,
+        kind: String,
+        account: Option<String>,
+        state: String,
+        attempted_secs_ago: &i32,
+    ) -> Result<Vec<models::File>>;
 
     async fn set_record_external_link(
         &self,
@@ -20,15 +49,130 @@ pub trait Storage {
         source_id: i32,
         external_link: String,
     ) -> Result<usize>;
+    /// Inserts new records and updates existing ones which title, content or raw content
+    /// changed at the source; returns inserted and updated records
     async fn save_records(&self, records: Vec<models::NewRecord>) -> Result<Vec<models::Record>>;
+    async fn get_record(
+        &self,
+        source_id: i32,
+        source_record_id: String,
+    ) -> Result<Option<models::Record>>;
+    async fn set_record_content(&self, record_id: i32, content: String) -> Result<()>;
+    /// Replaces record content with the full article, content from the source is kept as summary
+    async fn set_record_full_content(&self, record_id: i32, content: String) -> Result<()>;
+    /// Replaces record content with the content linking cached images,
+    /// content before the first replacement is kept as original content
+    async fn set_record_cached_content(&self, record_id: i32, content: String) -> Result<()>;
+    async fn set_record_image(&self, record_id: i32, image: Option<String>) -> Result<()>;
+    async fn set_record_parent(&self, record_id: i32, parent_id: i32) -> Result<()>;
+    /// Returns the whole thread of the record: thread root first, then replies level by level
+    async fn get_record_thread(&self, record_id: i32) -> Result<Vec<models::Record>>;
 
     async fn set_source_scraped_now(&self, source: models::Source) -> Result<()>;
+    async fn set_source_name(&self, source_id: i32, name: String) -> Result<()>;
+    /// Sets source image stored from the file with `image_remote_id`
+    async fn set_source_image(
+        &self,
+        source_id: i32,
+        image: Option<String>,
+        image_remote_id: Option<String>,
+    ) -> Result<()>;
+    async fn set_source_state(&self, source_id: i32, state: String) -> Result<()>;
+    async fn set_source_account(&self, source_id: i32, account: Option<String>) -> Result<()>;
+    /// Sets account of the sources of the kind saved without account, returns their number
+    async fn set_unowned_sources_account(&self, kind: String, account: String) -> Result<usize>;
+    /// Sets manual scrape interval of the source, adaptive interval is used if not set
+    async fn set_source_scrape_interval(&self, source_id: i32, interval: Option<i32>)
+        -> Result<()>;
+    async fn set_source_adaptive_interval(
+        &self,
+        source_id: i32,
+        interval: Option<i32>,
+    ) -> Result<()>;
+    async fn set_source_fetch_full_content(&self, source_id: i32, enabled: bool) -> Result<()>;
+    async fn set_source_feed_kind(&self, source_id: i32, feed_kind: Option<String>) -> Result<()>;
+    async fn set_source_next_scrape_time(
+        &self,
+        source_id: i32,
+        next_scrape_time: NaiveDateTime,
+    ) -> Result<()>;
+    async fn get_source(&self, source_id: i32) -> Result<Option<models::Source>>;
+    /// Returns locations which are still referenced by files or source images
+    async fn get_referenced_locations(&self, locations: Vec<String>) -> Result<Vec<String>>;
+    /// Returns files of all source records
+    async fn get_source_files(&self, source_id: i32) -> Result<Vec<models::File>>;
+    /// Deletes source with its records and files; references from other sources records
+    /// (forwards, replies) are cleared
+    async fn delete_source(&self, source_id: i32) -> Result<()>;
+    async fn get_source_by_origin(
+        &self,
+        kind: String,
+        origin: String,
+    ) -> Result<Option<models::Source>>;
     async fn search_source(&self, query: &str) -> Result<Vec<models::Source>>;
     async fn get_sources_by_kind(&self, kind: String) -> Result<Vec<models::Source>>;
+    /// Returns active sources which should be scraped before `until` (UTC)
     async fn get_sources_by_kind_for_scrape(
         &self,
         kind: String,
-        check_secs_interval: &i32,
+        until: NaiveDateTime,
     ) -> Result<Vec<models::Source>>;
+    /// Saves sources; account of the existing source is kept, so the source stays
+    /// with the account which found it first. Feed kind is kept if the new one is unknown
     async fn save_sources(&self, sources: Vec<models::NewSource>) -> Result<Vec<models::Source>>;
+
+    async fn get_http_cache(&self, source_id: i32) -> Result<Option<models::HttpCache>>;
+    /// Replaces cached validators of the source
+    async fn save_http_cache(&self, cache: models::NewHttpCache) -> Result<()>;
+
+    async fn get_source_health(&self, source_id: i32) -> Result<Option<models::SourceHealth>>;
+    /// Returns health of the sources of the kind which failed at least `min_failures` times in a row
+    async fn get_failing_sources_health(
+        &self,
+        kind: String,
+        min_failures: i32,
+    ) -> Result<Vec<models::SourceHealth>>;
+    /// Records successful scrape and resets failures counter
+    async fn set_source_success(&self, source_id: i32) -> Result<()>;
+    /// Records failed scrape and returns updated health
+    async fn set_source_failure(
+        &self,
+        source_id: i32,
+        error: String,
+    ) -> Result<models::SourceHealth>;
+    /// Records status of the last response of the source
+    async fn set_source_http_status(&self, source_id: i32, http_status: i32) -> Result<()>;
+    async fn reset_source_failures(&self, source_id: i32) -> Result<()>;
+
+    /// Saves new subscription or replaces the existing one of the source
+    async fn save_websub_subscription(
+        &self,
+        subscription: models::NewWebSubSubscription,
+    ) -> Result<models::WebSubSubscription>;
+    async fn get_websub_subscription(
+        &self,
+        source_id: i32,
+    ) -> Result<Option<models::WebSubSubscription>>;
+    async fn set_websub_subscription_state(
+        &self,
+        source_id: i32,
+        state: String,
+        lease_expires: Option<NaiveDateTime>,
+    ) -> Result<()>;
+    async fn delete_websub_subscription(&self, source_id: i32) -> Result<()>;
+    /// Returns active subscriptions which lease expires before `until` (UTC), failed and
+    /// unsupported ones which weren't retried during `retry_secs_interval`, pending and
+    /// unsubscribing ones which weren't verified during `verify_timeout_secs`
+    async fn get_websub_subscriptions_for_renewal(
+        &self,
+        until: NaiveDateTime,
+        retry_secs_interval: &i32,
+        verify_timeout_secs: &i32,
+    ) -> Result<Vec<models::WebSubSubscription>>;
+    /// Returns active sources of the kind which have no WebSub subscription
+    async fn get_sources_without_websub(&self, kind: String) -> Result<Vec<models::Source>>;
+
+    async fn get_webhook_token(&self, source_id: i32) -> Result<Option<models::WebhookToken>>;
+    /// Saves token of the source, the previous one is replaced
+    async fn save_webhook_token(&self, token: models::NewWebhookToken) -> Result<()>;
 }
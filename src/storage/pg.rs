@@ -1,13 +1,17 @@
-use super::schema::{files, records, sources};
+use super::schema::{
+    files, http_cache, records, source_health, sources, webhook_tokens, websub_subscriptions,
+};
 use super::Storage;
 use crate::models;
 use crate::result::{Error, Result};
 use async_trait::async_trait;
+use chrono::NaiveDateTime;
 
-use diesel::dsl::IntervalDsl;
+use diesel::dsl::{sql, IntervalDsl};
 use diesel::expression::functions::date_and_time::now;
 
 use diesel::pg::upsert::excluded;
+use diesel::sql_types::{Nullable, Text};
 use diesel::{
     prelude::*,
     r2d2::{ConnectionManager, Pool as _Pool},
@@ -18,6 +22,8 @@ use tokio_diesel::*;
 
 pub type Pool = _Pool<ConnectionManager<PgConnection>>;
 
+sql_function!(fn coalesce(x: Nullable<Text>, y: Nullable<Text>) -> Nullable<Text>);
+
 embed_migrations!();
 
 #[derive(Clone)]
@@ -44,6 +50,8 @@ impl Storage for PgStorage {
             .set((
                 files::local_path.eq(file.local_path.clone()),
                 files::file_name.eq(file.file_name.clone()),
+                files::download_state.eq(file.download_state.clone()),
+                files::download_error.eq(file.download_error.clone()),
             ))
             .execute_async(&self.pool)
             .await?;
@@ -70,65 +78,132 @@ impl Storage for PgStorage {
     }
 
     async fn save_files(&self, files: Vec<models::NewFile>) -> Result<()> {
-        let mut key_to_files: HashMap<(String, i32), Vec<models::NewFile>> = HashMap::new();
+        // the same row can't be upserted twice by one statement, the last file is kept
+        let mut remote_ids = HashMap::new();
+        let mut to_insert: Vec<models::NewFile> = vec![];
         for file in files {
-            let key = (file.remote_id.clone().unwrap(), file.record_id);
-            let existed = key_to_files.get_mut(&key);
-            match existed {
-                Some(files) => {
-                    files.push(file);
-                }
+            match file.remote_id.as_ref().and_then(|id| remote_ids.get(id)) {
+                Some(index) => to_insert[*index] = file,
                 None => {
-                    key_to_files.insert(key, vec![file]);
+                    if let Some(remote_id) = &file.remote_id {
+                        remote_ids.insert(remote_id.clone(), to_insert.len());
+                    }
+                    to_insert.push(file);
                 }
             }
         }
-        for file in files::table
-            .filter(
-                files::remote_id.eq_any(
-                    key_to_files
-                        .keys()
-                        .into_iter()
-                        .map(|(ri, _)| ri.clone())
-                        .collect::<Vec<String>>(),
-                ),
-            )
-            .load_async::<models::File>(&self.pool)
-            .await?
-        {
-            let file_id = file.id;
-            key_to_files
-                .remove(&(file.remote_id.unwrap(), file.record_id))
-                .map(|files| async move {
-                    for file in files {
-                        let update_result =
-                            diesel::update(files::table.filter(files::id.eq(file_id)))
-                                .set((
-                                    files::local_path.eq(file.local_path.clone()),
-                                    files::file_name.eq(file.file_name.clone()),
-                                ))
-                                .execute_async(&self.pool)
-                                .await;
-                        match update_result {
-                            Ok(_) => {}
-                            Err(e) => error!("{}", e),
-                        }
-                    }
-                });
-        }
-        let mut to_insert = vec![];
-        for files in key_to_files.values() {
-            to_insert.extend(files.clone())
-        }
         diesel::insert_into(files::table)
             .values(to_insert)
             .on_conflict(files::remote_id)
-            .do_nothing()
+            .do_update()
+            .set((
+                files::local_path.eq(coalesce(excluded(files::local_path), files::local_path)),
+                files::file_name.eq(coalesce(excluded(files::file_name), files::file_name)),
+                files::skipped.eq(excluded(files::skipped)),
+                // file which stopped being skipped is queued for download and vice versa
+                files::download_state.eq(sql::<Text>(
+                    "CASE WHEN files.skipped IS DISTINCT FROM excluded.skipped \
+                     THEN excluded.download_state ELSE files.download_state END",
+                )),
+            ))
             .execute_async(&self.pool)
             .await?;
         Ok(())
     }
 
+    async fn get_record_files(&self, record_id: i32) -> Result<Vec<models::File>> {
+        Ok(files::table
+            .filter(files::record_id.eq(record_id))
+            .order(files::id)
+            .load_async::<models::File>(&self.pool)
+            .await?)
+    }
+
+    async fn set_file_downloading(
+        &self,
+        file_id: i32,
+        state: String,
+        attempts: i32,
+    ) -> Result<bool> {
+        let claimed = update(
+            files::table.filter(
+                files::id
+                    .eq(file_id)
+                    .and(files::download_state.eq(state))
+                    .and(files::download_attempts.eq(attempts)),
+            ),
+        )
+        .set((
+            files::download_state.eq(models::DOWNLOAD_DOWNLOADING),
+            files::download_attempts.eq(files::download_attempts + 1),
+            files::download_attempted_at.eq(now.nullable()),
+        ))
+        .execute_async(&self.pool)
+        .await?;
+        Ok(claimed > 0)
+    }
+
+    async fn set_file_download_state(
+        &self,
+        file_id: i32,
+        state: String,
+        error: Option<String>,
+    ) -> Result<()> {
+        update(files::table.filter(files::id.eq(file_id)))
+            .set((
+                files::download_state.eq(state),
+                files::download_error.eq(error),
+            ))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn requeue_file(&self, file_id: i32) -> Result<()> {
+        update(files::table.filter(files::id.eq(file_id)))
+            .set((
+                files::download_state.eq(models::DOWNLOAD_QUEUED),
+                files::download_attempts.eq(0),
+                files::download_error.eq(None::<String>),
+            ))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_files_by_download_state(
+        &self,
+        kind: String,
+        account: Option<String>,
+        state: String,
+        attempted_secs_ago: &i32,
+    ) -> Result<Vec<models::File>> {
+        let mut query = files::table
+            .filter(
+                files::kind
+                    .eq(kind)
+                    .and(files::download_state.eq(state))
+                    .and(
+                        files::download_attempted_at
+                            .is_null()
+                            .or(files::download_attempted_at
+                                .le((now - attempted_secs_ago.second()).nullable())),
+                    ),
+            )
+            .into_boxed();
+        if let Some(account) = account {
+            query = query.filter(
+                files::record_id.eq_any(
+                    records::table
+                        .inner_join(sources::table)
+                        .filter(sources::account.eq(account))
+                        .select(records::id),
+                ),
+            );
+        }
+        Ok(query.load_async::<models::File>(&self.pool).await?)
+    }
+
     async fn set_record_external_link(
         &self,
         source_record_id: String,
@@ -148,11 +223,11 @@ impl Storage for PgStorage {
     }
 
     async fn save_records(&self, records: Vec<models::NewRecord>) -> Result<Vec<models::Record>> {
-        // TODO: do we need to return updated rows?
         let mut key_to_rec = records
             .into_iter()
             .map(|f| ((f.source_record_id.clone(), f.source_id), f))
             .collect::<HashMap<(String, i32), models::NewRecord>>();
+        let mut updated = vec![];
         for record in records::table
             .filter(
                 records::source_record_id.eq_any(
@@ -166,27 +241,40 @@ impl Storage for PgStorage {
             .load_async::<models::Record>(&self.pool)
             .await?
         {
-            let record_id = record.id;
-            key_to_rec
-                .remove(&(record.source_record_id, record.source_id))
-                .map(|r| async move {
-                    let update_result =
-                        diesel::update(records::table.filter(records::id.eq(record_id)))
-                            .set((
-                                records::title.eq(r.title.clone()),
-                                records::content.eq(r.content.clone()),
-                                records::image.eq(r.image.clone()),
-                            ))
-                            .execute_async(&self.pool)
-                            .await;
-                    match update_result {
-                        Ok(_) => {}
-                        Err(e) => error!("{}", e),
-                    }
-                });
+            let new = match key_to_rec.remove(&(record.source_record_id.clone(), record.source_id))
+            {
+                Some(new) => new,
+                None => continue,
+            };
+            // content replaced by the aggregator is compared by the source content in summary
+            // or by the content before the images are cached
+            let source_content = record
+                .summary
+                .as_ref()
+                .or_else(|| record.original_content.as_ref())
+                .unwrap_or(&record.content);
+            if record.title == new.title
+                && *source_content == new.content
+                && record.raw_content == new.raw_content
+            {
+                continue;
+            }
+            updated.push(
+                update(records::table.find(record.id))
+                    .set((
+                        records::title.eq(new.title),
+                        records::content.eq(new.content),
+                        records::summary.eq(None::<String>),
+                        records::original_content.eq(None::<String>),
+                        records::image.eq(new.image.or(record.image)),
+                        records::raw_content.eq(new.raw_content),
+                    ))
+                    .get_result_async::<models::Record>(&self.pool)
+                    .await?,
+            );
         }
 
-        Ok(diesel::insert_into(records::table)
+        let mut saved = diesel::insert_into(records::table)
             .values(
                 key_to_rec
                     .values()
@@ -195,8 +283,104 @@ impl Storage for PgStorage {
             )
             .on_conflict((records::source_record_id, records::source_id))
             .do_nothing()
-            .get_results_async(&self.pool)
-            .await?)
+            .get_results_async::<models::Record>(&self.pool)
+            .await?;
+        saved.extend(updated);
+        Ok(saved)
+    }
+
+    async fn get_record(
+        &self,
+        source_id: i32,
+        source_record_id: String,
+    ) -> Result<Option<models::Record>> {
+        match records::table
+            .filter(
+                records::source_id
+                    .eq(source_id)
+                    .and(records::source_record_id.eq(source_record_id)),
+            )
+            .first_async::<models::Record>(&self.pool)
+            .await
+        {
+            Ok(record) => Ok(Some(record)),
+            Err(tokio_diesel::AsyncError::Error(diesel::NotFound)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn set_record_content(&self, record_id: i32, content: String) -> Result<()> {
+        update(records::table.filter(records::id.eq(record_id)))
+            .set(records::content.eq(content))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_record_full_content(&self, record_id: i32, content: String) -> Result<()> {
+        update(records::table.filter(records::id.eq(record_id)))
+            .set((
+                records::summary.eq(coalesce(records::summary, records::content.nullable())),
+                records::content.eq(content),
+            ))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_record_cached_content(&self, record_id: i32, content: String) -> Result<()> {
+        update(records::table.filter(records::id.eq(record_id)))
+            .set((
+                records::original_content.eq(coalesce(
+                    records::original_content,
+                    records::content.nullable(),
+                )),
+                records::content.eq(content),
+            ))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_record_image(&self, record_id: i32, image: Option<String>) -> Result<()> {
+        update(records::table.filter(records::id.eq(record_id)))
+            .set(records::image.eq(image))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_record_parent(&self, record_id: i32, parent_id: i32) -> Result<()> {
+        update(records::table.filter(records::id.eq(record_id)))
+            .set(records::parent_id.eq(parent_id))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_record_thread(&self, record_id: i32) -> Result<Vec<models::Record>> {
+        let mut root = records::table
+            .find(record_id)
+            .first_async::<models::Record>(&self.pool)
+            .await?;
+        while let Some(parent_id) = root.parent_id {
+            root = records::table
+                .find(parent_id)
+                .first_async::<models::Record>(&self.pool)
+                .await?;
+        }
+        let mut thread = vec![];
+        let mut level = vec![root];
+        while !level.is_empty() {
+            let ids = level.iter().map(|r| r.id).collect::<Vec<i32>>();
+            thread.extend(level);
+            level = records::table
+                .filter(records::parent_id.eq_any(ids))
+                .order(records::date.asc())
+                .load_async::<models::Record>(&self.pool)
+                .await?;
+        }
+        Ok(thread)
     }
 
     async fn set_source_scraped_now(&self, source: models::Source) -> Result<()> {
@@ -207,6 +391,193 @@ impl Storage for PgStorage {
         Ok(())
     }
 
+    async fn set_source_name(&self, source_id: i32, name: String) -> Result<()> {
+        update(sources::table.filter(sources::id.eq(source_id)))
+            .set(sources::name.eq(name))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_source_image(
+        &self,
+        source_id: i32,
+        image: Option<String>,
+        image_remote_id: Option<String>,
+    ) -> Result<()> {
+        update(sources::table.filter(sources::id.eq(source_id)))
+            .set((
+                sources::image.eq(image),
+                sources::image_remote_id.eq(image_remote_id),
+            ))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_source_state(&self, source_id: i32, state: String) -> Result<()> {
+        update(sources::table.filter(sources::id.eq(source_id)))
+            .set(sources::state.eq(state))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_source(&self, source_id: i32) -> Result<Option<models::Source>> {
+        match sources::table
+            .find(source_id)
+            .first_async::<models::Source>(&self.pool)
+            .await
+        {
+            Ok(source) => Ok(Some(source)),
+            Err(tokio_diesel::AsyncError::Error(diesel::NotFound)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get_referenced_locations(&self, locations: Vec<String>) -> Result<Vec<String>> {
+        let mut referenced = files::table
+            .filter(files::local_path.eq_any(locations.clone()))
+            .select(files::local_path)
+            .load_async::<Option<String>>(&self.pool)
+            .await?;
+        referenced.extend(
+            sources::table
+                .filter(sources::image.eq_any(locations))
+                .select(sources::image)
+                .load_async::<Option<String>>(&self.pool)
+                .await?,
+        );
+        let mut referenced = referenced.into_iter().flatten().collect::<Vec<String>>();
+        referenced.sort_unstable();
+        referenced.dedup();
+        Ok(referenced)
+    }
+
+    async fn get_source_files(&self, source_id: i32) -> Result<Vec<models::File>> {
+        Ok(files::table
+            .filter(
+                files::record_id.eq_any(
+                    records::table
+                        .filter(records::source_id.eq(source_id))
+                        .select(records::id),
+                ),
+            )
+            .load_async::<models::File>(&self.pool)
+            .await?)
+    }
+
+    async fn delete_source(&self, source_id: i32) -> Result<()> {
+        self.pool
+            .transaction(move |conn| {
+                let source_records = records::table
+                    .filter(records::source_id.eq(source_id))
+                    .select(records::id);
+                update(records::table.filter(records::forward_source_id.eq(source_id)))
+                    .set(records::forward_source_id.eq(None::<i32>))
+                    .execute(conn)?;
+                update(records::table.filter(records::parent_id.eq_any(source_records)))
+                    .set(records::parent_id.eq(None::<i32>))
+                    .execute(conn)?;
+                diesel::delete(files::table.filter(files::record_id.eq_any(source_records)))
+                    .execute(conn)?;
+                diesel::delete(records::table.filter(records::source_id.eq(source_id)))
+                    .execute(conn)?;
+                diesel::delete(http_cache::table.find(source_id)).execute(conn)?;
+                diesel::delete(source_health::table.find(source_id)).execute(conn)?;
+                diesel::delete(websub_subscriptions::table.find(source_id)).execute(conn)?;
+                diesel::delete(webhook_tokens::table.find(source_id)).execute(conn)?;
+                diesel::delete(sources::table.find(source_id)).execute(conn)
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn set_source_account(&self, source_id: i32, account: Option<String>) -> Result<()> {
+        update(sources::table.filter(sources::id.eq(source_id)))
+            .set(sources::account.eq(account))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_unowned_sources_account(&self, kind: String, account: String) -> Result<usize> {
+        Ok(
+            update(sources::table.filter(sources::kind.eq(kind).and(sources::account.is_null())))
+                .set(sources::account.eq(account))
+                .execute_async(&self.pool)
+                .await?,
+        )
+    }
+
+    async fn set_source_scrape_interval(
+        &self,
+        source_id: i32,
+        interval: Option<i32>,
+    ) -> Result<()> {
+        update(sources::table.filter(sources::id.eq(source_id)))
+            .set(sources::scrape_interval.eq(interval))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_source_adaptive_interval(
+        &self,
+        source_id: i32,
+        interval: Option<i32>,
+    ) -> Result<()> {
+        update(sources::table.filter(sources::id.eq(source_id)))
+            .set(sources::adaptive_interval.eq(interval))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_source_fetch_full_content(&self, source_id: i32, enabled: bool) -> Result<()> {
+        update(sources::table.filter(sources::id.eq(source_id)))
+            .set(sources::fetch_full_content.eq(enabled))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_source_feed_kind(&self, source_id: i32, feed_kind: Option<String>) -> Result<()> {
+        update(sources::table.filter(sources::id.eq(source_id)))
+            .set(sources::feed_kind.eq(feed_kind))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_source_next_scrape_time(
+        &self,
+        source_id: i32,
+        next_scrape_time: NaiveDateTime,
+    ) -> Result<()> {
+        update(sources::table.filter(sources::id.eq(source_id)))
+            .set(sources::next_scrape_time.eq(next_scrape_time))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_source_by_origin(
+        &self,
+        kind: String,
+        origin: String,
+    ) -> Result<Option<models::Source>> {
+        match sources::table
+            .filter(sources::kind.eq(kind).and(sources::origin.eq(origin)))
+            .first_async::<models::Source>(&self.pool)
+            .await
+        {
+            Ok(source) => Ok(Some(source)),
+            Err(tokio_diesel::AsyncError::Error(diesel::NotFound)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
     async fn search_source(&self, query: &str) -> Result<Vec<models::Source>> {
         let like = format!("%{}%", query);
         let source = sources::table
@@ -239,13 +610,14 @@ impl Storage for PgStorage {
     async fn get_sources_by_kind_for_scrape(
         &self,
         kind: String,
-        check_secs_interval: &i32,
+        until: NaiveDateTime,
     ) -> Result<Vec<models::Source>> {
         Ok(sources::table
             .filter(
                 sources::kind
                     .eq(kind)
-                    .and(sources::last_scrape_time.le(now - check_secs_interval.second())),
+                    .and(sources::state.eq(models::SOURCE_ACTIVE))
+                    .and(sources::next_scrape_time.le(until)),
             )
             .load_async::<models::Source>(&self.pool)
             .await?)
@@ -256,10 +628,260 @@ impl Storage for PgStorage {
             .values(sources)
             .on_conflict((sources::origin, sources::kind))
             .do_update()
-            .set(sources::name.eq(excluded(sources::name)))
+            .set((
+                sources::name.eq(excluded(sources::name)),
+                sources::account.eq(coalesce(sources::account, excluded(sources::account))),
+                sources::feed_kind.eq(coalesce(excluded(sources::feed_kind), sources::feed_kind)),
+            ))
             .get_results_async::<models::Source>(&self.pool)
             .await?)
     }
+
+    async fn get_http_cache(&self, source_id: i32) -> Result<Option<models::HttpCache>> {
+        match http_cache::table
+            .find(source_id)
+            .first_async::<models::HttpCache>(&self.pool)
+            .await
+        {
+            Ok(cache) => Ok(Some(cache)),
+            Err(tokio_diesel::AsyncError::Error(diesel::NotFound)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn save_http_cache(&self, cache: models::NewHttpCache) -> Result<()> {
+        diesel::insert_into(http_cache::table)
+            .values(cache)
+            .on_conflict(http_cache::source_id)
+            .do_update()
+            .set((
+                http_cache::etag.eq(excluded(http_cache::etag)),
+                http_cache::last_modified.eq(excluded(http_cache::last_modified)),
+                http_cache::content_hash.eq(excluded(http_cache::content_hash)),
+                http_cache::updated_at.eq(now),
+            ))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_source_health(&self, source_id: i32) -> Result<Option<models::SourceHealth>> {
+        match source_health::table
+            .find(source_id)
+            .first_async::<models::SourceHealth>(&self.pool)
+            .await
+        {
+            Ok(health) => Ok(Some(health)),
+            Err(tokio_diesel::AsyncError::Error(diesel::NotFound)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn get_failing_sources_health(
+        &self,
+        kind: String,
+        min_failures: i32,
+    ) -> Result<Vec<models::SourceHealth>> {
+        Ok(source_health::table
+            .filter(
+                source_health::consecutive_failures.ge(min_failures).and(
+                    source_health::source_id.eq_any(
+                        sources::table
+                            .filter(sources::kind.eq(kind))
+                            .select(sources::id),
+                    ),
+                ),
+            )
+            .order(source_health::consecutive_failures.desc())
+            .load_async::<models::SourceHealth>(&self.pool)
+            .await?)
+    }
+
+    async fn set_source_success(&self, source_id: i32) -> Result<()> {
+        diesel::insert_into(source_health::table)
+            .values((
+                source_health::source_id.eq(source_id),
+                source_health::last_success_time.eq(now.nullable()),
+            ))
+            .on_conflict(source_health::source_id)
+            .do_update()
+            .set((
+                source_health::last_success_time.eq(now.nullable()),
+                source_health::consecutive_failures.eq(0),
+            ))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn set_source_failure(
+        &self,
+        source_id: i32,
+        error: String,
+    ) -> Result<models::SourceHealth> {
+        Ok(diesel::insert_into(source_health::table)
+            .values((
+                source_health::source_id.eq(source_id),
+                source_health::last_error_time.eq(now.nullable()),
+                source_health::last_error.eq(error.clone()),
+                source_health::consecutive_failures.eq(1),
+            ))
+            .on_conflict(source_health::source_id)
+            .do_update()
+            .set((
+                source_health::last_error_time.eq(now.nullable()),
+                source_health::last_error.eq(error),
+                source_health::consecutive_failures.eq(source_health::consecutive_failures + 1),
+            ))
+            .get_result_async::<models::SourceHealth>(&self.pool)
+            .await?)
+    }
+
+    async fn set_source_http_status(&self, source_id: i32, http_status: i32) -> Result<()> {
+        diesel::insert_into(source_health::table)
+            .values((
+                source_health::source_id.eq(source_id),
+                source_health::last_http_status.eq(http_status),
+            ))
+            .on_conflict(source_health::source_id)
+            .do_update()
+            .set(source_health::last_http_status.eq(http_status))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn reset_source_failures(&self, source_id: i32) -> Result<()> {
+        update(source_health::table.find(source_id))
+            .set(source_health::consecutive_failures.eq(0))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn save_websub_subscription(
+        &self,
+        subscription: models::NewWebSubSubscription,
+    ) -> Result<models::WebSubSubscription> {
+        Ok(diesel::insert_into(websub_subscriptions::table)
+            .values(subscription)
+            .on_conflict(websub_subscriptions::source_id)
+            .do_update()
+            .set((
+                websub_subscriptions::hub.eq(excluded(websub_subscriptions::hub)),
+                websub_subscriptions::topic.eq(excluded(websub_subscriptions::topic)),
+                websub_subscriptions::secret.eq(excluded(websub_subscriptions::secret)),
+                websub_subscriptions::state.eq(excluded(websub_subscriptions::state)),
+                websub_subscriptions::updated_at.eq(now),
+            ))
+            .get_result_async::<models::WebSubSubscription>(&self.pool)
+            .await?)
+    }
+
+    async fn get_websub_subscription(
+        &self,
+        source_id: i32,
+    ) -> Result<Option<models::WebSubSubscription>> {
+        match websub_subscriptions::table
+            .find(source_id)
+            .first_async::<models::WebSubSubscription>(&self.pool)
+            .await
+        {
+            Ok(subscription) => Ok(Some(subscription)),
+            Err(tokio_diesel::AsyncError::Error(diesel::NotFound)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn set_websub_subscription_state(
+        &self,
+        source_id: i32,
+        state: String,
+        lease_expires: Option<NaiveDateTime>,
+    ) -> Result<()> {
+        update(websub_subscriptions::table.find(source_id))
+            .set((
+                websub_subscriptions::state.eq(state),
+                websub_subscriptions::lease_expires.eq(lease_expires),
+                websub_subscriptions::updated_at.eq(now),
+            ))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn delete_websub_subscription(&self, source_id: i32) -> Result<()> {
+        diesel::delete(websub_subscriptions::table.find(source_id))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn get_websub_subscriptions_for_renewal(
+        &self,
+        until: NaiveDateTime,
+        retry_secs_interval: &i32,
+        verify_timeout_secs: &i32,
+    ) -> Result<Vec<models::WebSubSubscription>> {
+        Ok(websub_subscriptions::table
+            .filter(
+                websub_subscriptions::state
+                    .eq(models::WEBSUB_ACTIVE)
+                    .and(websub_subscriptions::lease_expires.le(until))
+                    .or(websub_subscriptions::state
+                        .eq_any(vec![models::WEBSUB_FAILED, models::WEBSUB_UNSUPPORTED])
+                        .and(
+                            websub_subscriptions::updated_at.le(now - retry_secs_interval.second()),
+                        ))
+                    .or(websub_subscriptions::state
+                        .eq_any(vec![models::WEBSUB_PENDING, models::WEBSUB_UNSUBSCRIBING])
+                        .and(
+                            websub_subscriptions::updated_at.le(now - verify_timeout_secs.second()),
+                        )),
+            )
+            .load_async::<models::WebSubSubscription>(&self.pool)
+            .await?)
+    }
+
+    async fn get_sources_without_websub(&self, kind: String) -> Result<Vec<models::Source>> {
+        Ok(sources::table
+            .filter(
+                sources::kind
+                    .eq(kind)
+                    .and(sources::state.eq(models::SOURCE_ACTIVE))
+                    .and(diesel::dsl::not(sources::id.eq_any(
+                        websub_subscriptions::table.select(websub_subscriptions::source_id),
+                    ))),
+            )
+            .load_async::<models::Source>(&self.pool)
+            .await?)
+    }
+
+    async fn get_webhook_token(&self, source_id: i32) -> Result<Option<models::WebhookToken>> {
+        match webhook_tokens::table
+            .find(source_id)
+            .first_async::<models::WebhookToken>(&self.pool)
+            .await
+        {
+            Ok(token) => Ok(Some(token)),
+            Err(tokio_diesel::AsyncError::Error(diesel::NotFound)) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn save_webhook_token(&self, token: models::NewWebhookToken) -> Result<()> {
+        diesel::insert_into(webhook_tokens::table)
+            .values(token)
+            .on_conflict(webhook_tokens::source_id)
+            .do_update()
+            .set((
+                webhook_tokens::token_hash.eq(excluded(webhook_tokens::token_hash)),
+                webhook_tokens::created_at.eq(now),
+            ))
+            .execute_async(&self.pool)
+            .await?;
+        Ok(())
+    }
 }
 
 impl From<tokio_diesel::AsyncError> for Error {
@@ -273,3 +895,431 @@ impl From<&tokio_diesel::AsyncError> for Error {
         Self::DbError(err.to_string())
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::{PgStorage, Pool};
+    use crate::models;
+    use crate::storage::Storage;
+    use diesel::r2d2::ConnectionManager;
+
+    /// Storage on the database from `TEST_DATABASE_URL`, tests using it are ignored,
+    /// e.g. `TEST_DATABASE_URL=postgres://... cargo test --features pg-storage -- --ignored`
+    pub(crate) fn test_storage() -> PgStorage {
+        let url = std::env::var("TEST_DATABASE_URL").expect("TEST_DATABASE_URL isn't set");
+        let pool = Pool::builder()
+            .max_size(2)
+            .build(ConnectionManager::new(url))
+            .expect("can't connect to test database");
+        let storage = PgStorage::new(pool);
+        storage.migrate().expect("can't migrate test database");
+        storage
+    }
+
+    /// Saves active source of the kind with a unique origin
+    pub(crate) async fn test_source(storage: &PgStorage, kind: &str) -> models::Source {
+        let origin = format!("test-{}", rand::random::<u64>());
+        storage
+            .save_sources(vec![models::NewSource {
+                name: origin.clone(),
+                origin: origin.clone(),
+                kind: kind.to_string(),
+                image: None,
+                external_link: origin,
+                account: None,
+                feed_kind: None,
+            }])
+            .await
+            .unwrap()
+            .pop()
+            .unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_save_records_updates_changed() {
+        let storage = test_storage();
+        let source = test_source(&storage, "TEST").await;
+        let record = models::NewRecord {
+            source_record_id: "1".to_string(),
+            source_id: source.id,
+            content: "first".to_string(),
+            raw_content: Some("first raw".to_string()),
+            ..Default::default()
+        };
+        let saved = storage.save_records(vec![record.clone()]).await.unwrap();
+        assert_eq!(saved.len(), 1);
+        // unchanged record isn't updated
+        assert!(storage
+            .save_records(vec![record.clone()])
+            .await
+            .unwrap()
+            .is_empty());
+        let edited = models::NewRecord {
+            content: "second".to_string(),
+            raw_content: Some("second raw".to_string()),
+            ..record
+        };
+        let updated = storage.save_records(vec![edited]).await.unwrap();
+        assert_eq!(updated.len(), 1);
+        assert_eq!(updated[0].id, saved[0].id);
+        let stored = storage
+            .get_record(source.id, "1".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.content, "second");
+        assert_eq!(stored.raw_content.as_deref(), Some("second raw"));
+        storage.delete_source(source.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_save_records_keeps_replaced_content() {
+        let storage = test_storage();
+        let source = test_source(&storage, "TEST").await;
+        let record = models::NewRecord {
+            source_record_id: "1".to_string(),
+            source_id: source.id,
+            content: "feed".to_string(),
+            ..Default::default()
+        };
+        let saved = storage.save_records(vec![record.clone()]).await.unwrap();
+        storage
+            .set_record_full_content(saved[0].id, "article".to_string())
+            .await
+            .unwrap();
+        // same feed content doesn't replace the article
+        assert!(storage.save_records(vec![record]).await.unwrap().is_empty());
+        let stored = storage
+            .get_record(source.id, "1".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.content, "article");
+        assert_eq!(stored.summary.as_deref(), Some("feed"));
+        storage.delete_source(source.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_save_records_keeps_cached_content() {
+        let storage = test_storage();
+        let source = test_source(&storage, "TEST").await;
+        let record = models::NewRecord {
+            source_record_id: "1".to_string(),
+            source_id: source.id,
+            content: r#"<img src="a.png">"#.to_string(),
+            ..Default::default()
+        };
+        let saved = storage.save_records(vec![record.clone()]).await.unwrap();
+        for content in &[r#"<img src="/a_1.png">"#, r#"<img src="/a_2.png">"#] {
+            storage
+                .set_record_cached_content(saved[0].id, content.to_string())
+                .await
+                .unwrap();
+        }
+        // same feed content doesn't revert cached images
+        assert!(storage
+            .save_records(vec![record.clone()])
+            .await
+            .unwrap()
+            .is_empty());
+        let stored = storage
+            .get_record(source.id, "1".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.content, r#"<img src="/a_2.png">"#);
+        assert_eq!(stored.original_content, Some(record.content));
+        // it isn't a fetched article
+        assert_eq!(stored.summary, None);
+        storage.delete_source(source.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_save_files_flips_skipped() {
+        let storage = test_storage();
+        let source = test_source(&storage, "TEST").await;
+        let record = storage
+            .save_records(vec![models::NewRecord {
+                source_record_id: "1".to_string(),
+                source_id: source.id,
+                content: "content".to_string(),
+                ..Default::default()
+            }])
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        let file = models::NewFile {
+            record_id: record.id,
+            kind: "TEST".to_string(),
+            local_path: None,
+            remote_path: "remote".to_string(),
+            remote_id: Some(format!("{}-file", source.origin)),
+            file_name: Some("file.jpg".to_string()),
+            type_: "IMAGE".to_string(),
+            meta: None,
+            skipped: None,
+            download_state: models::DOWNLOAD_QUEUED.to_string(),
+            original_id: None,
+        };
+        storage.save_files(vec![file.clone()]).await.unwrap();
+        let skipped = models::NewFile {
+            skipped: Some("too large".to_string()),
+            download_state: models::DOWNLOAD_SKIPPED.to_string(),
+            ..file.clone()
+        };
+        storage.save_files(vec![skipped]).await.unwrap();
+        let stored = storage.get_record_files(record.id).await.unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].skipped.as_deref(), Some("too large"));
+        assert_eq!(stored[0].download_state, models::DOWNLOAD_SKIPPED);
+        storage.save_files(vec![file]).await.unwrap();
+        let stored = storage.get_record_files(record.id).await.unwrap();
+        assert_eq!(stored[0].skipped, None);
+        assert_eq!(stored[0].download_state, models::DOWNLOAD_QUEUED);
+        storage.delete_source(source.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_claim_file_download() {
+        let storage = test_storage();
+        let source = test_source(&storage, "TEST").await;
+        let record = storage
+            .save_records(vec![models::NewRecord {
+                source_record_id: "1".to_string(),
+                source_id: source.id,
+                content: "content".to_string(),
+                ..Default::default()
+            }])
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        storage
+            .save_files(vec![models::NewFile {
+                record_id: record.id,
+                kind: "TEST".to_string(),
+                local_path: None,
+                remote_path: "1".to_string(),
+                remote_id: Some(format!("{}-file", source.origin)),
+                file_name: None,
+                type_: "IMAGE".to_string(),
+                meta: None,
+                skipped: None,
+                download_state: models::DOWNLOAD_QUEUED.to_string(),
+                original_id: None,
+            }])
+            .await
+            .unwrap();
+        let file = storage.get_record_files(record.id).await.unwrap().remove(0);
+        let claim = || {
+            storage.set_file_downloading(
+                file.id,
+                file.download_state.clone(),
+                file.download_attempts,
+            )
+        };
+        // file is downloaded once if both the worker and the updates handler got it
+        assert!(claim().await.unwrap());
+        assert!(!claim().await.unwrap());
+        let stored = storage.get_record_files(record.id).await.unwrap();
+        assert_eq!(stored[0].download_state, models::DOWNLOAD_DOWNLOADING);
+        assert_eq!(stored[0].download_attempts, file.download_attempts + 1);
+        storage.delete_source(source.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_set_source_image() {
+        let storage = test_storage();
+        let source = test_source(&storage, "TEST").await;
+        storage
+            .set_source_image(
+                source.id,
+                Some("image.jpg".to_string()),
+                Some("photo".to_string()),
+            )
+            .await
+            .unwrap();
+        // synchronized source keeps the photo it's stored from
+        let saved = storage
+            .save_sources(vec![models::NewSource {
+                name: source.name.clone(),
+                origin: source.origin.clone(),
+                kind: source.kind.clone(),
+                image: None,
+                external_link: source.external_link.clone(),
+                account: None,
+                feed_kind: None,
+            }])
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        assert_eq!(saved.image.as_deref(), Some("image.jpg"));
+        assert_eq!(saved.image_remote_id.as_deref(), Some("photo"));
+        storage.delete_source(source.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_get_referenced_locations() {
+        let storage = test_storage();
+        let source = test_source(&storage, "TEST").await;
+        let record = storage
+            .save_records(vec![models::NewRecord {
+                source_record_id: "1".to_string(),
+                source_id: source.id,
+                content: "content".to_string(),
+                ..Default::default()
+            }])
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        let shared = format!("{}/shared.jpg", source.origin);
+        storage
+            .save_files(vec![models::NewFile {
+                record_id: record.id,
+                kind: "TEST".to_string(),
+                local_path: Some(shared.clone()),
+                remote_path: "remote".to_string(),
+                remote_id: Some(format!("{}-file", source.origin)),
+                file_name: None,
+                type_: "IMAGE".to_string(),
+                meta: None,
+                skipped: None,
+                download_state: models::DOWNLOAD_DONE.to_string(),
+                original_id: None,
+            }])
+            .await
+            .unwrap();
+        let image = format!("{}/image.jpg", source.origin);
+        storage
+            .set_source_image(source.id, Some(image.clone()), None)
+            .await
+            .unwrap();
+        let unreferenced = format!("{}/removed.jpg", source.origin);
+        let referenced = storage
+            .get_referenced_locations(vec![shared.clone(), image.clone(), unreferenced])
+            .await
+            .unwrap();
+        let mut expected = vec![shared.clone(), image];
+        expected.sort_unstable();
+        assert_eq!(referenced, expected);
+        storage.delete_source(source.id).await.unwrap();
+        assert!(storage
+            .get_referenced_locations(vec![shared])
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_set_unowned_sources_account() {
+        let storage = test_storage();
+        let unowned = test_source(&storage, "TEST").await;
+        let owned = test_source(&storage, "TEST").await;
+        storage
+            .set_source_account(owned.id, Some("second".to_string()))
+            .await
+            .unwrap();
+        assert!(
+            storage
+                .set_unowned_sources_account("TEST".to_string(), "main".to_string())
+                .await
+                .unwrap()
+                >= 1
+        );
+        for (source, account) in &[(&unowned, "main"), (&owned, "second")] {
+            let source = storage.get_source(source.id).await.unwrap().unwrap();
+            assert_eq!(source.account.as_deref(), Some(*account));
+            storage.delete_source(source.id).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_websub_renewal_includes_unverified() {
+        let storage = test_storage();
+        let source = test_source(&storage, "TEST").await;
+        storage
+            .save_websub_subscription(models::NewWebSubSubscription {
+                source_id: source.id,
+                hub: "https://hub.example.com/".to_string(),
+                topic: source.origin.clone(),
+                secret: "secret".to_string(),
+                state: models::WEBSUB_PENDING.to_string(),
+            })
+            .await
+            .unwrap();
+        let renewed = |verify_timeout_secs: i32| {
+            let storage = storage.clone();
+            async move {
+                storage
+                    .get_websub_subscriptions_for_renewal(
+                        chrono::NaiveDate::from_ymd(2000, 1, 1).and_hms(0, 0, 0),
+                        &3600,
+                        &verify_timeout_secs,
+                    )
+                    .await
+                    .unwrap()
+                    .iter()
+                    .any(|s| s.source_id == source.id)
+            }
+        };
+        assert!(!renewed(3600).await);
+        assert!(renewed(0).await);
+        storage
+            .set_websub_subscription_state(
+                source.id,
+                models::WEBSUB_UNSUBSCRIBING.to_string(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert!(!renewed(3600).await);
+        assert!(renewed(0).await);
+        storage.delete_source(source.id).await.unwrap();
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_source_http_status_kept() {
+        let storage = test_storage();
+        let source = test_source(&storage, "TEST").await;
+        let status = || {
+            let storage = storage.clone();
+            async move {
+                storage
+                    .get_source_health(source.id)
+                    .await
+                    .unwrap()
+                    .unwrap()
+                    .last_http_status
+            }
+        };
+        storage
+            .set_source_http_status(source.id, 503)
+            .await
+            .unwrap();
+        let health = storage
+            .set_source_failure(source.id, "unavailable".to_string())
+            .await
+            .unwrap();
+        assert_eq!(health.last_http_status, Some(503));
+        storage
+            .set_source_http_status(source.id, 304)
+            .await
+            .unwrap();
+        storage.set_source_success(source.id).await.unwrap();
+        assert_eq!(status().await, Some(304));
+        storage.delete_source(source.id).await.unwrap();
+    }
+}
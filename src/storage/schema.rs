@@ -10,6 +10,22 @@ table! {
         #[sql_name = "type"]
         type_ -> Text,
         meta -> Nullable<Text>,
+        skipped -> Nullable<Text>,
+        download_state -> Text,
+        download_attempts -> Int4,
+        download_error -> Nullable<Text>,
+        download_attempted_at -> Nullable<Timestamp>,
+        original_id -> Nullable<Int4>,
+    }
+}
+
+table! {
+    http_cache (source_id) {
+        source_id -> Int4,
+        etag -> Nullable<Text>,
+        last_modified -> Nullable<Text>,
+        content_hash -> Nullable<Text>,
+        updated_at -> Timestamp,
     }
 }
 
@@ -23,6 +39,14 @@ table! {
         date -> Timestamp,
         image -> Nullable<Text>,
         external_link -> Text,
+        forward_source_id -> Nullable<Int4>,
+        forward_source_record_id -> Nullable<Text>,
+        forward_origin -> Nullable<Text>,
+        forward_date -> Nullable<Timestamp>,
+        parent_id -> Nullable<Int4>,
+        raw_content -> Nullable<Text>,
+        summary -> Nullable<Text>,
+        original_content -> Nullable<Text>,
     }
 }
 
@@ -35,10 +59,61 @@ table! {
         image -> Nullable<Text>,
         last_scrape_time -> Timestamp,
         external_link -> Text,
+        account -> Nullable<Text>,
+        state -> Text,
+        scrape_interval -> Nullable<Int4>,
+        adaptive_interval -> Nullable<Int4>,
+        next_scrape_time -> Timestamp,
+        feed_kind -> Nullable<Text>,
+        fetch_full_content -> Bool,
+        image_remote_id -> Nullable<Text>,
+    }
+}
+
+table! {
+    source_health (source_id) {
+        source_id -> Int4,
+        last_success_time -> Nullable<Timestamp>,
+        last_error_time -> Nullable<Timestamp>,
+        last_error -> Nullable<Text>,
+        consecutive_failures -> Int4,
+        last_http_status -> Nullable<Int4>,
+    }
+}
+
+table! {
+    websub_subscriptions (source_id) {
+        source_id -> Int4,
+        hub -> Text,
+        topic -> Text,
+        secret -> Text,
+        state -> Text,
+        lease_expires -> Nullable<Timestamp>,
+        updated_at -> Timestamp,
+    }
+}
+
+table! {
+    webhook_tokens (source_id) {
+        source_id -> Int4,
+        token_hash -> Text,
+        created_at -> Timestamp,
     }
 }
 
 joinable!(files -> records (record_id));
+joinable!(http_cache -> sources (source_id));
 joinable!(records -> sources (source_id));
+joinable!(source_health -> sources (source_id));
+joinable!(webhook_tokens -> sources (source_id));
+joinable!(websub_subscriptions -> sources (source_id));
 
-allow_tables_to_appear_in_same_query!(files, records, sources,);
+allow_tables_to_appear_in_same_query!(
+    files,
+    http_cache,
+    records,
+    source_health,
+    sources,
+    webhook_tokens,
+    websub_subscriptions,
+);
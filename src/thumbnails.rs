@@ -0,0 +1,204 @@
+use crate::config::{ThumbnailFormat, ThumbnailsConfig};
+use crate::result::{Error, Result};
+use image::imageops::FilterType;
+use image::DynamicImage;
+use std::path::{Path, PathBuf};
+
+/// Generated thumbnail in the temporary directory
+#[derive(Debug)]
+pub(crate) struct Thumbnail {
+    pub path: PathBuf,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Generates thumbnails of the image, one for every configured size.
+///
+/// Sizes bigger than the image itself are skipped, so thumbnails are never upscaled;
+/// image smaller than every size gets a single thumbnail of its own size.
+pub(crate) async fn image_thumbnails(
+    path: &Path,
+    config: &ThumbnailsConfig,
+) -> Result<Vec<Thumbnail>> {
+    let path = path.to_path_buf();
+    let config = config.clone();
+    tokio::task::spawn_blocking(move || {
+        let image = image::open(&path).map_err(|e| Error::InvalidContent(e.to_string()))?;
+        resize_all(&image, &path, &config)
+    })
+    .await
+    .map_err(|e| Error::InvalidContent(e.to_string()))?
+}
+
+/// Generates thumbnails from the first frame of the animation.
+///
+/// GIFs are decoded directly, other formats (Telegram converts animations to MP4)
+/// require `ffmpeg` in `PATH`.
+pub(crate) async fn animation_thumbnails(
+    path: &Path,
+    config: &ThumbnailsConfig,
+) -> Result<Vec<Thumbnail>> {
+    if let Ok(thumbnails) = image_thumbnails(path, config).await {
+        return Ok(thumbnails);
+    }
+    let frame_path = temp_path(path, "frame", "png")?;
+    let output = tokio::process::Command::new("ffmpeg")
+        .arg("-y")
+        .args(&["-loglevel", "error"])
+        .arg("-i")
+        .arg(path)
+        .args(&["-vframes", "1"])
+        .arg(&frame_path)
+        .output()
+        .await?;
+    if !output.status.success() {
+        tokio::fs::remove_file(&frame_path).await?;
+        return Err(Error::InvalidContent(
+            String::from_utf8_lossy(&output.stderr).to_string(),
+        ));
+    }
+    let thumbnails = image_thumbnails(&frame_path, config).await;
+    tokio::fs::remove_file(&frame_path).await?;
+    thumbnails
+}
+
+/// Writes thumbnails of all sizes, thumbnails already written are removed if one fails
+fn resize_all(
+    image: &DynamicImage,
+    path: &Path,
+    config: &ThumbnailsConfig,
+) -> Result<Vec<Thumbnail>> {
+    let image_size = image.width().max(image.height());
+    let mut sizes = config
+        .sizes
+        .iter()
+        .copied()
+        .filter(|size| *size < image_size)
+        .collect::<Vec<u32>>();
+    if sizes.is_empty() && !config.sizes.is_empty() {
+        sizes.push(image_size);
+    }
+    let mut thumbnails = vec![];
+    for size in sizes {
+        match resize(image, path, config, size) {
+            Ok(thumbnail) => thumbnails.push(thumbnail),
+            Err(e) => {
+                for thumbnail in thumbnails {
+                    if let Err(e) = std::fs::remove_file(&thumbnail.path) {
+                        warn!("{} not removed: {}", thumbnail.path.display(), e);
+                    }
+                }
+                return Err(e);
+            }
+        }
+    }
+    Ok(thumbnails)
+}
+
+fn resize(
+    image: &DynamicImage,
+    path: &Path,
+    config: &ThumbnailsConfig,
+    size: u32,
+) -> Result<Thumbnail> {
+    let thumbnail = match size < image.width().max(image.height()) {
+        true => image.resize(size, size, FilterType::Lanczos3),
+        false => image.clone(),
+    };
+    let (extension, data) = match config.format {
+        ThumbnailFormat::Jpeg => {
+            let mut data = vec![];
+            image::jpeg::JpegEncoder::new_with_quality(&mut data, config.quality)
+                .encode_image(&thumbnail)
+                .map_err(|e| Error::InvalidContent(e.to_string()))?;
+            ("jpg", data)
+        }
+        ThumbnailFormat::Webp => {
+            // encoder supports RGB(A) images only
+            let rgba = DynamicImage::ImageRgba8(thumbnail.to_rgba());
+            let data = webp::Encoder::from_image(&rgba)
+                .encode(config.quality as f32)
+                .to_vec();
+            ("webp", data)
+        }
+    };
+    let thumbnail_path = temp_path(path, &size.to_string(), extension)?;
+    if let Err(e) = std::fs::write(&thumbnail_path, data) {
+        std::fs::remove_file(&thumbnail_path)?;
+        return Err(e.into());
+    }
+    Ok(Thumbnail {
+        path: thumbnail_path,
+        width: thumbnail.width(),
+        height: thumbnail.height(),
+    })
+}
+
+/// Creates empty temp file with a unique name, so files of the same name don't collide
+fn temp_path(path: &Path, suffix: &str, extension: &str) -> Result<PathBuf> {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    Ok(tempfile::Builder::new()
+        .prefix(&format!("{}_{}_", stem, suffix))
+        .suffix(&format!(".{}", extension))
+        .tempfile()?
+        .into_temp_path()
+        .keep()
+        .map_err(|e| e.error)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::image_thumbnails;
+    use crate::config::{ThumbnailFormat, ThumbnailsConfig};
+
+    #[tokio::test]
+    async fn test_image_thumbnails() {
+        let path = std::env::temp_dir().join("agg-r-thumbnails-test.png");
+        image::RgbImage::new(800, 400).save(&path).unwrap();
+        for format in &[ThumbnailFormat::Jpeg, ThumbnailFormat::Webp] {
+            let config = ThumbnailsConfig {
+                sizes: vec![100, 320, 1280],
+                format: *format,
+                quality: 80,
+            };
+            let thumbnails = image_thumbnails(&path, &config).await.unwrap();
+            assert_eq!(
+                thumbnails
+                    .iter()
+                    .map(|t| (t.width, t.height))
+                    .collect::<Vec<(u32, u32)>>(),
+                vec![(100, 50), (320, 160)]
+            );
+            for thumbnail in thumbnails {
+                assert!(image::open(&thumbnail.path).is_ok());
+                std::fs::remove_file(&thumbnail.path).unwrap();
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_small_image_thumbnails() {
+        let path = std::env::temp_dir().join("agg-r-thumbnails-small-test.png");
+        image::RgbImage::new(60, 30).save(&path).unwrap();
+        let config = ThumbnailsConfig {
+            sizes: vec![100, 320],
+            format: ThumbnailFormat::Jpeg,
+            quality: 80,
+        };
+        // thumbnails of the same file don't overwrite each other
+        let first = image_thumbnails(&path, &config).await.unwrap();
+        let second = image_thumbnails(&path, &config).await.unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!((first[0].width, first[0].height), (60, 30));
+        assert_ne!(first[0].path, second[0].path);
+        for thumbnail in first.iter().chain(second.iter()) {
+            assert!(image::open(&thumbnail.path).is_ok());
+            std::fs::remove_file(&thumbnail.path).unwrap();
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,6 +1,105 @@
+use hyper::body::HttpBody;
+use hyper::{Body, StatusCode};
+use url::Url;
+
 pub fn empty_string_as_option(value: &str) -> Option<String> {
     match value.len() {
         0 => None,
         _ => Some(value.to_string()),
     }
 }
+
+/// Reads request body up to `max_size` bytes, `413 Payload Too Large` is returned if it's bigger
+pub(crate) async fn read_body(
+    mut body: Body,
+    max_size: usize,
+) -> std::result::Result<Vec<u8>, StatusCode> {
+    let mut bytes = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| {
+            warn!("can't read request: {}", e);
+            StatusCode::BAD_REQUEST
+        })?;
+        if bytes.len() + chunk.len() > max_size {
+            return Err(StatusCode::PAYLOAD_TOO_LARGE);
+        }
+        bytes.extend_from_slice(&chunk);
+    }
+    Ok(bytes)
+}
+
+/// Resolves relative link against the base url, only http links are returned
+pub(crate) fn resolve_link(base_url: &str, link: &str) -> Option<Url> {
+    let url = match Url::parse(base_url) {
+        Ok(base) => base.join(link).ok()?,
+        Err(_) => Url::parse(link).ok()?,
+    };
+    match url.scheme() {
+        "http" | "https" => Some(url),
+        _ => None,
+    }
+}
+
+/// Removes tracking query parameters: `utm_*`, `fbclid`
+pub(crate) fn strip_tracking_params(url: &mut Url) {
+    let is_tracking = |name: &str| name.starts_with("utm_") || name == "fbclid";
+    if !url.query_pairs().any(|(name, _)| is_tracking(&name)) {
+        return;
+    }
+    let params = url
+        .query_pairs()
+        .filter(|(name, _)| !is_tracking(name))
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect::<Vec<(String, String)>>();
+    if params.is_empty() {
+        url.set_query(None);
+    } else {
+        url.query_pairs_mut().clear().extend_pairs(params);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{resolve_link, strip_tracking_params};
+    use url::Url;
+
+    #[test]
+    fn test_resolve_link() {
+        let base = "https://example.com/blog/feed.xml";
+        let resolve = |link| resolve_link(base, link).map(|u| u.to_string());
+        assert_eq!(
+            resolve("/b.jpg"),
+            Some("https://example.com/b.jpg".to_string())
+        );
+        assert_eq!(
+            resolve("c.gif"),
+            Some("https://example.com/blog/c.gif".to_string())
+        );
+        assert_eq!(
+            resolve("//cdn.example.com/d.png"),
+            Some("https://cdn.example.com/d.png".to_string())
+        );
+        assert_eq!(resolve("mailto:a@example.com"), None);
+    }
+
+    #[test]
+    fn test_strip_tracking_params() {
+        let strip = |link| {
+            let mut url = Url::parse(link).unwrap();
+            strip_tracking_params(&mut url);
+            url.to_string()
+        };
+        assert_eq!(
+            strip("https://example.com/a?utm_source=rss&utm_medium=feed"),
+            "https://example.com/a"
+        );
+        assert_eq!(
+            strip("https://example.com/a?id=1&fbclid=abc#top"),
+            "https://example.com/a?id=1#top"
+        );
+        assert_eq!(
+            strip("https://example.com/a?id=1&b=2"),
+            "https://example.com/a?id=1&b=2"
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use crate::config::FullContentConfig;
+use crate::result::{Error, Result};
+use scraper::{ElementRef, Html, Selector};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+use url::Url;
+
+/// Paragraphs shorter than this are not counted as article text
+const MIN_PARAGRAPH_LEN: usize = 25;
+
+lazy_static! {
+    static ref PARAGRAPHS: Selector = Selector::parse("p, pre").unwrap();
+}
+
+/// Fetches pages of the web records and extracts the article from them
+pub(crate) struct FullContentFetcher {
+    client: reqwest::Client,
+    host_interval: Duration,
+    /// Time the next request to the host is allowed at
+    hosts: Mutex<HashMap<String, Instant>>,
+}
+
+impl FullContentFetcher {
+    pub fn new(config: &FullContentConfig) -> Self {
+        Self {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(config.timeout_secs))
+                .build()
+                .expect("can't build http client"),
+            host_interval: Duration::from_secs(config.host_secs_interval),
+            hosts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Returns html of the page main content, requests to the same host are rate limited
+    pub async fn fetch(&self, link: &str) -> Result<String> {
+        let url = Url::parse(link).map_err(|e| Error::InvalidContent(e.to_string()))?;
+        self.wait_for_host(url.host_str().unwrap_or_default()).await;
+        let page = self
+            .client
+            .get(url)
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| Error::InvalidContent(e.to_string()))?
+            .text()
+            .await
+            .map_err(|e| Error::InvalidContent(e.to_string()))?;
+        extract_article(&page)
+            .ok_or_else(|| Error::InvalidContent(format!("no article found at {}", link)))
+    }
+
+    async fn wait_for_host(&self, host: &str) {
+        let now = Instant::now();
+        let allowed_at = {
+            let mut hosts = self.hosts.lock().await;
+            let allowed_at = match hosts.get(host) {
+                Some(next) if *next > now => *next,
+                _ => now,
+            };
+            hosts.insert(host.to_string(), allowed_at + self.host_interval);
+            allowed_at
+        };
+        if allowed_at > now {
+            tokio::time::delay_until(allowed_at.into()).await;
+        }
+    }
+}
+
+/// Readability-style extraction: paragraphs score their parent and a half of it to
+/// the grandparent, the best scored element is the article
+fn extract_article(page: &str) -> Option<String> {
+    let document = Html::parse_document(page);
+    let mut scores = HashMap::new();
+    for paragraph in document.select(&PARAGRAPHS) {
+        let text = paragraph.text().collect::<String>();
+        let text = text.trim();
+        if text.len() < MIN_PARAGRAPH_LEN {
+            continue;
+        }
+        let score = 1.0 + text.matches(',').count() as f64 + (text.len() as f64 / 100.0).min(3.0);
+        let parent = match paragraph.parent() {
+            Some(parent) => parent,
+            None => continue,
+        };
+        *scores.entry(parent.id()).or_insert(0.0) += score;
+        if let Some(grandparent) = parent.parent() {
+            *scores.entry(grandparent.id()).or_insert(0.0) += score / 2.0;
+        }
+    }
+    let (best, _) = scores
+        .into_iter()
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap())?;
+    let article = ElementRef::wrap(document.tree.get(best)?)?;
+    Some(article.inner_html().trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::extract_article;
+
+    #[test]
+    fn test_extract_article() {
+        let page = r#"<html><head><title>Post</title></head><body>
+            <nav><p>Home</p><p>About</p></nav>
+            <div class="sidebar"><p>Subscribe to the newsletter, it's free.</p></div>
+            <div class="post">
+                <p>First paragraph of the article, with some text, long enough to count.</p>
+                <p>Second paragraph of the article, also with text, and commas, to score.</p>
+            </div>
+            <footer><p>Copyright</p></footer>
+        </body></html>"#;
+        let article = extract_article(page).unwrap();
+        assert!(article.starts_with("<p>First paragraph"));
+        assert!(article.contains("Second paragraph"));
+        assert!(!article.contains("newsletter"));
+        assert_eq!(
+            extract_article("<html><body><p>short</p></body></html>"),
+            None
+        );
+    }
+}
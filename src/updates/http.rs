@@ -2,22 +2,38 @@ use async_trait::async_trait;
 use chrono::NaiveDateTime;
 use http_collector::models::{Feed, FeedItem, FeedKind};
 use http_collector::result::Result as HttpResult;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+use super::full_content::FullContentFetcher;
+use super::http_cache::StorageCache;
+use super::media_cache::MediaCache;
+use super::schedule::{self, ScrapeIntervals};
+use super::websub::{self, WebSub};
 use super::{SourceData, SourceProvider, UpdatesHandler};
+use crate::config::{FullContentConfig, MediaCacheConfig, SanitizeConfig, WebSubConfig};
+use crate::file_store::FileStore;
 use crate::models;
 use crate::result::{Error, Result};
+use crate::sanitize::Sanitizer;
 use crate::storage::Storage;
+use crate::tools::{resolve_link, strip_tracking_params};
 
 use crate::updates::Source;
-use http_collector::collector::{CacheStub, HttpCollector, ResultsHandler};
+use http_collector::collector::{HttpCollector, ResultsHandler};
 use http_collector::result::Error as CollectorError;
 use serde::Serialize;
 use tokio::sync::{mpsc, Mutex};
 use tokio::time::Duration;
+use url::Url;
 
 // TODO: enum?
-const WEB: &str = "WEB";
+pub(crate) const WEB: &str = "WEB";
+/// Number of items in the source candidate preview
+const SAMPLE_ITEMS: usize = 3;
+/// WebSub subscriptions are renewed and new sources are subscribed with this period
+const WEBSUB_RENEW_PERIOD: Duration = Duration::from_secs(10 * 60);
 
 impl From<Feed> for FeedUpdate {
     fn from(feed_update: Feed) -> Self {
@@ -25,7 +41,10 @@ impl From<Feed> for FeedUpdate {
             link: feed_update.link,
             name: feed_update.name,
             image: feed_update.image,
-            kind: feed_update.kind,
+            kind: Some(feed_update.kind),
+            ttl: feed_update.ttl,
+            update_period: feed_update.update_period,
+            update_frequency: feed_update.update_frequency,
             updates: feed_update
                 .content
                 .iter()
@@ -42,27 +61,44 @@ impl From<FeedItem> for Update {
             content: feed_item.content,
             pub_date: feed_item.pub_date,
             guid: feed_item.guid,
+            link: feed_item.link,
             image_link: feed_item.image_link,
         }
     }
 }
 
+/// Passes results of the source scrape to the updates sender, errors are tagged by the source link
 struct Handler {
     sender: Arc<Mutex<mpsc::Sender<Result<SourceData>>>>,
+    link: String,
+    processed: AtomicBool,
 }
 
 impl Handler {
-    pub fn new(sender: Arc<Mutex<mpsc::Sender<Result<SourceData>>>>) -> Self {
-        Self { sender }
+    pub fn new(sender: Arc<Mutex<mpsc::Sender<Result<SourceData>>>>, link: &str) -> Self {
+        Self {
+            sender,
+            link: link.to_string(),
+            processed: AtomicBool::new(false),
+        }
+    }
+
+    /// Whether collector returned any result, unchanged feeds are skipped by the collector
+    fn processed(&self) -> bool {
+        self.processed.load(Ordering::SeqCst)
     }
 }
 
 #[async_trait]
 impl ResultsHandler for Handler {
     async fn process(&self, result: HttpResult<(&Feed, FeedKind, String)>) {
+        self.processed.store(true, Ordering::SeqCst);
         let update = match result {
             Ok((updates, _, _)) => Ok(SourceData::WebFeed(FeedUpdate::from(updates.clone()))),
-            Err(err) => Err(Error::HttpCollectorError(err)),
+            Err(err) => Ok(SourceData::WebError(
+                self.link.clone(),
+                Error::HttpCollectorError(err),
+            )),
         };
         let mut local = self.sender.lock().await;
         if local.send(update).await.is_err() {
@@ -78,15 +114,39 @@ pub struct Update {
     pub content: String,
     pub pub_date: NaiveDateTime,
     pub guid: String,
+    /// Item `<link>`, may be relative to the feed url
+    pub link: Option<String>,
     pub image_link: Option<String>,
 }
 
+impl Update {
+    /// Link to the item page: item link or guid if it's a link, tracking params are removed
+    fn external_link(&self, feed_link: &str) -> Option<String> {
+        let mut url = self
+            .link
+            .as_deref()
+            .and_then(|l| resolve_link(feed_link, l.trim()))
+            .or_else(|| match Url::parse(self.guid.trim()) {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Some(url),
+                _ => None,
+            })?;
+        strip_tracking_params(&mut url);
+        Some(url.to_string())
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct FeedUpdate {
     pub link: String,
-    pub kind: FeedKind,
+    /// `None` for feeds pushed by WebSub hubs, kind of the source is kept
+    pub kind: Option<FeedKind>,
     pub name: String,
     pub image: Option<String>,
+    /// RSS `<ttl>` in minutes
+    pub ttl: Option<u32>,
+    /// `sy:updatePeriod` and `sy:updateFrequency` of the syndication module
+    pub update_period: Option<String>,
+    pub update_frequency: Option<u32>,
     pub updates: Vec<Update>,
 }
 
@@ -95,7 +155,13 @@ where
     S: Storage + Send + Sync + Clone + 'static,
 {
     sleep_secs: u64,
-    scrape_source_secs_interval: i32,
+    scrape_intervals: ScrapeIntervals,
+    max_failures: i32,
+    media_cache: MediaCacheConfig,
+    full_content: FullContentConfig,
+    sanitize: SanitizeConfig,
+    websub: WebSubConfig,
+    file_store: Option<Arc<dyn FileStore + Send + Sync>>,
     storage: Option<S>,
 }
 
@@ -114,7 +180,17 @@ where
     pub fn new() -> Self {
         Self {
             sleep_secs: 60,
-            scrape_source_secs_interval: 60,
+            scrape_intervals: ScrapeIntervals {
+                default: 60,
+                min: 60,
+                max: 86400,
+            },
+            max_failures: 10,
+            media_cache: MediaCacheConfig::default(),
+            full_content: FullContentConfig::default(),
+            sanitize: SanitizeConfig::default(),
+            websub: WebSubConfig::default(),
+            file_store: None,
             storage: None,
         }
     }
@@ -125,7 +201,20 @@ where
     }
 
     pub fn with_scrape_source_secs_interval(mut self, scrape_source_secs_interval: i32) -> Self {
-        self.scrape_source_secs_interval = scrape_source_secs_interval;
+        self.scrape_intervals.default = scrape_source_secs_interval;
+        self
+    }
+
+    /// Bounds of the interval learned from the source publishing frequency
+    pub fn with_scrape_secs_bounds(mut self, min: i32, max: i32) -> Self {
+        self.scrape_intervals.min = min;
+        self.scrape_intervals.max = max;
+        self
+    }
+
+    /// Source is disabled after `max_failures` failed scrapes in a row
+    pub fn with_max_failures(mut self, max_failures: i32) -> Self {
+        self.max_failures = max_failures;
         self
     }
 
@@ -134,15 +223,72 @@ where
         self
     }
 
+    /// Caches records images to the file store, file store is required if cache is enabled
+    pub fn with_media_cache(
+        mut self,
+        media_cache: &MediaCacheConfig,
+        file_store: Arc<dyn FileStore + Send + Sync>,
+    ) -> Self {
+        self.media_cache = media_cache.clone();
+        self.file_store = Some(file_store);
+        self
+    }
+
+    /// Fetching settings of the sources with full content enabled
+    pub fn with_full_content(mut self, full_content: &FullContentConfig) -> Self {
+        self.full_content = full_content.clone();
+        self
+    }
+
+    pub fn with_sanitize(mut self, sanitize: &SanitizeConfig) -> Self {
+        self.sanitize = sanitize.clone();
+        self
+    }
+
+    /// Subscribes to the hubs of the feeds, source is polled if the hub is unavailable
+    pub fn with_websub(mut self, websub: &WebSubConfig) -> Self {
+        self.websub = websub.clone();
+        self
+    }
+
     pub fn build(self) -> HttpSource<S> {
         if self.storage.is_none() {
             panic!("storage not specified")
         }
+        let media_cache = match (self.media_cache.enabled, self.file_store) {
+            (false, _) => None,
+            (true, Some(file_store)) => {
+                Some(Arc::new(MediaCache::new(&self.media_cache, file_store)))
+            }
+            (true, None) => panic!("file store not specified"),
+        };
+        let storage = self.storage.unwrap();
+        let (websub, websub_notifications) = match self.websub.enabled {
+            false => (None, None),
+            true => {
+                let address = self
+                    .websub
+                    .listen_address
+                    .parse::<SocketAddr>()
+                    .expect("invalid websub listen address");
+                let (sender, receiver) = mpsc::channel(100);
+                let websub = WebSub::new(&self.websub, storage.clone(), sender);
+                (Some((Arc::new(websub), address)), Some(receiver))
+            }
+        };
         HttpSource {
             sleep_secs: self.sleep_secs,
-            scrape_source_secs_interval: self.scrape_source_secs_interval,
-            storage: self.storage.unwrap(),
-            collector: Arc::new(HttpCollector::new()),
+            scrape_intervals: self.scrape_intervals,
+            max_failures: self.max_failures,
+            collector: Arc::new(HttpCollector::with_cache(StorageCache::new(
+                storage.clone(),
+            ))),
+            storage,
+            media_cache,
+            full_content: Arc::new(FullContentFetcher::new(&self.full_content)),
+            sanitizer: Sanitizer::new(&self.sanitize),
+            websub,
+            websub_notifications: Mutex::new(websub_notifications),
         }
     }
 }
@@ -152,8 +298,15 @@ where
     S: Storage + Send + Sync + Clone + 'static,
 {
     sleep_secs: u64,
-    scrape_source_secs_interval: i32,
-    collector: Arc<HttpCollector<CacheStub>>,
+    scrape_intervals: ScrapeIntervals,
+    max_failures: i32,
+    collector: Arc<HttpCollector<StorageCache<S>>>,
+    media_cache: Option<Arc<MediaCache>>,
+    full_content: Arc<FullContentFetcher>,
+    sanitizer: Sanitizer,
+    websub: Option<(Arc<WebSub<S>>, SocketAddr)>,
+    // notifications of the hubs, taken by `run`
+    websub_notifications: Mutex<Option<mpsc::Receiver<websub::Notification>>>,
     storage: S,
 }
 
@@ -164,6 +317,132 @@ where
     pub fn builder() -> HttpSourceBuilder<S> {
         HttpSourceBuilder::new()
     }
+
+    async fn detect_feeds(&self, query: &str) -> Result<Vec<Feed>> {
+        let mut query = query.to_string();
+        if !query.starts_with("http://") && !query.starts_with("https://") {
+            query = format!("https://{}", query);
+        }
+        match self.collector.detect_feeds(query.as_str()).await {
+            Ok(feeds) => Ok(feeds),
+            Err(CollectorError::RequestError) => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Records failed scrape of the source: source is backed off exponentially and disabled
+    /// after `max_failures` failures in a row
+    pub async fn process_failure(&self, link: &str, error: &Error) -> Result<()> {
+        let source = self
+            .storage
+            .get_source_by_origin(WEB.to_string(), link.to_string())
+            .await?
+            .ok_or(Error::SourceNotFound)?;
+        let health = self
+            .storage
+            .set_source_failure(source.id, error.to_string())
+            .await?;
+        // feed may switch format, kind is detected again on the next scrape
+        if source.feed_kind.is_some() && is_not_a_feed(error) {
+            debug!(
+                "{} isn't a {:?} feed, detect its kind again: {}",
+                source.origin, source.feed_kind, error
+            );
+            self.storage.set_source_feed_kind(source.id, None).await?;
+        }
+        if health.consecutive_failures >= self.max_failures {
+            warn!(
+                "{} is disabled after {} failures: {}",
+                source.origin, health.consecutive_failures, error
+            );
+            return self
+                .storage
+                .set_source_state(source.id, models::SOURCE_DISABLED.to_string())
+                .await;
+        }
+        let interval = self
+            .scrape_intervals
+            .backoff_interval(&source, health.consecutive_failures);
+        debug!(
+            "{} failed {} times, retry in {}s: {}",
+            source.origin, health.consecutive_failures, interval, error
+        );
+        self.storage
+            .set_source_next_scrape_time(source.id, schedule::next_scrape_time(interval))
+            .await
+    }
+
+    /// Replaces content of the records with articles fetched from their links in background,
+    /// images are cached after the article is fetched
+    fn spawn_full_content(&self, records: Vec<models::Record>, feed_link: &str) {
+        let fetcher = self.full_content.clone();
+        let sanitizer = self.sanitizer.clone();
+        let media_cache = self.media_cache.clone();
+        let storage = self.storage.clone();
+        let feed_link = feed_link.to_string();
+        tokio::spawn(async move {
+            for mut record in records {
+                let base_url = match record.external_link.as_str() {
+                    "" => feed_link.clone(),
+                    link => link.to_string(),
+                };
+                if !record.external_link.is_empty() {
+                    match fetcher.fetch(&record.external_link).await {
+                        Ok(content) => {
+                            let content = sanitizer.clean(&content, Some(&base_url));
+                            match storage
+                                .set_record_full_content(record.id, content.clone())
+                                .await
+                            {
+                                Ok(_) => {
+                                    record.summary = Some(record.content);
+                                    record.content = content;
+                                }
+                                Err(e) => error!("{}", e),
+                            }
+                        }
+                        Err(e) => warn!(
+                            "can't fetch full content of {}: {}",
+                            record.external_link, e
+                        ),
+                    }
+                }
+                if let Some(media_cache) = &media_cache {
+                    if let Err(e) = media_cache
+                        .cache_record(&storage, &record, &base_url, WEB)
+                        .await
+                    {
+                        error!("{}", e);
+                    }
+                }
+            }
+        });
+    }
+
+    async fn update_adaptive_interval(
+        &self,
+        source: &models::Source,
+        updates: &FeedUpdate,
+    ) -> Result<()> {
+        let dates = updates
+            .updates
+            .iter()
+            .map(|u| u.pub_date)
+            .collect::<Vec<NaiveDateTime>>();
+        let hint = schedule::feed_hint(
+            updates.ttl,
+            updates.update_period.as_deref(),
+            updates.update_frequency,
+        );
+        let interval = self.scrape_intervals.adaptive_interval(&dates, hint);
+        if source.adaptive_interval != Some(interval) {
+            debug!("scrape interval of {} is {}s", source.name, interval);
+            self.storage
+                .set_source_adaptive_interval(source.id, Some(interval))
+                .await?;
+        }
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -178,6 +457,8 @@ where
             external_link: updates.link.clone(),
             kind: WEB.to_string(),
             image: updates.image.clone(),
+            account: None,
+            feed_kind: updates.kind.as_ref().and_then(feed_kind_to_string),
         };
 
         Ok(self
@@ -189,10 +470,13 @@ where
     }
 
     async fn process_updates(&self, updates: &FeedUpdate) -> Result<usize> {
-        let mut sources = self.storage.search_source(updates.link.as_str()).await?;
-        let source = match sources.len() {
-            0 => self.create_source(updates).await?,
-            _ => sources.pop().unwrap(),
+        let source = match self
+            .storage
+            .get_source_by_origin(WEB.to_string(), updates.link.clone())
+            .await?
+        {
+            Some(source) => source,
+            None => self.create_source(updates).await?,
         };
         let affected = self
             .storage
@@ -205,28 +489,37 @@ where
                         title: u.title.clone(),
                         source_record_id: u.guid.clone(),
                         source_id: source.id,
-                        content: u.content.clone(),
+                        content: self.sanitizer.clean(&u.content, Some(&updates.link)),
                         image: u.image_link.clone(),
+                        external_link: u.external_link(&updates.link),
+                        ..Default::default()
                     })
                     .collect::<Vec<models::NewRecord>>(),
             )
             .await?;
-        if affected.is_empty() {
-            let mut tasks = vec![];
-            updates.updates.iter().for_each(|u| {
-                if affected
-                    .iter()
-                    .any(|r| r.source_record_id == u.guid && r.source_id == source.id)
+        if source.fetch_full_content {
+            self.spawn_full_content(affected.clone(), &updates.link);
+        } else if let Some(media_cache) = &self.media_cache {
+            for record in &affected {
+                if let Err(e) = media_cache
+                    .cache_record(&self.storage, record, &updates.link, WEB)
+                    .await
                 {
-                    tasks.push(self.storage.set_record_external_link(
-                        u.guid.clone(),
-                        source.id,
-                        u.guid.clone(),
-                    ));
+                    error!("{}", e);
                 }
-            });
-            futures::future::join_all(tasks).await;
+            }
+        }
+        let feed_kind = updates.kind.as_ref().and_then(feed_kind_to_string);
+        if feed_kind.is_some() && source.feed_kind != feed_kind {
+            debug!("feed kind of {} is {:?}", source.origin, feed_kind);
+            self.storage
+                .set_source_feed_kind(source.id, feed_kind)
+                .await?;
         }
+        if source.scrape_interval.is_none() {
+            self.update_adaptive_interval(&source, updates).await?;
+        }
+        self.storage.set_source_success(source.id).await?;
         self.storage.set_source_scraped_now(source).await?;
         Ok(affected.len())
     }
@@ -246,85 +539,416 @@ where
         Ok(())
     }
 
-    async fn run(&self, updates_sender: Arc<Mutex<mpsc::Sender<Result<SourceData>>>>) {
-        let (sources_sender, sources_receiver) = mpsc::channel(2000);
-        let sleep_secs = self.sleep_secs;
-        let scrape_source_secs_interval = self.scrape_source_secs_interval;
-        let st = self.storage.clone();
-        tokio::spawn(async move {
-            sources_gen(st, scrape_source_secs_interval, sleep_secs, sources_sender).await
-        });
-        let http_handler = Handler::new(updates_sender);
-        let http_runner = self.collector.clone();
-        tokio::spawn(async move { http_runner.run(sources_receiver, &http_handler).await });
+    async fn join(&self, _source: &models::Source) -> Result<()> {
+        // web sources are scraped while active
+        Ok(())
     }
 
-    async fn search_source(&self, query: &str) -> Result<Vec<models::Source>> {
-        let mut query = query.to_string();
-        if !query.starts_with("http://") && !query.starts_with("https://") {
-            query = format!("https://{}", query);
+    async fn leave(&self, source: &models::Source) -> Result<()> {
+        match &self.websub {
+            Some((websub, _)) => websub.unsubscribe(source.id).await,
+            None => Ok(()),
+        }
+    }
+
+    async fn remove_files(&self, _source: &models::Source, files: &[models::File]) -> Result<()> {
+        // source image is a remote link
+        match &self.media_cache {
+            Some(media_cache) => media_cache.remove_files(&self.storage, files).await,
+            None => Ok(()),
         }
-        let feeds = match self.collector.detect_feeds(query.as_str()).await {
-            Ok(feeds) => feeds,
-            Err(CollectorError::RequestError) => vec![],
-            Err(e) => return Err(e.into()),
+    }
+
+    async fn run(&self, updates_sender: Arc<Mutex<mpsc::Sender<Result<SourceData>>>>) {
+        let scraper = Scraper {
+            storage: self.storage.clone(),
+            collector: self.collector.clone(),
+            intervals: self.scrape_intervals,
+            updates_sender,
+            websub: self.websub.as_ref().map(|(websub, _)| websub.clone()),
         };
-        let new_sources = self
-            .storage
-            .save_sources(
-                feeds
-                    .iter()
-                    .map(|f| models::NewSource {
-                        name: f.name.clone(),
-                        origin: f.link.clone(),
-                        external_link: f.link.clone(),
-                        kind: WEB.to_string(),
-                        image: f.image.clone(),
+        if let Some((websub, address)) = &self.websub {
+            tokio::spawn(websub::serve(*address, websub.clone()));
+            let renewer = websub.clone();
+            tokio::spawn(async move {
+                loop {
+                    if let Err(e) = renewer.renew().await {
+                        error!("{}", e)
+                    }
+                    tokio::time::delay_for(WEBSUB_RENEW_PERIOD).await;
+                }
+            });
+            if let Some(notifications) = self.websub_notifications.lock().await.take() {
+                let scraper = scraper.clone();
+                tokio::spawn(async move { scrape_notified(scraper, notifications).await });
+            }
+        }
+        let sleep_secs = self.sleep_secs;
+        tokio::spawn(async move { sources_gen(scraper, sleep_secs).await });
+    }
+
+    async fn discover_sources(&self, query: &str) -> Result<Vec<models::SourceCandidate>> {
+        Ok(self
+            .detect_feeds(query)
+            .await?
+            .into_iter()
+            .map(|f| models::SourceCandidate {
+                kind: WEB.to_string(),
+                origin: f.link.clone(),
+                name: f.name,
+                description: None,
+                image: f.image,
+                external_link: f.link,
+                account: None,
+                sample_items: f
+                    .content
+                    .into_iter()
+                    .take(SAMPLE_ITEMS)
+                    .map(|i| models::SampleItem {
+                        title: i.title,
+                        content: i.content,
+                        date: Some(i.pub_date),
                     })
                     .collect(),
-            )
-            .await?;
-        let feeds: Vec<FeedUpdate> = feeds.iter().map(|f| FeedUpdate::from(f.clone())).collect();
-        let mut tasks = vec![];
-        feeds
-            .iter()
-            .for_each(|f| tasks.push(self.process_updates(f)));
-        futures::future::join_all(tasks).await;
-        Ok(new_sources)
+            })
+            .collect())
+    }
+
+    async fn subscribe(&self, candidate: &models::SourceCandidate) -> Result<models::Source> {
+        let feed = self
+            .detect_feeds(&candidate.origin)
+            .await?
+            .into_iter()
+            .find(|f| f.link == candidate.origin);
+        match feed {
+            // ingest current items right away, source is created by `process_updates`
+            Some(feed) => {
+                let feed = FeedUpdate::from(feed);
+                self.process_updates(&feed).await?;
+                self.storage
+                    .get_source_by_origin(WEB.to_string(), feed.link)
+                    .await?
+                    .ok_or(Error::SourceNotFound)
+            }
+            None => Ok(self
+                .storage
+                .save_sources(vec![models::NewSource {
+                    name: candidate.name.clone(),
+                    origin: candidate.origin.clone(),
+                    external_link: candidate.external_link.clone(),
+                    kind: WEB.to_string(),
+                    image: candidate.image.clone(),
+                    account: None,
+                    feed_kind: None,
+                }])
+                .await?
+                .pop()
+                .unwrap()),
+        }
+    }
+}
+
+/// Whether the response was received, but it isn't a feed of the expected kind
+fn is_not_a_feed(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::HttpCollectorError(CollectorError::ParseError { .. })
+    )
+}
+
+/// Feed kind is stored by its serialized name
+fn feed_kind_to_string(kind: &FeedKind) -> Option<String> {
+    match serde_json::to_value(kind) {
+        Ok(serde_json::Value::String(kind)) => Some(kind),
+        _ => None,
+    }
+}
+
+fn feed_kind_from_str(kind: &str) -> Option<FeedKind> {
+    match serde_json::from_value(serde_json::Value::String(kind.to_string())) {
+        Ok(kind) => Some(kind),
+        Err(e) => {
+            warn!("unknown feed kind {}: {}", kind, e);
+            None
+        }
     }
 }
 
-async fn sources_gen<S: Storage>(
+/// Scrapes scheduled sources
+struct Scraper<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
     storage: S,
-    source_check_period: i32,
-    sleep_period: u64,
-    mut sender: mpsc::Sender<Vec<(Option<FeedKind>, String)>>,
-) {
-    let sleep_period = Duration::from_secs(sleep_period);
-    loop {
-        match get_sources(&storage, &source_check_period).await {
-            Ok(sources) => {
-                debug!("found sources for scrape: {:?}", sources);
-                if let Err(err) = sender.send(sources).await {
-                    error!("{}", err)
-                };
+    collector: Arc<HttpCollector<StorageCache<S>>>,
+    intervals: ScrapeIntervals,
+    updates_sender: Arc<Mutex<mpsc::Sender<Result<SourceData>>>>,
+    websub: Option<Arc<WebSub<S>>>,
+}
+
+impl<S> Clone for Scraper<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    fn clone(&self) -> Self {
+        Self {
+            storage: self.storage.clone(),
+            collector: self.collector.clone(),
+            intervals: self.intervals,
+            updates_sender: self.updates_sender.clone(),
+            websub: self.websub.clone(),
+        }
+    }
+}
+
+impl<S> Scraper<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    /// Picks sources due during the next `period`, each one is scraped at its own scrape time
+    /// and rescheduled after its interval with jitter
+    async fn schedule_sources(&self, period: Duration) -> Result<()> {
+        let now = schedule::utc_now();
+        let until = now + chrono::Duration::from_std(period).unwrap();
+        let sources = self
+            .storage
+            .get_sources_by_kind_for_scrape(WEB.to_string(), until)
+            .await?;
+        debug!("found {} sources for scrape", sources.len());
+        for source in sources {
+            let delay = (source.next_scrape_time - now)
+                .to_std()
+                .unwrap_or_else(|_| Duration::from_secs(0));
+            let interval = match &self.websub {
+                // pushed sources are polled rarely in case the hub misses updates
+                Some(websub) if websub.is_active(source.id).await => self.intervals.max,
+                _ => self.intervals.source_interval(&source),
+            };
+            // rescheduled right away, so the source isn't picked again during the next period
+            self.storage
+                .set_source_next_scrape_time(
+                    source.id,
+                    schedule::next_scrape_time(interval)
+                        + chrono::Duration::from_std(delay).unwrap(),
+                )
+                .await?;
+            let scraper = self.clone();
+            tokio::spawn(async move {
+                tokio::time::delay_for(delay).await;
+                scraper.scrape(source).await
+            });
+        }
+        Ok(())
+    }
+
+    /// Runs collector for the single source, so its failures are attributed to it
+    async fn scrape(&self, source: models::Source) {
+        trace!("scrape {}", source.origin);
+        let (mut sources_sender, sources_receiver) = mpsc::channel(1);
+        let feed_kind = source.feed_kind.as_deref().and_then(feed_kind_from_str);
+        if let Err(err) = sources_sender
+            .send(vec![(feed_kind, source.origin.clone())])
+            .await
+        {
+            error!("{}", err);
+            return;
+        }
+        drop(sources_sender);
+        let handler = Handler::new(self.updates_sender.clone(), &source.origin);
+        self.collector.run(sources_receiver, &handler).await;
+        // unchanged feeds don't reach `process_updates`, so the scrape is recorded here
+        if !handler.processed() {
+            trace!("{} is not modified", source.origin);
+            if let Err(e) = self.storage.set_source_success(source.id).await {
+                error!("{}", e);
             }
-            Err(e) => error!("{}", e),
-        };
+            if let Err(e) = self.storage.set_source_scraped_now(source).await {
+                error!("{}", e);
+            }
+        }
+    }
+}
 
-        debug!("send sources delayed for {:?}", sleep_period);
+/// Passes feeds pushed by WebSub hubs to the updates sender, sources notified without
+/// content are scraped right away
+async fn scrape_notified<S>(
+    scraper: Scraper<S>,
+    mut notifications: mpsc::Receiver<websub::Notification>,
+) where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    while let Some(notification) = notifications.recv().await {
+        let source_id = notification.source_id;
+        match (
+            scraper.storage.get_source(source_id).await,
+            notification.feed,
+        ) {
+            (Ok(Some(source)), feed) if source.state == models::SOURCE_ACTIVE => match feed {
+                Some(feed) => {
+                    let mut sender = scraper.updates_sender.lock().await;
+                    if sender.send(Ok(SourceData::WebFeed(feed))).await.is_err() {
+                        error!("updates receiver dropped");
+                        return;
+                    }
+                }
+                None => {
+                    let scraper = scraper.clone();
+                    tokio::spawn(async move { scraper.scrape(source).await });
+                }
+            },
+            (Ok(_), _) => debug!("skip notification of inactive source {}", source_id),
+            (Err(e), _) => error!("{}", e),
+        }
+    }
+}
+
+async fn sources_gen<S>(scraper: Scraper<S>, sleep_period: u64)
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    let sleep_period = Duration::from_secs(sleep_period);
+    loop {
+        if let Err(e) = scraper.schedule_sources(sleep_period).await {
+            error!("{}", e)
+        }
+        debug!("schedule sources delayed for {:?}", sleep_period);
         tokio::time::delay_for(sleep_period).await;
     }
 }
 
-async fn get_sources<S: Storage>(
-    storage: &S,
-    source_check_period_secs: &i32,
-) -> Result<Vec<(Option<FeedKind>, String)>> {
-    Ok(storage
-        .get_sources_by_kind_for_scrape(WEB.to_string(), source_check_period_secs)
-        .await?
-        .iter()
-        .map(|r| (None, r.origin.clone()))
-        .collect())
+#[cfg(test)]
+mod tests {
+    use super::Update;
+
+    #[test]
+    fn test_external_link() {
+        let update = |link: Option<&str>, guid: &str| Update {
+            title: None,
+            content: String::new(),
+            pub_date: chrono::NaiveDateTime::from_timestamp(0, 0),
+            guid: guid.to_string(),
+            link: link.map(String::from),
+            image_link: None,
+        };
+        let feed = "https://example.com/blog/feed.xml";
+        assert_eq!(
+            update(Some("/posts/1?utm_source=rss"), "1").external_link(feed),
+            Some("https://example.com/posts/1".to_string())
+        );
+        assert_eq!(
+            update(None, "https://example.com/posts/2?fbclid=x").external_link(feed),
+            Some("https://example.com/posts/2".to_string())
+        );
+        assert_eq!(
+            update(None, "tag:example.com,2021:3").external_link(feed),
+            None
+        );
+    }
+
+    #[cfg(feature = "pg-storage")]
+    #[tokio::test]
+    #[ignore]
+    async fn test_process_failure_keeps_feed_kind() {
+        use super::{HttpSource, WEB};
+        use crate::models;
+        use crate::result::Error;
+        use crate::storage::pg::tests::{test_source, test_storage};
+        use crate::storage::Storage;
+
+        let storage = test_storage();
+        let http_source = HttpSource::builder()
+            .with_storage(storage.clone())
+            .with_max_failures(2)
+            .build();
+        let source = test_source(&storage, WEB).await;
+        storage
+            .set_source_feed_kind(source.id, Some("rss".to_string()))
+            .await
+            .unwrap();
+        let error = Error::InvalidContent("connection reset".to_string());
+
+        http_source
+            .process_failure(&source.origin, &error)
+            .await
+            .unwrap();
+        let failed = storage.get_source(source.id).await.unwrap().unwrap();
+        assert_eq!(failed.feed_kind.as_deref(), Some("rss"));
+        assert_eq!(failed.state, models::SOURCE_ACTIVE);
+        assert!(failed.next_scrape_time > source.next_scrape_time);
+        let health = storage.get_source_health(source.id).await.unwrap().unwrap();
+        assert_eq!(health.consecutive_failures, 1);
+
+        http_source
+            .process_failure(&source.origin, &error)
+            .await
+            .unwrap();
+        let disabled = storage.get_source(source.id).await.unwrap().unwrap();
+        assert_eq!(disabled.state, models::SOURCE_DISABLED);
+        storage.delete_source(source.id).await.unwrap();
+    }
+
+    #[cfg(feature = "pg-storage")]
+    #[tokio::test]
+    #[ignore]
+    async fn test_process_updates_exact_origin() {
+        use super::{FeedUpdate, HttpSource, Update, WEB};
+        use crate::models;
+        use crate::storage::pg::tests::test_storage;
+        use crate::storage::Storage;
+
+        let storage = test_storage();
+        let http_source = HttpSource::builder().with_storage(storage.clone()).build();
+        let feed = format!("https://example.com/{}/feed", rand::random::<u64>());
+        // saved source which origin contains the subscribed one
+        let comments = storage
+            .save_sources(vec![models::NewSource {
+                name: feed.clone(),
+                origin: format!("{}/comments", feed),
+                kind: WEB.to_string(),
+                image: None,
+                external_link: feed.clone(),
+                account: None,
+                feed_kind: None,
+            }])
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        let updates = FeedUpdate {
+            link: feed.clone(),
+            kind: None,
+            name: "feed".to_string(),
+            image: None,
+            ttl: None,
+            update_period: None,
+            update_frequency: None,
+            updates: vec![Update {
+                title: None,
+                content: "content".to_string(),
+                pub_date: chrono::NaiveDateTime::from_timestamp(0, 0),
+                guid: "1".to_string(),
+                link: None,
+                image_link: None,
+            }],
+        };
+        http_source.process_updates(&updates).await.unwrap();
+        let source = storage
+            .get_source_by_origin(WEB.to_string(), feed)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_ne!(source.id, comments.id);
+        assert!(storage
+            .get_record(source.id, "1".to_string())
+            .await
+            .unwrap()
+            .is_some());
+        assert!(storage
+            .get_record(comments.id, "1".to_string())
+            .await
+            .unwrap()
+            .is_none());
+        storage.delete_source(source.id).await.unwrap();
+        storage.delete_source(comments.id).await.unwrap();
+    }
 }
@@ -0,0 +1,122 @@
+use super::http::WEB;
+use super::schedule;
+use crate::models;
+use crate::result::Result;
+use crate::storage::Storage;
+use async_trait::async_trait;
+use http_collector::collector::{Cache, CacheEntry};
+
+/// Conditional requests cache of the http collector backed by the storage.
+///
+/// Collector sends `If-None-Match`/`If-Modified-Since` from the cached entry and skips the feed
+/// on `304 Not Modified` or if the body hash is the same, so unchanged feeds never reach
+/// the updates handler. `Retry-After` and `Cache-Control` of the responses postpone
+/// the source scrapes.
+///
+/// Entry of every response is set, including `304` and error responses, so the status
+/// of the last response is kept with the source health.
+pub(crate) struct StorageCache<S> {
+    storage: S,
+}
+
+impl<S> StorageCache<S>
+where
+    S: Storage + Send + Sync,
+{
+    pub fn new(storage: S) -> Self {
+        Self { storage }
+    }
+
+    async fn source(&self, link: &str) -> Option<models::Source> {
+        match self
+            .storage
+            .get_source_by_origin(WEB.to_string(), link.to_string())
+            .await
+        {
+            Ok(source) => source,
+            Err(e) => {
+                error!("{}", e);
+                None
+            }
+        }
+    }
+
+    /// Postpones next scrape of the source according to `Retry-After` and `Cache-Control: max-age`.
+    ///
+    /// `max-age` is ignored if scrape interval is set manually.
+    async fn postpone_scrape(&self, source: &models::Source, entry: &CacheEntry) -> Result<()> {
+        let max_age = match source.scrape_interval {
+            Some(_) => None,
+            None => entry.max_age,
+        };
+        let delay = match entry.retry_after.max(max_age) {
+            Some(delay) => delay,
+            None => return Ok(()),
+        };
+        let not_before = schedule::utc_now() + chrono::Duration::seconds(delay as i64);
+        if not_before > source.next_scrape_time {
+            debug!(
+                "scrape of {} is postponed until {}",
+                source.name, not_before
+            );
+            self.storage
+                .set_source_next_scrape_time(source.id, not_before)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl<S> Cache for StorageCache<S>
+where
+    S: Storage + Send + Sync,
+{
+    async fn get(&self, link: &str) -> Option<CacheEntry> {
+        let source = self.source(link).await?;
+        match self.storage.get_http_cache(source.id).await {
+            Ok(cache) => cache.map(|c| CacheEntry {
+                etag: c.etag,
+                last_modified: c.last_modified,
+                content_hash: c.content_hash,
+                ..Default::default()
+            }),
+            Err(e) => {
+                error!("{}", e);
+                None
+            }
+        }
+    }
+
+    async fn set(&self, link: &str, entry: CacheEntry) {
+        // feeds of unknown sources are cached after the source is created by the handler
+        let source = match self.source(link).await {
+            Some(source) => source,
+            None => return,
+        };
+        if let Err(e) = self.postpone_scrape(&source, &entry).await {
+            error!("{}", e);
+        }
+        if let Some(status) = entry.status {
+            if let Err(e) = self
+                .storage
+                .set_source_http_status(source.id, status as i32)
+                .await
+            {
+                error!("{}", e);
+            }
+        }
+        if let Err(e) = self
+            .storage
+            .save_http_cache(models::NewHttpCache {
+                source_id: source.id,
+                etag: entry.etag,
+                last_modified: entry.last_modified,
+                content_hash: entry.content_hash,
+            })
+            .await
+        {
+            error!("{}", e);
+        }
+    }
+}
@@ -0,0 +1,622 @@
+use super::{SourceData, SourceProvider, UpdatesHandler};
+use crate::config::SanitizeConfig;
+use crate::models;
+use crate::result::{Error, Result};
+use crate::sanitize::Sanitizer;
+use crate::storage::Storage;
+use crate::updates::Source;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use feed_rs::model::Link;
+use pulldown_cmark::{html, Options, Parser};
+use scraper::{Html, Selector};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::Duration;
+use url::Url;
+
+pub(crate) const LOCAL: &str = "LOCAL";
+
+/// Files with these extensions are parsed as RSS, Atom or JSON feeds
+const FEED_EXTENSIONS: &[&str] = &["xml", "rss", "atom", "json"];
+/// Files with these extensions are records of the directory source
+const DOCUMENT_EXTENSIONS: &[&str] = &["md", "markdown", "html", "htm"];
+/// Number of items in the source candidate preview
+const SAMPLE_ITEMS: usize = 3;
+
+lazy_static! {
+    static ref TITLE: Selector = Selector::parse("title, h1").unwrap();
+    static ref BODY: Selector = Selector::parse("body").unwrap();
+}
+
+/// Items of the feed file or documents of the directory
+#[derive(Debug)]
+pub struct LocalUpdate {
+    /// Canonical path of the feed file or of the documents directory
+    pub path: String,
+    pub name: String,
+    pub image: Option<String>,
+    /// Site link of the feed
+    pub link: Option<String>,
+    pub items: Vec<LocalItem>,
+}
+
+#[derive(Debug)]
+pub struct LocalItem {
+    /// Entry id of the feed or file name of the document
+    pub guid: String,
+    pub title: Option<String>,
+    pub content: String,
+    pub date: NaiveDateTime,
+    pub link: Option<String>,
+}
+
+pub struct LocalSourceBuilder<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    directories: Vec<String>,
+    poll_secs_interval: u64,
+    sanitize: SanitizeConfig,
+    storage: Option<S>,
+}
+
+impl<S> Default for LocalSourceBuilder<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    fn default() -> Self {
+        Self {
+            directories: vec![],
+            poll_secs_interval: 10,
+            sanitize: SanitizeConfig::default(),
+            storage: None,
+        }
+    }
+}
+
+impl<S> LocalSourceBuilder<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    pub fn with_directories(mut self, directories: &[String]) -> Self {
+        self.directories = directories.to_vec();
+        self
+    }
+
+    pub fn with_poll_secs_interval(mut self, poll_secs_interval: u64) -> Self {
+        self.poll_secs_interval = poll_secs_interval;
+        self
+    }
+
+    pub fn with_sanitize(mut self, sanitize: &SanitizeConfig) -> Self {
+        self.sanitize = sanitize.clone();
+        self
+    }
+
+    pub fn with_storage(mut self, storage: S) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    pub fn build(self) -> LocalSource<S> {
+        if self.storage.is_none() {
+            panic!("storage not passed");
+        }
+        LocalSource {
+            directories: self.directories.iter().map(PathBuf::from).collect(),
+            poll_interval: Duration::from_secs(self.poll_secs_interval),
+            sanitizer: Sanitizer::new(&self.sanitize),
+            storage: self.storage.unwrap(),
+        }
+    }
+}
+
+/// Watches local directories: feed files are sources, documents are records of the directory
+pub struct LocalSource<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    directories: Vec<PathBuf>,
+    poll_interval: Duration,
+    sanitizer: Sanitizer,
+    storage: S,
+}
+
+impl<S> LocalSource<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    pub fn builder() -> LocalSourceBuilder<S> {
+        LocalSourceBuilder::default()
+    }
+
+    /// Whether the path is a watched directory or a file in it
+    async fn is_watched(&self, path: &Path) -> bool {
+        for directory in &self.directories {
+            if let Ok(directory) = tokio::fs::canonicalize(directory).await {
+                if path == directory || path.parent() == Some(directory.as_path()) {
+                    return true;
+                }
+            }
+        }
+        false
+    }
+
+    /// Reads the source at the path, `None` if it's not a watched source
+    async fn read_source(&self, path: &str) -> Result<Option<LocalUpdate>> {
+        let path = match tokio::fs::canonicalize(path).await {
+            Ok(path) => path,
+            Err(_) => return Ok(None),
+        };
+        if !self.is_watched(&path).await {
+            return Ok(None);
+        }
+        if tokio::fs::metadata(&path).await?.is_dir() {
+            let origin = path_string(&path);
+            return Ok(read_directory(&path, &mut HashMap::new())
+                .await?
+                .into_iter()
+                .find(|u| u.path == origin));
+        }
+        match FEED_EXTENSIONS.contains(&extension(&path).as_str()) {
+            true => read_feed(&path).await.map(Some),
+            false => Ok(None),
+        }
+    }
+}
+
+#[async_trait]
+impl<S> UpdatesHandler<LocalUpdate> for LocalSource<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    async fn create_source(&self, updates: &LocalUpdate) -> Result<models::Source> {
+        let new_source = models::NewSource {
+            name: updates.name.clone(),
+            origin: updates.path.clone(),
+            external_link: updates
+                .link
+                .clone()
+                .or_else(|| file_url(Path::new(&updates.path)))
+                .unwrap_or_else(|| updates.path.clone()),
+            kind: LOCAL.to_string(),
+            image: updates.image.clone(),
+            account: None,
+            feed_kind: None,
+        };
+        Ok(self
+            .storage
+            .save_sources(vec![new_source])
+            .await?
+            .pop()
+            .unwrap())
+    }
+
+    async fn process_updates(&self, updates: &LocalUpdate) -> Result<usize> {
+        let source = match self
+            .storage
+            .get_source_by_origin(LOCAL.to_string(), updates.path.clone())
+            .await?
+        {
+            Some(source) => source,
+            None => self.create_source(updates).await?,
+        };
+        if source.state != models::SOURCE_ACTIVE {
+            debug!("skip updates of {} source {}", source.state, source.origin);
+            return Ok(0);
+        }
+        let affected = self
+            .storage
+            .save_records(
+                updates
+                    .items
+                    .iter()
+                    .map(|i| models::NewRecord {
+                        date: Some(i.date),
+                        title: i.title.clone(),
+                        source_record_id: i.guid.clone(),
+                        source_id: source.id,
+                        content: self.sanitizer.clean(&i.content, updates.link.as_deref()),
+                        external_link: i.link.clone(),
+                        ..Default::default()
+                    })
+                    .collect::<Vec<models::NewRecord>>(),
+            )
+            .await?;
+        self.storage.set_source_scraped_now(source).await?;
+        Ok(affected.len())
+    }
+}
+
+#[async_trait]
+impl<S> SourceProvider for LocalSource<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    fn get_source(&self) -> Source {
+        Source::Local
+    }
+
+    async fn run(&self, updates_sender: Arc<Mutex<mpsc::Sender<Result<SourceData>>>>) {
+        let directories = self.directories.clone();
+        let poll_interval = self.poll_interval;
+        tokio::spawn(async move { watch(directories, poll_interval, updates_sender).await });
+    }
+
+    async fn discover_sources(&self, query: &str) -> Result<Vec<models::SourceCandidate>> {
+        let update = match self.read_source(query).await? {
+            Some(update) => update,
+            None => return Ok(vec![]),
+        };
+        Ok(vec![models::SourceCandidate {
+            kind: LOCAL.to_string(),
+            external_link: update
+                .link
+                .clone()
+                .or_else(|| file_url(Path::new(&update.path)))
+                .unwrap_or_else(|| update.path.clone()),
+            origin: update.path,
+            name: update.name,
+            description: None,
+            image: update.image,
+            account: None,
+            sample_items: update
+                .items
+                .into_iter()
+                .take(SAMPLE_ITEMS)
+                .map(|i| models::SampleItem {
+                    title: i.title,
+                    content: i.content,
+                    date: Some(i.date),
+                })
+                .collect(),
+        }])
+    }
+
+    async fn subscribe(&self, candidate: &models::SourceCandidate) -> Result<models::Source> {
+        // source is created by `process_updates` with the current items
+        let update = self
+            .read_source(&candidate.origin)
+            .await?
+            .ok_or(Error::SourceNotFound)?;
+        self.process_updates(&update).await?;
+        self.storage
+            .get_source_by_origin(LOCAL.to_string(), update.path)
+            .await?
+            .ok_or(Error::SourceNotFound)
+    }
+
+    /// Reads all files of the directories, including not modified ones
+    async fn synchronize(&self, _secs_depth: i32) -> Result<()> {
+        for directory in &self.directories {
+            for update in read_directory(directory, &mut HashMap::new()).await? {
+                self.process_updates(&update).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn join(&self, _source: &models::Source) -> Result<()> {
+        // local sources are read while active
+        Ok(())
+    }
+
+    async fn leave(&self, _source: &models::Source) -> Result<()> {
+        Ok(())
+    }
+
+    async fn remove_files(&self, _source: &models::Source, _files: &[models::File]) -> Result<()> {
+        // nothing is downloaded for local sources
+        Ok(())
+    }
+}
+
+/// Sends updates of the new and modified files of the directories
+async fn watch(
+    directories: Vec<PathBuf>,
+    poll_interval: Duration,
+    updates_sender: Arc<Mutex<mpsc::Sender<Result<SourceData>>>>,
+) {
+    let mut modified = HashMap::new();
+    loop {
+        for directory in &directories {
+            match read_directory(directory, &mut modified).await {
+                Ok(updates) => {
+                    for update in updates {
+                        if let Err(e) = updates_sender
+                            .lock()
+                            .await
+                            .send(Ok(SourceData::Local(update)))
+                            .await
+                        {
+                            error!("{}", e);
+                        }
+                    }
+                }
+                Err(e) => error!("can't read directory {}: {}", directory.display(), e),
+            }
+        }
+        tokio::time::delay_for(poll_interval).await;
+    }
+}
+
+/// Reads feeds and documents of the directory, files modified at the time in `modified`
+/// are skipped and read files are added to it
+async fn read_directory(
+    directory: &Path,
+    modified: &mut HashMap<PathBuf, SystemTime>,
+) -> Result<Vec<LocalUpdate>> {
+    let directory = tokio::fs::canonicalize(directory).await?;
+    let mut entries = tokio::fs::read_dir(&directory).await?;
+    let mut updates = vec![];
+    let mut documents = vec![];
+    while let Some(entry) = entries.next_entry().await? {
+        let path = entry.path();
+        let metadata = entry.metadata().await?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let file_modified = metadata.modified()?;
+        if modified.get(&path) == Some(&file_modified) {
+            continue;
+        }
+        let extension = extension(&path);
+        let read = if FEED_EXTENSIONS.contains(&extension.as_str()) {
+            read_feed(&path).await.map(|u| updates.push(u))
+        } else if DOCUMENT_EXTENSIONS.contains(&extension.as_str()) {
+            read_document(&path).await.map(|i| documents.push(i))
+        } else {
+            continue;
+        };
+        match read {
+            Ok(()) => {
+                modified.insert(path, file_modified);
+            }
+            // file may be written yet, it's read again on the next check
+            Err(e) => warn!("can't read {}: {}", path.display(), e),
+        }
+    }
+    if !documents.is_empty() {
+        updates.push(LocalUpdate {
+            path: path_string(&directory),
+            name: file_name(&directory),
+            image: None,
+            link: None,
+            items: documents,
+        });
+    }
+    Ok(updates)
+}
+
+async fn read_feed(path: &Path) -> Result<LocalUpdate> {
+    let modified = tokio::fs::metadata(path).await?.modified()?;
+    let content = tokio::fs::read(path).await?;
+    parse_feed(path, &content, utc(modified))
+}
+
+async fn read_document(path: &Path) -> Result<LocalItem> {
+    let modified = tokio::fs::metadata(path).await?.modified()?;
+    let content = tokio::fs::read_to_string(path).await?;
+    Ok(parse_document(path, &content, utc(modified)))
+}
+
+/// Parses RSS, Atom or JSON feed, entries without a date get the file modification time
+fn parse_feed(path: &Path, content: &[u8], modified: NaiveDateTime) -> Result<LocalUpdate> {
+    let feed = feed_rs::parser::parse(content).map_err(|e| Error::InvalidContent(e.to_string()))?;
+    Ok(LocalUpdate {
+        path: path_string(path),
+        name: feed
+            .title
+            .map(|t| t.content)
+            .unwrap_or_else(|| file_stem(path)),
+        image: feed.logo.or(feed.icon).map(|i| i.uri),
+        link: alternate_link(&feed.links),
+        items: feed
+            .entries
+            .into_iter()
+            .map(|e| LocalItem {
+                link: alternate_link(&e.links),
+                guid: e.id,
+                title: e.title.map(|t| t.content),
+                content: e
+                    .content
+                    .and_then(|c| c.body)
+                    .or_else(|| e.summary.map(|s| s.content))
+                    .unwrap_or_default(),
+                date: e
+                    .published
+                    .or(e.updated)
+                    .map(|d| d.naive_utc())
+                    .unwrap_or(modified),
+            })
+            .collect(),
+    })
+}
+
+/// Renders Markdown or takes body of HTML document, title is the first heading
+fn parse_document(path: &Path, content: &str, modified: NaiveDateTime) -> LocalItem {
+    let (title, content) = match extension(path).as_str() {
+        "md" | "markdown" => {
+            let title = content
+                .lines()
+                .find_map(|l| l.trim().strip_prefix("# "))
+                .map(|t| t.trim().to_string());
+            let mut rendered = String::new();
+            html::push_html(&mut rendered, Parser::new_ext(content, Options::all()));
+            (title, rendered)
+        }
+        _ => {
+            let document = Html::parse_document(content);
+            let title = document
+                .select(&TITLE)
+                .map(|t| t.text().collect::<String>().trim().to_string())
+                .find(|t| !t.is_empty());
+            let body = document
+                .select(&BODY)
+                .next()
+                .map(|b| b.inner_html())
+                .unwrap_or_else(|| content.to_string());
+            (title, body.trim().to_string())
+        }
+    };
+    LocalItem {
+        guid: file_name(path),
+        title: title.or_else(|| Some(file_stem(path))),
+        content,
+        date: modified,
+        link: None,
+    }
+}
+
+fn alternate_link(links: &[Link]) -> Option<String> {
+    links
+        .iter()
+        .find(|l| l.rel.as_deref().unwrap_or("alternate") == "alternate")
+        .map(|l| l.href.clone())
+}
+
+fn utc(time: SystemTime) -> NaiveDateTime {
+    DateTime::<Utc>::from(time).naive_utc()
+}
+
+fn file_url(path: &Path) -> Option<String> {
+    Url::from_file_path(path).ok().map(|u| u.to_string())
+}
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().to_string()
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| path_string(path))
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| path_string(path))
+}
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_document, parse_feed};
+    use chrono::NaiveDate;
+    use std::path::Path;
+
+    #[test]
+    fn test_parse_feed() {
+        let rss = r#"<?xml version="1.0"?>
+            <rss version="2.0"><channel>
+                <title>Builds</title>
+                <link>https://ci.example.com/</link>
+                <item>
+                    <guid>build-1</guid>
+                    <title>Build 1 passed</title>
+                    <link>https://ci.example.com/builds/1</link>
+                    <description>All tests passed</description>
+                    <pubDate>Mon, 18 Jan 2021 10:00:00 GMT</pubDate>
+                </item>
+                <item><guid>build-2</guid><title>Build 2 failed</title></item>
+            </channel></rss>"#;
+        let modified = NaiveDate::from_ymd(2021, 1, 19).and_hms(0, 0, 0);
+        let update = parse_feed(Path::new("/feeds/builds.xml"), rss.as_bytes(), modified).unwrap();
+        assert_eq!(update.path, "/feeds/builds.xml");
+        assert_eq!(update.name, "Builds");
+        assert_eq!(update.link.as_deref(), Some("https://ci.example.com/"));
+        assert_eq!(update.items.len(), 2);
+        let item = &update.items[0];
+        assert_eq!(item.guid, "build-1");
+        assert_eq!(item.title.as_deref(), Some("Build 1 passed"));
+        assert_eq!(item.content, "All tests passed");
+        assert_eq!(
+            item.link.as_deref(),
+            Some("https://ci.example.com/builds/1")
+        );
+        assert_eq!(
+            item.date,
+            NaiveDate::from_ymd(2021, 1, 18).and_hms(10, 0, 0)
+        );
+        assert_eq!(update.items[1].date, modified);
+        assert!(parse_feed(Path::new("/feeds/broken.xml"), b"<rss>", modified).is_err());
+    }
+
+    #[test]
+    fn test_parse_document() {
+        let modified = NaiveDate::from_ymd(2021, 1, 19).and_hms(0, 0, 0);
+        let item = parse_document(
+            Path::new("/docs/release.md"),
+            "# Release 1.2\n\nFixed *everything*.\n",
+            modified,
+        );
+        assert_eq!(item.guid, "release.md");
+        assert_eq!(item.title.as_deref(), Some("Release 1.2"));
+        assert!(item.content.contains("<p>Fixed <em>everything</em>.</p>"));
+        assert_eq!(item.date, modified);
+
+        let item = parse_document(
+            Path::new("/docs/notes.html"),
+            "<html><head><title>Notes</title></head><body><p>Text</p></body></html>",
+            modified,
+        );
+        assert_eq!(item.title.as_deref(), Some("Notes"));
+        assert_eq!(item.content, "<p>Text</p>");
+
+        let item = parse_document(Path::new("/docs/todo.md"), "- item\n", modified);
+        assert_eq!(item.title.as_deref(), Some("todo"));
+    }
+
+    #[cfg(feature = "pg-storage")]
+    #[tokio::test]
+    #[ignore]
+    async fn test_modified_document_updates_record() {
+        use super::{LocalSource, LOCAL};
+        use crate::storage::pg::tests::test_storage;
+        use crate::storage::Storage;
+        use crate::updates::SourceProvider;
+
+        let storage = test_storage();
+        let directory = std::env::temp_dir().join(format!("agg-r-local-{}", rand::random::<u64>()));
+        tokio::fs::create_dir_all(&directory).await.unwrap();
+        let local = LocalSource::builder()
+            .with_directories(&[directory.to_string_lossy().to_string()])
+            .with_storage(storage.clone())
+            .build();
+        let document = directory.join("note.md");
+        tokio::fs::write(&document, "# Note\n\nfirst\n")
+            .await
+            .unwrap();
+        local.synchronize(0).await.unwrap();
+        tokio::fs::write(&document, "# Note\n\nsecond\n")
+            .await
+            .unwrap();
+        local.synchronize(0).await.unwrap();
+
+        let origin = tokio::fs::canonicalize(&directory).await.unwrap();
+        let source = storage
+            .get_source_by_origin(LOCAL.to_string(), origin.to_string_lossy().to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        let record = storage
+            .get_record(source.id, "note.md".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(record.content.contains("second"));
+        assert!(!record.content.contains("first"));
+        storage.delete_source(source.id).await.unwrap();
+        tokio::fs::remove_dir_all(&directory).await.unwrap();
+    }
+}
@@ -0,0 +1,319 @@
+use crate::config::MediaCacheConfig;
+use crate::file_store::{self, FileStore};
+use crate::models;
+use crate::result::{Error, Result};
+use crate::storage::Storage;
+use crate::tools::resolve_link;
+use regex::{Captures, Regex};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+use url::Url;
+
+lazy_static! {
+    static ref IMG_SRC: Regex =
+        Regex::new(r#"(?i)<img\s[^>]*?\bsrc\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#).unwrap();
+}
+
+/// Downloads images of the web records to the file store, so records keep working
+/// after the origin deletes them
+pub(crate) struct MediaCache {
+    client: reqwest::Client,
+    file_store: Arc<dyn FileStore + Send + Sync>,
+    config: MediaCacheConfig,
+}
+
+impl MediaCache {
+    pub fn new(config: &MediaCacheConfig, file_store: Arc<dyn FileStore + Send + Sync>) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            file_store,
+            config: config.clone(),
+        }
+    }
+
+    /// Caches record image and images referenced in the record content.
+    ///
+    /// Relative links are resolved against `base_url`. Failed downloads are saved with
+    /// `DOWNLOAD_FAILED` state and original links are kept in the content. Links are replaced
+    /// with the public urls of the cached files if `rewrite_content` is enabled.
+    pub async fn cache_record<S: Storage + Send + Sync>(
+        &self,
+        storage: &S,
+        record: &models::Record,
+        base_url: &str,
+        kind: &str,
+    ) -> Result<()> {
+        let mut links = vec![];
+        if let Some(image) = &record.image {
+            links.push(image.clone());
+        }
+        for link in image_links(&record.content) {
+            if !links.contains(&link) {
+                links.push(link);
+            }
+        }
+
+        let public_url = match (self.config.rewrite_content, &self.config.public_url) {
+            (true, Some(public_url)) => Some(public_url),
+            (true, None) => {
+                warn!("media cache content isn't rewritten: public url isn't set");
+                None
+            }
+            (false, _) => None,
+        };
+        let mut cached = HashMap::new();
+        for link in links {
+            let url = match resolve_link(base_url, &link.replace("&amp;", "&")) {
+                Some(url) => url,
+                None => {
+                    debug!("skip invalid image link {}", link);
+                    continue;
+                }
+            };
+            let local_path = match self.cache_file(storage, record.id, &url, kind).await {
+                Ok(local_path) => local_path,
+                Err(e) => {
+                    warn!("image {} of record {} not cached: {}", url, record.id, e);
+                    continue;
+                }
+            };
+            match public_url.map(|p| p.url(&local_path)) {
+                Some(Some(url)) => {
+                    cached.insert(link, url);
+                }
+                Some(None) => warn!("{} isn't under the public url location prefix", local_path),
+                None => {}
+            }
+        }
+        if cached.is_empty() {
+            return Ok(());
+        }
+        let content = rewrite_image_links(&record.content, &cached);
+        if content != record.content {
+            // source content is kept, so unchanged records aren't updated on the next scrape
+            storage
+                .set_record_cached_content(record.id, content)
+                .await?;
+        }
+        let image = record
+            .image
+            .as_ref()
+            .map(|image| cached.get(image).unwrap_or(image).clone());
+        if image != record.image {
+            storage.set_record_image(record.id, image).await?;
+        }
+        Ok(())
+    }
+
+    /// Removes cached files which aren't referenced by other records
+    pub async fn remove_files<S: Storage + Send + Sync>(
+        &self,
+        storage: &S,
+        files: &[models::File],
+    ) -> Result<()> {
+        let locations = files.iter().filter_map(|f| f.local_path.clone()).collect();
+        file_store::remove_unreferenced(storage, self.file_store.as_ref(), locations).await
+    }
+
+    /// Returns location of the cached file, downloads it if it's not cached yet
+    async fn cache_file<S: Storage + Send + Sync>(
+        &self,
+        storage: &S,
+        record_id: i32,
+        url: &Url,
+        kind: &str,
+    ) -> Result<String> {
+        let remote_id = format!("{}_{}", record_id, url);
+        if let Some(file) = storage.get_file_by_remote_id(remote_id.clone()).await? {
+            if let (models::DOWNLOAD_DONE, Some(local_path)) =
+                (file.download_state.as_str(), file.local_path)
+            {
+                return Ok(local_path);
+            }
+        }
+        let file_name = url
+            .path_segments()
+            .and_then(|s| s.last())
+            .filter(|s| !s.is_empty())
+            .unwrap_or("image")
+            .to_string();
+        let downloaded = self.download(url, &file_name).await;
+        let (local_path, download_state) = match &downloaded {
+            Ok(local_path) => (Some(local_path.clone()), models::DOWNLOAD_DONE),
+            Err(_) => (None, models::DOWNLOAD_FAILED),
+        };
+        storage
+            .save_files(vec![models::NewFile {
+                record_id,
+                kind: kind.to_string(),
+                local_path,
+                remote_path: url.to_string(),
+                remote_id: Some(remote_id),
+                file_name: Some(file_name),
+                type_: "IMAGE".to_string(),
+                meta: None,
+                skipped: None,
+                download_state: download_state.to_string(),
+                original_id: None,
+            }])
+            .await?;
+        downloaded
+    }
+
+    /// Streams the file to a temp file and moves it to the file store, download is aborted
+    /// as soon as the file exceeds `max_file_size`
+    async fn download(&self, url: &Url, file_name: &str) -> Result<String> {
+        let mut response = self
+            .client
+            .get(url.clone())
+            .send()
+            .await
+            .and_then(|r| r.error_for_status())
+            .map_err(|e| Error::FileStoreError(e.to_string()))?;
+        if let (Some(max_file_size), Some(length)) =
+            (self.config.max_file_size, response.content_length())
+        {
+            if length as i64 > max_file_size {
+                return Err(Error::FileStoreError(format!(
+                    "file size exceeds {} bytes",
+                    max_file_size
+                )));
+            }
+        }
+        // removed on drop unless the file store moved it
+        let temp_file = tempfile::NamedTempFile::new()?;
+        let mut file = tokio::fs::File::from_std(temp_file.reopen()?);
+        let mut size = 0;
+        while let Some(chunk) = response
+            .chunk()
+            .await
+            .map_err(|e| Error::FileStoreError(e.to_string()))?
+        {
+            size += chunk.len() as i64;
+            if let Some(max_file_size) = self.config.max_file_size {
+                if size > max_file_size {
+                    return Err(Error::FileStoreError(format!(
+                        "file size exceeds {} bytes",
+                        max_file_size
+                    )));
+                }
+            }
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        drop(file);
+        self.file_store.put(temp_file.path(), file_name).await
+    }
+}
+
+/// Returns `src` of the images in HTML content as is, i.e. with escaped entities
+pub(crate) fn image_links(content: &str) -> Vec<String> {
+    IMG_SRC
+        .captures_iter(content)
+        .filter_map(|c| c.get(1).or_else(|| c.get(2)).or_else(|| c.get(3)))
+        .map(|m| m.as_str().to_string())
+        .filter(|l| !l.is_empty() && !l.starts_with("data:"))
+        .collect()
+}
+
+/// Replaces `src` of the images found by `image_links` with the urls of the cached files
+fn rewrite_image_links(content: &str, urls: &HashMap<String, String>) -> String {
+    IMG_SRC
+        .replace_all(content, |c: &Captures| {
+            let tag = c.get(0).unwrap();
+            let src = match c.get(1).or_else(|| c.get(2)).or_else(|| c.get(3)) {
+                Some(src) => src,
+                None => return tag.as_str().to_string(),
+            };
+            match urls.get(src.as_str()) {
+                Some(url) => format!(
+                    "{}{}{}",
+                    &content[tag.start()..src.start()],
+                    url.replace('&', "&amp;")
+                        .replace('"', "&quot;")
+                        .replace('\'', "&#39;"),
+                    &content[src.end()..tag.end()]
+                ),
+                None => tag.as_str().to_string(),
+            }
+        })
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{image_links, rewrite_image_links, MediaCache};
+    use crate::config::MediaCacheConfig;
+    use crate::file_store::{FileStore, LocalFileStore};
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, Response, Server};
+    use std::convert::Infallible;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_image_links() {
+        let content = r#"<p>text <img src="https://example.com/a.png" alt="a"></p>
+            <IMG class=x SRC='/b.jpg'/><img alt="c" src=c.gif>
+            <img src="data:image/png;base64,AAAA"><img src="https://example.com/d?x=1&amp;y=2">"#;
+        assert_eq!(
+            image_links(content),
+            vec![
+                "https://example.com/a.png",
+                "/b.jpg",
+                "c.gif",
+                "https://example.com/d?x=1&amp;y=2"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rewrite_image_links() {
+        let content = r#"<p>see a.png <img src="a.png" alt="a.png"><img src='ba.png'>
+            <img src=a.png><img src="/c?x=1&amp;y=2"></p>"#;
+        let urls = vec![
+            ("a.png", "https://cdn.example.com/1/a.png"),
+            ("/c?x=1&amp;y=2", "https://cdn.example.com/2/c?x=1&y=2"),
+        ]
+        .into_iter()
+        .map(|(link, url)| (link.to_string(), url.to_string()))
+        .collect();
+        assert_eq!(
+            rewrite_image_links(content, &urls),
+            r#"<p>see a.png <img src="https://cdn.example.com/1/a.png" alt="a.png"><img src='ba.png'>
+            <img src=https://cdn.example.com/1/a.png><img src="https://cdn.example.com/2/c?x=1&amp;y=2"></p>"#
+        );
+    }
+
+    #[tokio::test]
+    async fn test_download_size_limit() {
+        let make_service = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|_| async {
+                // chunked body without `Content-Length`, so the limit is checked while streaming
+                let chunks = (0..4).map(|_| Ok::<_, std::io::Error>(vec![b'x'; 256]));
+                Ok::<_, Infallible>(Response::new(Body::wrap_stream(futures::stream::iter(
+                    chunks,
+                ))))
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let url = url::Url::parse(&format!("http://{}/a.png", server.local_addr())).unwrap();
+        tokio::spawn(server);
+        let directory = std::env::temp_dir().join("agg-r-media-cache");
+        let file_store = Arc::new(LocalFileStore::new(directory.to_str().unwrap()));
+        let media_cache = |max_file_size| {
+            let config = MediaCacheConfig {
+                enabled: true,
+                rewrite_content: false,
+                max_file_size: Some(max_file_size),
+                public_url: None,
+            };
+            MediaCache::new(&config, file_store.clone())
+        };
+        assert!(media_cache(100).download(&url, "a.png").await.is_err());
+        let location = media_cache(1024).download(&url, "a.png").await.unwrap();
+        assert_eq!(tokio::fs::read(&location).await.unwrap().len(), 1024);
+        file_store.remove(&location).await.unwrap();
+        tokio::fs::remove_dir_all(&directory).await.unwrap();
+    }
+}
@@ -5,21 +5,42 @@ use async_trait::async_trait;
 use futures::future::join_all;
 use std::sync::Arc;
 use tokio::sync::mpsc::{Receiver, Sender};
-use tokio::sync::{mpsc, Mutex};
+use tokio::sync::{mpsc, watch, Mutex};
 
 pub mod http;
+pub mod local;
+// conditional requests cache of the http collector
+mod http_cache;
+// article extraction of the web records
+mod full_content;
+// caching of the web records images
+mod media_cache;
+// scrape scheduling of the web sources
+mod schedule;
 pub mod tg;
+pub mod webhook;
+// push subscriptions of the web feeds
+mod websub;
 
 #[derive(Debug)]
 pub enum SourceData {
     WebFeed(http::FeedUpdate),
-    Telegram(tg::TelegramUpdate),
+    /// Scrape of the web source with the link failed
+    WebError(String, Error),
+    /// Update received by the account
+    Telegram(String, tg::TelegramUpdate),
+    /// New or modified files of the watched directory
+    Local(local::LocalUpdate),
+    /// Items pushed to the webhook endpoint
+    Webhook(webhook::WebhookUpdate),
 }
 
 #[derive(Debug, PartialEq)]
 pub enum Source {
     Web,
     Telegram,
+    Local,
+    Webhook,
 }
 
 #[async_trait]
@@ -32,8 +53,18 @@ pub trait UpdatesHandler<T> {
 pub trait SourceProvider {
     fn get_source(&self) -> Source;
     async fn run(&self, updates_sender: Arc<Mutex<mpsc::Sender<Result<SourceData>>>>);
-    async fn search_source(&self, query: &str) -> Result<Vec<models::Source>>;
+    /// Finds sources by query without saving them
+    async fn discover_sources(&self, query: &str) -> Result<Vec<models::SourceCandidate>>;
+    /// Saves discovered source and starts receiving its updates
+    async fn subscribe(&self, candidate: &models::SourceCandidate) -> Result<models::Source>;
     async fn synchronize(&self, secs_depth: i32) -> Result<()>;
+    /// Starts receiving updates of the source again, e.g. joins Telegram chat
+    async fn join(&self, source: &models::Source) -> Result<()>;
+    /// Stops receiving updates of the source, e.g. leaves Telegram chat
+    async fn leave(&self, source: &models::Source) -> Result<()>;
+    /// Removes files of the deleted source from the file store: downloaded files and
+    /// the source image if it's stored. Files referenced by other sources are kept
+    async fn remove_files(&self, source: &models::Source, files: &[models::File]) -> Result<()>;
 }
 
 pub struct SourcesAggregator<S>
@@ -41,7 +72,9 @@ where
     S: Storage + Send + Sync + Clone + 'static,
 {
     http_source: Option<Arc<http::HttpSource<S>>>,
-    tg_source: Option<Arc<tg::TelegramSource<S>>>,
+    tg_sources: Vec<Arc<tg::TelegramSource<S>>>,
+    local_source: Option<Arc<local::LocalSource<S>>>,
+    webhook_source: Option<Arc<webhook::WebhookSource<S>>>,
     updates_sender: Arc<Mutex<Sender<Result<SourceData>>>>,
     updates_receiver: Mutex<Receiver<Result<SourceData>>>,
     storage: S,
@@ -90,26 +123,261 @@ where
         Ok(())
     }
 
+    /// Searches saved sources
     pub async fn search_source(&self, query: &str) -> Result<Vec<models::Source>> {
+        self.storage.search_source(query).await
+    }
+
+    /// Searches new sources with all enabled providers, nothing is saved
+    pub async fn discover_sources(&self, query: &str) -> Result<Vec<models::SourceCandidate>> {
         let source_providers = self.get_enabled_sources();
         let mut tasks = vec![];
         for provider in &source_providers {
-            tasks.push(provider.search_source(query))
+            tasks.push(provider.discover_sources(query))
         }
-        let mut results = vec![];
-        let tasks_results = join_all(tasks).await;
-        for task_result in tasks_results {
-            match task_result {
-                Ok(res) => results.extend(res),
-                Err(err) => return Err(err),
+        let mut results: Vec<models::SourceCandidate> = vec![];
+        for task_result in join_all(tasks).await {
+            for candidate in task_result? {
+                // several telegram accounts may find the same chat
+                if !results
+                    .iter()
+                    .any(|c| c.kind == candidate.kind && c.origin == candidate.origin)
+                {
+                    results.push(candidate);
+                }
             }
         }
-        results.extend(self.storage.search_source(query).await?);
-        // TODO: check: duplicates appears
-        results.dedup_by_key(|s| s.id);
         Ok(results)
     }
 
+    /// Saves candidate found by `discover_sources`; Telegram chat is joined by the account
+    /// which found it
+    pub async fn subscribe(&self, candidate: &models::SourceCandidate) -> Result<models::Source> {
+        self.provider(&candidate.kind, candidate.account.as_deref())?
+            .subscribe(candidate)
+            .await
+    }
+
+    pub async fn failed_downloads(&self) -> Result<Vec<models::File>> {
+        self.storage
+            .get_files_by_download_state(
+                tg::TELEGRAM.to_string(),
+                None,
+                models::DOWNLOAD_FAILED.to_string(),
+                &0,
+            )
+            .await
+    }
+
+    pub async fn requeue_download(&self, file_id: i32) -> Result<()> {
+        self.storage.requeue_file(file_id).await
+    }
+
+    /// Joins the Telegram chat of the source with the specified account,
+    /// the first configured account is used if not specified
+    pub async fn join_source(&self, source: &models::Source, account: Option<&str>) -> Result<()> {
+        if source.kind != tg::TELEGRAM {
+            return Err(Error::SourceKindConflict(format!(
+                "can't join {} source",
+                source.kind
+            )));
+        }
+        self.tg_source(account)?.join_source(source).await
+    }
+
+    pub fn auth_state(&self, account: Option<&str>) -> Result<tg::AuthState> {
+        Ok(self.tg_source(account)?.auth_state())
+    }
+
+    pub fn auth_state_stream(
+        &self,
+        account: Option<&str>,
+    ) -> Result<watch::Receiver<tg::AuthState>> {
+        Ok(self.tg_source(account)?.auth_state_stream())
+    }
+
+    pub async fn submit_auth_code(&self, account: Option<&str>, code: &str) -> Result<()> {
+        self.tg_source(account)?.submit_auth_code(code).await
+    }
+
+    pub async fn submit_auth_password(&self, account: Option<&str>, password: &str) -> Result<()> {
+        self.tg_source(account)?
+            .submit_auth_password(password)
+            .await
+    }
+
+    pub async fn log_out(&self, account: Option<&str>) -> Result<()> {
+        self.tg_source(account)?.log_out().await
+    }
+
+    pub async fn restart_client(&self, account: Option<&str>) -> Result<()> {
+        self.tg_source(account)?.restart_client().await
+    }
+
+    /// Unsubscribes from the source; source is disabled or deleted with all its records and files
+    pub async fn remove_source(&self, source_id: i32, delete: bool) -> Result<()> {
+        let source = self.get_source(source_id).await?;
+        let provider = self.source_provider(&source)?;
+        provider.leave(&source).await?;
+        if !delete {
+            return self
+                .storage
+                .set_source_state(source.id, models::SOURCE_DISABLED.to_string())
+                .await;
+        }
+        let files = self.storage.get_source_files(source.id).await?;
+        // files are removed only when no rows point to them
+        self.storage.delete_source(source.id).await?;
+        provider.remove_files(&source, &files).await
+    }
+
+    /// Stops scraping of the source, data is kept
+    pub async fn pause_source(&self, source_id: i32) -> Result<()> {
+        let source = self.get_source(source_id).await?;
+        self.storage
+            .set_source_state(source.id, models::SOURCE_PAUSED.to_string())
+            .await
+    }
+
+    /// Resumes paused or disabled source, disabled sources are joined again
+    pub async fn resume_source(&self, source_id: i32) -> Result<()> {
+        let source = self.get_source(source_id).await?;
+        if source.state == models::SOURCE_DISABLED {
+            self.source_provider(&source)?.join(&source).await?;
+            // source may be disabled by failures, it gets a new chance
+            self.storage.reset_source_failures(source.id).await?;
+        }
+        self.storage
+            .set_source_state(source.id, models::SOURCE_ACTIVE.to_string())
+            .await
+    }
+
+    /// Returns scrape results of the web source, `None` if it wasn't scraped yet
+    pub async fn source_health(&self, source_id: i32) -> Result<Option<models::SourceHealth>> {
+        self.storage.get_source_health(source_id).await
+    }
+
+    /// Returns health of the web sources which failed at least `min_failures` times in a row
+    pub async fn failing_sources(&self, min_failures: i32) -> Result<Vec<models::SourceHealth>> {
+        self.storage
+            .get_failing_sources_health(http::WEB.to_string(), min_failures)
+            .await
+    }
+
+    /// Sets scrape interval of the web source in seconds, adaptive interval is used if `None`
+    pub async fn set_scrape_interval(&self, source_id: i32, interval: Option<i32>) -> Result<()> {
+        if let Some(interval) = interval.filter(|i| *i <= 0) {
+            return Err(Error::InvalidArgument(format!(
+                "scrape interval must be positive: {}",
+                interval
+            )));
+        }
+        let source = self.get_web_source(source_id).await?;
+        self.storage
+            .set_source_scrape_interval(source.id, interval)
+            .await?;
+        // source is scraped right away and rescheduled with the new interval
+        self.storage
+            .set_source_next_scrape_time(source.id, schedule::utc_now())
+            .await
+    }
+
+    /// Enables fetching of the full articles for new records of the web source
+    pub async fn set_fetch_full_content(&self, source_id: i32, enabled: bool) -> Result<()> {
+        let source = self.get_web_source(source_id).await?;
+        self.storage
+            .set_source_fetch_full_content(source.id, enabled)
+            .await
+    }
+
+    /// Adds source which records are pushed to its endpoint, returns it with the token
+    /// to push with, the token is shown only once
+    pub async fn add_webhook_source(&self, name: &str) -> Result<(models::Source, String)> {
+        self.webhook_source()?.add_source(name).await
+    }
+
+    /// Issues new token of the webhook source, the previous one stops working
+    pub async fn reset_webhook_token(&self, source_id: i32) -> Result<String> {
+        let source = self.get_source(source_id).await?;
+        if source.kind != webhook::WEBHOOK {
+            return Err(Error::SourceKindConflict(format!(
+                "{} source has no token",
+                source.kind
+            )));
+        }
+        self.webhook_source()?.reset_token(&source).await
+    }
+
+    fn webhook_source(&self) -> Result<&Arc<webhook::WebhookSource<S>>> {
+        self.webhook_source
+            .as_ref()
+            .ok_or_else(|| Error::SourceKindConflict("webhook source disabled".to_string()))
+    }
+
+    async fn get_web_source(&self, source_id: i32) -> Result<models::Source> {
+        let source = self.get_source(source_id).await?;
+        if source.kind != http::WEB {
+            return Err(Error::SourceKindConflict(format!(
+                "{} source isn't scraped",
+                source.kind
+            )));
+        }
+        Ok(source)
+    }
+
+    async fn get_source(&self, source_id: i32) -> Result<models::Source> {
+        self.storage
+            .get_source(source_id)
+            .await?
+            .ok_or(Error::SourceNotFound)
+    }
+
+    fn source_provider(&self, source: &models::Source) -> Result<Arc<dyn SourceProvider>> {
+        self.provider(&source.kind, source.account.as_deref())
+    }
+
+    /// Returns provider of the source kind; telegram account is chosen by `account`
+    fn provider(&self, kind: &str, account: Option<&str>) -> Result<Arc<dyn SourceProvider>> {
+        let provider: Arc<dyn SourceProvider> = match kind {
+            tg::TELEGRAM => self.tg_source(account)?.clone(),
+            http::WEB => match &self.http_source {
+                Some(http_source) => http_source.clone(),
+                None => {
+                    return Err(Error::SourceKindConflict(
+                        "http source disabled".to_string(),
+                    ))
+                }
+            },
+            local::LOCAL => match &self.local_source {
+                Some(local_source) => local_source.clone(),
+                None => {
+                    return Err(Error::SourceKindConflict(
+                        "local source disabled".to_string(),
+                    ))
+                }
+            },
+            webhook::WEBHOOK => self.webhook_source()?.clone(),
+            kind => {
+                return Err(Error::SourceKindConflict(format!(
+                    "unknown source kind {}",
+                    kind
+                )))
+            }
+        };
+        Ok(provider)
+    }
+
+    /// Returns telegram source of the account, the first one if account isn't specified
+    fn tg_source(&self, account: Option<&str>) -> Result<&Arc<tg::TelegramSource<S>>> {
+        let tg_source = match account {
+            None => self.tg_sources.first(),
+            Some(account) => self.tg_sources.iter().find(|s| s.account() == account),
+        };
+        tg_source.ok_or_else(|| {
+            Error::SourceKindConflict(format!("telegram account {:?} not enabled", account))
+        })
+    }
+
     fn get_enabled_sources(&self) -> Vec<Arc<dyn SourceProvider>> {
         let mut enabled: Vec<Arc<dyn SourceProvider>> = vec![];
         macro_rules! push_if_enabled {
@@ -121,7 +389,11 @@ where
             };
         }
         push_if_enabled!(self.http_source);
-        push_if_enabled!(self.tg_source);
+        push_if_enabled!(self.local_source);
+        push_if_enabled!(self.webhook_source);
+        for tg_source in &self.tg_sources {
+            enabled.push(tg_source.clone());
+        }
         enabled
     }
 
@@ -138,8 +410,25 @@ where
                 }
             };
         }
-        run_source!(self.tg_source);
+        // sources saved before accounts were tracked belong to the main account,
+        // otherwise no account downloads their files
+        if let Some(main) = self.tg_sources.first() {
+            match self
+                .storage
+                .set_unowned_sources_account(tg::TELEGRAM.to_string(), main.account().to_string())
+                .await
+            {
+                Ok(0) => {}
+                Ok(claimed) => info!("{} sources assigned to {}", claimed, main.account()),
+                Err(e) => error!("{}", e),
+            }
+        }
+        for tg_source in &self.tg_sources {
+            tg_source.run(self.updates_sender.clone()).await;
+        }
         run_source!(self.http_source);
+        run_source!(self.local_source);
+        run_source!(self.webhook_source);
         self.process_updates().await;
     }
 
@@ -156,12 +445,35 @@ where
                             }
                             Some(source) => source.process_updates(feed_data).await,
                         },
-                        SourceData::Telegram(telegram_update) => match &self.tg_source {
+                        SourceData::WebError(link, err) => match &self.http_source {
                             None => {
                                 debug!("http source disabled");
                                 Ok(0)
                             }
-                            Some(source) => source.process_updates(telegram_update).await,
+                            Some(source) => source.process_failure(link, err).await.map(|_| 0),
+                        },
+                        SourceData::Telegram(account, telegram_update) => {
+                            match self.tg_sources.iter().find(|s| s.account() == account) {
+                                None => {
+                                    debug!("telegram account {} disabled", account);
+                                    Ok(0)
+                                }
+                                Some(source) => source.process_updates(telegram_update).await,
+                            }
+                        }
+                        SourceData::Local(local_update) => match &self.local_source {
+                            None => {
+                                debug!("local source disabled");
+                                Ok(0)
+                            }
+                            Some(source) => source.process_updates(local_update).await,
+                        },
+                        SourceData::Webhook(webhook_update) => match &self.webhook_source {
+                            None => {
+                                debug!("webhook source disabled");
+                                Ok(0)
+                            }
+                            Some(source) => source.process_updates(webhook_update).await,
                         },
                     },
                     Err(err) => Err(Error::DbError(err.to_string())),
@@ -185,7 +497,9 @@ where
     S: Storage + Send + Sync + Clone + 'static,
 {
     http_source: Option<Arc<http::HttpSource<S>>>,
-    tg_source: Option<Arc<tg::TelegramSource<S>>>,
+    tg_sources: Vec<Arc<tg::TelegramSource<S>>>,
+    local_source: Option<Arc<local::LocalSource<S>>>,
+    webhook_source: Option<Arc<webhook::WebhookSource<S>>>,
     storage: Option<S>,
 }
 
@@ -196,7 +510,9 @@ where
     fn default() -> Self {
         Self {
             http_source: None,
-            tg_source: None,
+            tg_sources: vec![],
+            local_source: None,
+            webhook_source: None,
             storage: None,
         }
     }
@@ -215,8 +531,19 @@ where
         self
     }
 
+    /// Adds telegram account, may be called several times
     pub fn with_tg_source(mut self, tg_source: Arc<tg::TelegramSource<S>>) -> Self {
-        self.tg_source = Some(tg_source);
+        self.tg_sources.push(tg_source);
+        self
+    }
+
+    pub fn with_local_source(mut self, local_source: Arc<local::LocalSource<S>>) -> Self {
+        self.local_source = Some(local_source);
+        self
+    }
+
+    pub fn with_webhook_source(mut self, webhook_source: Arc<webhook::WebhookSource<S>>) -> Self {
+        self.webhook_source = Some(webhook_source);
         self
     }
 
@@ -229,10 +556,104 @@ where
         let updates_receiver = Mutex::new(updates_receiver);
         SourcesAggregator {
             http_source: self.http_source,
-            tg_source: self.tg_source,
+            tg_sources: self.tg_sources,
+            local_source: self.local_source,
+            webhook_source: self.webhook_source,
             storage: self.storage.unwrap(),
             updates_sender,
             updates_receiver,
         }
     }
 }
+
+#[cfg(all(test, feature = "pg-storage"))]
+mod tests {
+    use super::{http, webhook, SourcesAggregator};
+    use crate::models;
+    use crate::result::Error;
+    use crate::storage::pg::tests::{test_source, test_storage};
+    use crate::storage::Storage;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_pause_resume_remove() {
+        let storage = test_storage();
+        let webhook_source = webhook::WebhookSource::builder()
+            .with_storage(storage.clone())
+            .build();
+        let aggregator = SourcesAggregator::builder()
+            .with_storage(storage.clone())
+            .with_webhook_source(Arc::new(webhook_source))
+            .build();
+        let (source, _) = aggregator.add_webhook_source("test").await.unwrap();
+        let state = |source_id| {
+            let storage = storage.clone();
+            async move {
+                storage
+                    .get_source(source_id)
+                    .await
+                    .unwrap()
+                    .map(|s| s.state)
+            }
+        };
+
+        aggregator.pause_source(source.id).await.unwrap();
+        assert_eq!(
+            state(source.id).await.as_deref(),
+            Some(models::SOURCE_PAUSED)
+        );
+        aggregator.resume_source(source.id).await.unwrap();
+        assert_eq!(
+            state(source.id).await.as_deref(),
+            Some(models::SOURCE_ACTIVE)
+        );
+
+        aggregator.remove_source(source.id, false).await.unwrap();
+        assert_eq!(
+            state(source.id).await.as_deref(),
+            Some(models::SOURCE_DISABLED)
+        );
+        aggregator.resume_source(source.id).await.unwrap();
+        assert_eq!(
+            state(source.id).await.as_deref(),
+            Some(models::SOURCE_ACTIVE)
+        );
+
+        aggregator.remove_source(source.id, true).await.unwrap();
+        assert_eq!(state(source.id).await, None);
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_set_scrape_interval() {
+        let storage = test_storage();
+        let http_source = http::HttpSource::builder()
+            .with_storage(storage.clone())
+            .build();
+        let aggregator = SourcesAggregator::builder()
+            .with_storage(storage.clone())
+            .with_http_source(Arc::new(http_source))
+            .build();
+        let source = test_source(&storage, http::WEB).await;
+        for interval in &[0, -60] {
+            assert!(matches!(
+                aggregator
+                    .set_scrape_interval(source.id, Some(*interval))
+                    .await,
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+        aggregator
+            .set_scrape_interval(source.id, Some(600))
+            .await
+            .unwrap();
+        aggregator
+            .set_scrape_interval(source.id, None)
+            .await
+            .unwrap();
+        let saved = storage.get_source(source.id).await.unwrap().unwrap();
+        assert_eq!(saved.scrape_interval, None);
+        storage.delete_source(source.id).await.unwrap();
+    }
+}
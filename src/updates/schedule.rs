@@ -0,0 +1,186 @@
+use crate::models;
+use chrono::{Duration, NaiveDateTime, Utc};
+use rand::Rng;
+use std::convert::TryFrom;
+
+/// Share of the interval scrape time is randomly shifted by, so sources aren't scraped at once
+const JITTER: f64 = 0.1;
+/// Number of latest items the publishing frequency is learned from
+const FREQUENCY_ITEMS: usize = 10;
+
+/// Scrape intervals settings of the web sources, in seconds
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct ScrapeIntervals {
+    /// Interval of the sources which publishing frequency isn't known yet
+    pub default: i32,
+    pub min: i32,
+    pub max: i32,
+}
+
+impl ScrapeIntervals {
+    /// Interval of the source: manual one if set, otherwise adaptive one
+    pub fn source_interval(&self, source: &models::Source) -> i32 {
+        source
+            .scrape_interval
+            .or(source.adaptive_interval)
+            .unwrap_or(self.default)
+    }
+
+    /// Adaptive interval from the items publishing dates, not shorter than the feed hint
+    pub fn adaptive_interval(&self, dates: &[NaiveDateTime], hint: Option<i32>) -> i32 {
+        let interval = learned_interval(dates).unwrap_or(self.default);
+        let interval = hint.map_or(interval, |h| interval.max(h));
+        interval.max(self.min).min(self.max)
+    }
+
+    /// Interval of the failing source, doubled on each failure up to the max interval
+    pub fn backoff_interval(&self, source: &models::Source, failures: i32) -> i32 {
+        let interval = self.source_interval(source) as i64;
+        let factor = 1i64 << failures.max(0).min(30);
+        interval.saturating_mul(factor).min(self.max as i64) as i32
+    }
+}
+
+pub(crate) fn utc_now() -> NaiveDateTime {
+    Utc::now().naive_utc()
+}
+
+/// Next scrape time after the interval with jitter
+pub(crate) fn next_scrape_time(interval: i32) -> NaiveDateTime {
+    let spread = (interval as f64 * JITTER) as i64;
+    let shift = match spread {
+        0 => 0,
+        _ => rand::thread_rng().gen_range(-spread, spread + 1),
+    };
+    utc_now() + Duration::seconds(interval as i64 + shift)
+}
+
+/// Half of the median gap between the latest items, so new items are caught up quickly
+fn learned_interval(dates: &[NaiveDateTime]) -> Option<i32> {
+    let mut dates = dates.to_vec();
+    dates.sort_unstable_by(|a, b| b.cmp(a));
+    dates.truncate(FREQUENCY_ITEMS);
+    let mut gaps = dates
+        .windows(2)
+        .map(|w| (w[0] - w[1]).num_seconds())
+        .filter(|g| *g > 0)
+        .collect::<Vec<i64>>();
+    if gaps.is_empty() {
+        return None;
+    }
+    gaps.sort_unstable();
+    Some((gaps[gaps.len() / 2] / 2) as i32)
+}
+
+/// Interval hinted by the feed: RSS `<ttl>` in minutes or `sy:updatePeriod` and
+/// `sy:updateFrequency`, the longest one is used. Values come from the remote feed,
+/// so the hint saturates instead of overflowing, it's clamped by `adaptive_interval`
+pub(crate) fn feed_hint(
+    ttl: Option<u32>,
+    update_period: Option<&str>,
+    update_frequency: Option<u32>,
+) -> Option<i32> {
+    let ttl = ttl.map(|t| {
+        i32::try_from(t)
+            .ok()
+            .and_then(|t| t.checked_mul(60))
+            .unwrap_or(i32::MAX)
+    });
+    let period = update_period
+        .and_then(|p| match p.trim().to_lowercase().as_str() {
+            "hourly" => Some(60 * 60),
+            "daily" => Some(24 * 60 * 60),
+            "weekly" => Some(7 * 24 * 60 * 60),
+            "monthly" => Some(30 * 24 * 60 * 60),
+            "yearly" => Some(365 * 24 * 60 * 60),
+            _ => None,
+        })
+        .map(|p| p / i32::try_from(update_frequency.unwrap_or(1).max(1)).unwrap_or(i32::MAX));
+    match (ttl, period) {
+        (Some(ttl), Some(period)) => Some(ttl.max(period)),
+        (ttl, period) => ttl.or(period),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{feed_hint, learned_interval, next_scrape_time, utc_now, ScrapeIntervals};
+    use crate::models;
+    use chrono::Duration;
+
+    const INTERVALS: ScrapeIntervals = ScrapeIntervals {
+        default: 600,
+        min: 60,
+        max: 86400,
+    };
+
+    #[test]
+    fn test_learned_interval() {
+        let now = utc_now();
+        assert_eq!(learned_interval(&[]), None);
+        assert_eq!(learned_interval(&[now]), None);
+        let dates = (0..5)
+            .map(|i| now - Duration::hours(2 * i))
+            .collect::<Vec<_>>();
+        assert_eq!(learned_interval(&dates), Some(60 * 60));
+    }
+
+    #[test]
+    fn test_adaptive_interval() {
+        let now = utc_now();
+        let dates = vec![
+            now,
+            now - Duration::seconds(10),
+            now - Duration::seconds(20),
+        ];
+        assert_eq!(INTERVALS.adaptive_interval(&dates, None), 60);
+        assert_eq!(INTERVALS.adaptive_interval(&dates, Some(3600)), 3600);
+        assert_eq!(INTERVALS.adaptive_interval(&[], None), 600);
+        assert_eq!(INTERVALS.adaptive_interval(&[], Some(10 * 86400)), 86400);
+        assert_eq!(INTERVALS.adaptive_interval(&[], Some(i32::MAX)), 86400);
+    }
+
+    #[test]
+    fn test_backoff_interval() {
+        let source = models::Source {
+            id: 1,
+            name: String::new(),
+            origin: String::new(),
+            kind: String::new(),
+            image: None,
+            last_scrape_time: utc_now(),
+            external_link: String::new(),
+            account: None,
+            state: models::SOURCE_ACTIVE.to_string(),
+            scrape_interval: Some(600),
+            adaptive_interval: None,
+            next_scrape_time: utc_now(),
+            feed_kind: None,
+            fetch_full_content: false,
+            image_remote_id: None,
+        };
+        assert_eq!(INTERVALS.backoff_interval(&source, 1), 1200);
+        assert_eq!(INTERVALS.backoff_interval(&source, 3), 4800);
+        assert_eq!(INTERVALS.backoff_interval(&source, 100), 86400);
+    }
+
+    #[test]
+    fn test_feed_hint() {
+        assert_eq!(feed_hint(None, None, None), None);
+        assert_eq!(feed_hint(Some(30), None, None), Some(1800));
+        assert_eq!(feed_hint(None, Some("hourly"), Some(2)), Some(1800));
+        assert_eq!(feed_hint(Some(10), Some("daily"), None), Some(86400));
+        assert_eq!(feed_hint(None, Some("sometimes"), None), None);
+        assert_eq!(feed_hint(Some(u32::MAX), None, None), Some(i32::MAX));
+        assert_eq!(feed_hint(Some(35_791_395), None, None), Some(i32::MAX));
+        assert_eq!(feed_hint(None, Some("hourly"), Some(u32::MAX)), Some(0));
+    }
+
+    #[test]
+    fn test_next_scrape_time() {
+        let from = utc_now();
+        let next = next_scrape_time(1000);
+        assert!(next >= from + Duration::seconds(900));
+        assert!(next <= utc_now() + Duration::seconds(1100));
+    }
+}
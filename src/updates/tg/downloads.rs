@@ -0,0 +1,172 @@
+use super::TELEGRAM;
+use crate::models;
+use crate::result::{Error, Result};
+use crate::storage::Storage;
+use std::sync::Arc;
+use tg_collector::tg_client::TgClient;
+use tokio::sync::RwLock;
+use tokio::time::Duration;
+
+/// Settings of the failed downloads retry
+#[derive(Clone, Debug)]
+pub struct DownloadRetry {
+    /// Delay before the first retry, doubles with every next attempt
+    pub retry_secs_interval: i32,
+    pub max_attempts: i32,
+    /// Downloads without `TelegramUpdate::FileDownloadFinished` for this time are failed
+    pub timeout_secs: i32,
+}
+
+impl Default for DownloadRetry {
+    fn default() -> Self {
+        Self {
+            retry_secs_interval: 60,
+            max_attempts: 5,
+            timeout_secs: 3600,
+        }
+    }
+}
+
+/// Requests file download and tracks its state.
+///
+/// Download is requested only if the file is in the same state as it was loaded, so the file
+/// isn't requested twice by the worker and the updates handler. File stays in `DOWNLOADING`
+/// state until `TelegramUpdate::FileDownloadFinished` received.
+pub(super) async fn request_download<S: Storage + Send + Sync>(
+    collector: &RwLock<TgClient>,
+    storage: &S,
+    file: &models::File,
+) -> Result<()> {
+    let remote_file = match file.remote_path.parse() {
+        Ok(remote_file) => remote_file,
+        Err(_) => {
+            let error = format!("invalid remote file: {}", file.remote_path);
+            storage
+                .set_file_download_state(
+                    file.id,
+                    models::DOWNLOAD_FAILED.to_string(),
+                    Some(error.clone()),
+                )
+                .await?;
+            return Err(Error::InvalidContent(error));
+        }
+    };
+    if !storage
+        .set_file_downloading(file.id, file.download_state.clone(), file.download_attempts)
+        .await?
+    {
+        trace!("telegram file {} is already claimed", file.id);
+        return Ok(());
+    }
+    let download_result = collector.write().await.download_file(remote_file).await;
+    if let Err(e) = download_result {
+        error!("telegram file download failed: {}", e);
+        storage
+            .set_file_download_state(
+                file.id,
+                models::DOWNLOAD_FAILED.to_string(),
+                Some(e.to_string()),
+            )
+            .await?;
+    }
+    Ok(())
+}
+
+/// Resumes downloads interrupted by restart, then periodically requests queued downloads,
+/// fails timed out ones and retries failed with exponential backoff.
+///
+/// Only files of the `account` sources are handled.
+pub(super) async fn run_download_worker<S: Storage + Send + Sync>(
+    collector: Arc<RwLock<TgClient>>,
+    storage: S,
+    account: String,
+    retry: DownloadRetry,
+) {
+    match storage
+        .get_files_by_download_state(
+            TELEGRAM.to_string(),
+            Some(account.to_string()),
+            models::DOWNLOAD_DOWNLOADING.to_string(),
+            &0,
+        )
+        .await
+    {
+        Ok(files) => {
+            debug!("resume {} telegram downloads", files.len());
+            for file in files {
+                if let Err(e) = request_download(&collector, &storage, &file).await {
+                    error!("{}", e);
+                }
+            }
+        }
+        Err(e) => error!("{}", e),
+    }
+    let sleep_period = Duration::from_secs(retry.retry_secs_interval as u64);
+    loop {
+        if let Err(e) = process_downloads(&collector, &storage, &account, &retry).await {
+            error!("{}", e);
+        }
+        tokio::time::delay_for(sleep_period).await;
+    }
+}
+
+async fn process_downloads<S: Storage + Send + Sync>(
+    collector: &RwLock<TgClient>,
+    storage: &S,
+    account: &str,
+    retry: &DownloadRetry,
+) -> Result<()> {
+    for file in storage
+        .get_files_by_download_state(
+            TELEGRAM.to_string(),
+            Some(account.to_string()),
+            models::DOWNLOAD_DOWNLOADING.to_string(),
+            &retry.timeout_secs,
+        )
+        .await?
+    {
+        warn!("telegram file download timed out: {}", file.id);
+        storage
+            .set_file_download_state(
+                file.id,
+                models::DOWNLOAD_FAILED.to_string(),
+                Some("download timed out".to_string()),
+            )
+            .await?;
+    }
+    for file in storage
+        .get_files_by_download_state(
+            TELEGRAM.to_string(),
+            Some(account.to_string()),
+            models::DOWNLOAD_QUEUED.to_string(),
+            &0,
+        )
+        .await?
+    {
+        if let Err(e) = request_download(collector, storage, &file).await {
+            error!("{}", e);
+        }
+    }
+    for attempts in 1..retry.max_attempts {
+        let backoff = retry
+            .retry_secs_interval
+            .saturating_mul(2i32.saturating_pow((attempts - 1) as u32));
+        for file in storage
+            .get_files_by_download_state(
+                TELEGRAM.to_string(),
+                Some(account.to_string()),
+                models::DOWNLOAD_FAILED.to_string(),
+                &backoff,
+            )
+            .await?
+            .iter()
+            .filter(|f| f.download_attempts == attempts)
+        {
+            debug!("retry telegram file download: {}", file.id);
+            if let Err(e) = request_download(collector, storage, file).await {
+                error!("{}", e);
+            }
+        }
+    }
+    Ok(())
+}
@@ -9,11 +9,13 @@ use tokio::task::spawn;
 
 /// Handler interacts with tdlib using `tg_collector` crate.
 /// It initializes updates listener and pass all updates from `tg_collector` to specified sender
+/// tagged by the account
 
 #[derive(Clone)]
 pub struct Handler {
     sender: Arc<Mutex<mpsc::Sender<Result<SourceData>>>>,
     tg: Arc<RwLock<TgClient>>,
+    account: String,
     orig_sender: mpsc::Sender<TgUpdate>,
     orig_receiver: Arc<Mutex<mpsc::Receiver<TgUpdate>>>,
 }
@@ -23,24 +25,26 @@ impl Handler {
     pub fn new(
         sender: Arc<Mutex<mpsc::Sender<Result<SourceData>>>>,
         tg: Arc<RwLock<TgClient>>,
+        account: &str,
     ) -> Self {
         // TODO: configure channel size
         let (orig_sender, orig_receiver) = mpsc::channel::<TgUpdate>(2000);
         Self {
             sender,
             tg,
+            account: account.to_string(),
             orig_sender,
             orig_receiver: Arc::new(Mutex::new(orig_receiver)),
         }
     }
 
+    /// Starts the client and the forwarder of its updates, returns the client thread
     pub async fn run(&mut self) -> JoinHandle<()> {
-        let mut guard = self.tg.write().await;
-        guard.start_listen_updates(self.orig_sender.clone());
         // TODO handle join
-        let join_handle = guard.start();
+        let join_handle = self.start_client().await;
         let recv = self.orig_receiver.clone();
         let sender = self.sender.clone();
+        let account = self.account.clone();
         spawn(async move {
             loop {
                 let update = recv.lock().await.recv().await;
@@ -48,7 +52,7 @@ impl Handler {
                     None => return,
                     Some(update) => {
                         let parsed_update = match parse_update(update).await {
-                            Ok(Some(update)) => Ok(SourceData::Telegram(update)),
+                            Ok(Some(update)) => Ok(SourceData::Telegram(account.clone(), update)),
                             Err(e) => Err(e),
 
                             Ok(None) => continue,
@@ -64,4 +68,11 @@ impl Handler {
         });
         join_handle
     }
+
+    /// Starts the current client, e.g. replaced one, its updates go to the running forwarder
+    pub async fn start_client(&self) -> JoinHandle<()> {
+        let mut guard = self.tg.write().await;
+        guard.start_listen_updates(self.orig_sender.clone());
+        guard.start()
+    }
 }
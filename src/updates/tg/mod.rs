@@ -2,6 +2,12 @@
 mod handler;
 // updates parsers
 mod parsers;
+// formatted text renderers
+mod render;
+// files download policy
+mod policy;
+// persistent files download queue
+mod downloads;
 // telegram source struct and methods
 mod source;
 // SourceProvider trait implementation
@@ -11,6 +17,7 @@ mod updates_handler;
 
 mod structs;
 
+pub use parsers::render_raw_content;
 pub use source::*;
 pub use source_provider::*;
 pub use structs::*;
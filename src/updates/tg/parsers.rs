@@ -1,9 +1,18 @@
+use super::render::{render, HtmlRenderer, MarkdownRenderer, PlainRenderer};
 use super::{TelegramUpdate, TELEGRAM};
+use crate::config::TextFormat;
 use crate::result::{Error, Result};
-use crate::updates::tg::{FilePath, FileType, TelegramFile, TelegramFileWithMeta, TelegramMessage};
+use crate::tools;
+use crate::updates::tg::{
+    AuthState, FilePath, FileType, ForwardOrigin, TelegramChatPhoto, TelegramChatTitle,
+    TelegramFile, TelegramFileWithMeta, TelegramForwardInfo, TelegramMessage,
+};
 use tg_collector::tg_client::TgUpdate;
 use tg_collector::types::Channel;
-use tg_collector::{FormattedText, MessageContent, RObject, TextEntity, TextEntityType};
+use tg_collector::{
+    AuthorizationState, FormattedText, MessageContent, MessageForwardInfo, MessageForwardOrigin,
+    RObject,
+};
 
 /// Parses updates from `tg_collector` to `TelegramUpdate` struct
 pub async fn parse_update(tg_update: &TgUpdate) -> Result<Option<TelegramUpdate>> {
@@ -26,6 +35,10 @@ pub async fn parse_update(tg_update: &TgUpdate) -> Result<Option<TelegramUpdate>
                     date: Some(new_message.message().date()),
                     content,
                     files,
+                    forward: parse_forward_info(new_message.message().forward_info()),
+                    reply_to_message_id: parse_reply_to(
+                        new_message.message().reply_to_message_id(),
+                    ),
                 }))
             }
         }
@@ -41,47 +54,105 @@ pub async fn parse_update(tg_update: &TgUpdate) -> Result<Option<TelegramUpdate>
                     date: None,
                     content,
                     files,
+                    forward: None,
+                    reply_to_message_id: None,
                 }))
             }
         }
-        TgUpdate::ChatPhoto(photo) => {
-            return Err(Error::UpdateNotSupported(photo.td_name().to_string()))
-        }
-        TgUpdate::ChatTitle(title) => {
-            return Err(Error::UpdateNotSupported(title.td_name().to_string()))
+        TgUpdate::ChatPhoto(chat_photo) => Some(TelegramUpdate::ChatPhoto(TelegramChatPhoto {
+            chat_id: chat_photo.chat_id(),
+            photo: chat_photo.photo().as_ref().map(|p| FilePath::new(p.big())),
+        })),
+        TgUpdate::ChatTitle(chat_title) => Some(TelegramUpdate::ChatTitle(TelegramChatTitle {
+            chat_id: chat_title.chat_id(),
+            title: chat_title.title().clone(),
+        })),
+        TgUpdate::AuthorizationState(auth_state) => Some(TelegramUpdate::AuthState(
+            parse_auth_state(auth_state.authorization_state()),
+        )),
+    })
+}
+
+pub fn parse_auth_state(auth_state: &AuthorizationState) -> AuthState {
+    match auth_state {
+        AuthorizationState::WaitTdlibParameters(_) | AuthorizationState::WaitEncryptionKey(_) => {
+            AuthState::WaitTdlibParameters
+        }
+        AuthorizationState::WaitPhoneNumber(_) => AuthState::WaitPhoneNumber,
+        AuthorizationState::WaitCode(wait_code) => AuthState::WaitCode {
+            phone_number: wait_code.code_info().phone_number().clone(),
+        },
+        AuthorizationState::WaitPassword(wait_password) => AuthState::WaitPassword {
+            hint: wait_password.password_hint().clone(),
+        },
+        AuthorizationState::WaitRegistration(_) => AuthState::WaitRegistration,
+        AuthorizationState::WaitOtherDeviceConfirmation(confirmation) => {
+            AuthState::WaitOtherDeviceConfirmation {
+                link: confirmation.link().clone(),
+            }
         }
+        AuthorizationState::Ready(_) => AuthState::Ready,
+        AuthorizationState::LoggingOut(_) => AuthState::LoggingOut,
+        AuthorizationState::Closing(_) => AuthState::Closing,
+        AuthorizationState::Closed(_) => AuthState::Closed,
+        AuthorizationState::_Default(_) => AuthState::Unknown,
+    }
+}
+
+/// tdlib uses zero `reply_to_message_id` for messages without reply
+pub fn parse_reply_to(reply_to_message_id: i64) -> Option<i64> {
+    match reply_to_message_id {
+        0 => None,
+        id => Some(id),
+    }
+}
+
+/// Parses origin of the forwarded message
+pub fn parse_forward_info(
+    forward_info: &Option<MessageForwardInfo>,
+) -> Option<TelegramForwardInfo> {
+    let forward_info = forward_info.as_ref()?;
+    let origin = match forward_info.origin() {
+        MessageForwardOrigin::Channel(channel) => ForwardOrigin::Channel {
+            chat_id: channel.chat_id(),
+            message_id: channel.message_id(),
+            author_signature: tools::empty_string_as_option(channel.author_signature()),
+        },
+        MessageForwardOrigin::User(user) => ForwardOrigin::User {
+            user_id: user.sender_user_id(),
+        },
+        MessageForwardOrigin::HiddenUser(user) => ForwardOrigin::HiddenUser {
+            sender_name: user.sender_name().clone(),
+        },
+        _ => return None,
+    };
+    Some(TelegramForwardInfo {
+        origin,
+        date: forward_info.date(),
     })
 }
 
 pub async fn parse_message_content(
     message: &MessageContent,
-) -> Result<(Option<String>, Option<Vec<TelegramFileWithMeta>>)> {
+) -> Result<(Option<FormattedText>, Option<Vec<TelegramFileWithMeta>>)> {
     match message {
-        MessageContent::MessageText(text) => Ok((Some(parse_formatted_text(text.text())), None)),
+        MessageContent::MessageText(text) => Ok((Some(text.text().clone()), None)),
         MessageContent::MessageAnimation(message_animation) => {
             let file = TelegramFileWithMeta {
                 path: FilePath::new(message_animation.animation().animation()),
                 file_type: FileType::Animation(message_animation.animation().into()),
                 file_name: Some(message_animation.animation().file_name().clone()),
             };
-            Ok((
-                Some(parse_formatted_text(message_animation.caption())),
-                Some(vec![file]),
-            ))
-        }
-        MessageContent::MessageAudio(audio) => {
-            Ok((Some(parse_formatted_text(audio.caption())), None))
+            Ok((Some(message_animation.caption().clone()), Some(vec![file])))
         }
+        MessageContent::MessageAudio(audio) => Ok((Some(audio.caption().clone()), None)),
         MessageContent::MessageDocument(message_document) => {
             let file = TelegramFileWithMeta {
                 path: FilePath::new(message_document.document().document()),
                 file_type: FileType::Document,
                 file_name: Some(message_document.document().file_name().clone()),
             };
-            Ok((
-                Some(parse_formatted_text(message_document.caption())),
-                Some(vec![file]),
-            ))
+            Ok((Some(message_document.caption().clone()), Some(vec![file])))
         }
         MessageContent::MessagePhoto(photo) => {
             let files = photo
@@ -94,11 +165,9 @@ pub async fn parse_message_content(
                     file_name: None,
                 })
                 .collect();
-            Ok((Some(parse_formatted_text(photo.caption())), Some(files)))
-        }
-        MessageContent::MessageVideo(video) => {
-            Ok((Some(parse_formatted_text(video.caption())), None))
+            Ok((Some(photo.caption().clone()), Some(files)))
         }
+        MessageContent::MessageVideo(video) => Ok((Some(video.caption().clone()), None)),
 
         MessageContent::MessageChatChangePhoto(u) => {
             Err(Error::UpdateNotSupported(u.td_name().to_string()))
@@ -187,93 +256,199 @@ pub async fn parse_message_content(
     }
 }
 
-pub fn parse_formatted_text(formatted_text: &FormattedText) -> String {
-    let mut entities_by_index = make_entities_stack(formatted_text.entities());
-    let mut result_text = String::new();
-    let mut current_entity = match entities_by_index.pop() {
-        None => return formatted_text.text().clone(),
-        Some(entity) => entity,
-    };
-    for (i, ch) in formatted_text.text().chars().enumerate() {
-        if i == current_entity.0 {
-            result_text = format!("{}{}{}", result_text, current_entity.1, ch);
-            current_entity = match entities_by_index.pop() {
-                None => {
-                    result_text = format!(
-                        "{}{}",
-                        result_text,
-                        &formatted_text
-                            .text()
-                            .chars()
-                            .skip(i + 1)
-                            .take(formatted_text.text().len() - i)
-                            .collect::<String>()
-                    );
-                    return result_text;
-                }
-                Some(entity) => entity,
-            };
-        } else {
-            result_text.push(ch)
-        }
+/// Renders `FormattedText` to the specified format
+pub fn parse_formatted_text(formatted_text: &FormattedText, format: TextFormat) -> String {
+    match format {
+        TextFormat::Html => render(formatted_text, &HtmlRenderer),
+        TextFormat::Markdown => render(formatted_text, &MarkdownRenderer),
+        TextFormat::Plain => render(formatted_text, &PlainRenderer),
     }
-    result_text
 }
 
-fn make_entities_stack(entities: &[TextEntity]) -> Vec<(usize, String)> {
-    let mut stack = Vec::new();
-    for entity in entities {
-        let formatting = match entity.type_() {
-            TextEntityType::Bold(_) => Some(("<b>".to_string(), "</b>".to_string())),
-            TextEntityType::Code(_) => Some(("<code>".to_string(), "</code>".to_string())),
-            TextEntityType::Hashtag(_) => Some(("#".to_string(), "".to_string())),
-            TextEntityType::Italic(_) => Some(("<i>".to_string(), "</i>".to_string())),
-            TextEntityType::PhoneNumber(_) => Some(("<phone>".to_string(), "</phone>".to_string())),
-            TextEntityType::Pre(_) => Some(("<pre>".to_string(), "</pre>".to_string())),
-            TextEntityType::PreCode(_) => {
-                Some(("<pre><code>".to_string(), "</code></pre>".to_string()))
-            }
-            TextEntityType::Strikethrough(_) => {
-                Some(("<strike>".to_string(), "</strike>".to_string()))
-            }
-            TextEntityType::TextUrl(u) => {
-                let tag = format!(r#"<a href="{}">"#, u.url());
-                Some((tag, "</a>".to_string()))
-            }
-            TextEntityType::Underline(_) => Some(("<u>".to_string(), "</u>".to_string())),
-            TextEntityType::Url(_) => Some(("<a>".to_string(), "</a>".to_string())),
-            TextEntityType::_Default(_) => None,
-            // TextEntityType::BankCardNumber(_) => None,
-            TextEntityType::BotCommand(_) => None,
-            TextEntityType::Cashtag(_) => None,
-            TextEntityType::EmailAddress(_) => None,
-            TextEntityType::Mention(_) => None,
-            TextEntityType::MentionName(_) => None,
-        };
-        if let Some((start_tag, end_tag)) = formatting {
-            stack.push((entity.offset() as usize, start_tag));
-            stack.push(((entity.offset() + entity.length()) as usize, end_tag));
-        }
-    }
-    stack.sort_by_key(|(i, _)| *i);
-    stack.reverse();
-    stack
+/// Renders `FormattedText` saved as record raw content
+pub fn render_raw_content(raw_content: &str, format: TextFormat) -> Result<String> {
+    let formatted_text = serde_json::from_str::<FormattedText>(raw_content)
+        .map_err(|e| Error::InvalidContent(e.to_string()))?;
+    Ok(parse_formatted_text(&formatted_text, format))
 }
 
-pub(super) fn channel_to_new_source(channel: Channel) -> crate::models::NewSource {
+/// Converts `Channel` to `NewSource`.
+///
+/// Image is always empty here: it's set after chat photo download, see `channel_photo`.
+/// Source is tagged by the account which found the channel.
+pub(super) fn channel_to_new_source(channel: Channel, account: &str) -> crate::models::NewSource {
     crate::models::NewSource {
         name: channel.title,
         origin: channel.chat_id.to_string(),
         kind: TELEGRAM.to_string(),
         image: None,
         external_link: channel.username,
+        account: Some(account.to_string()),
+        feed_kind: None,
+    }
+}
+
+/// Converts `Channel` found by search to `SourceCandidate`
+pub(super) fn channel_to_candidate(
+    channel: Channel,
+    account: &str,
+    sample_items: Vec<crate::models::SampleItem>,
+) -> crate::models::SourceCandidate {
+    let image = channel
+        .photo
+        .as_ref()
+        .and_then(|p| tools::empty_string_as_option(p.small().local().path()));
+    crate::models::SourceCandidate {
+        kind: TELEGRAM.to_string(),
+        origin: channel.chat_id.to_string(),
+        name: channel.title,
+        description: tools::empty_string_as_option(&channel.description),
+        image,
+        external_link: channel.username,
+        account: Some(account.to_string()),
+        sample_items,
+    }
+}
+
+/// Telegram chat link given instead of the search query
+#[derive(Debug, PartialEq)]
+pub(super) enum ChatLink {
+    /// Private chat invite link, normalized to `https://t.me/joinchat/<hash>`
+    Invite(String),
+    /// Public chat username
+    Username(String),
+    /// Chat id of the private channel message link `t.me/c/<id>/<message id>`
+    ChatId(i64),
+    /// Phone number of the user link `t.me/+<digits>`
+    Phone(String),
+}
+
+/// Recognizes `t.me/joinchat/<hash>`, `t.me/+<hash>`, `tg://join?invite=<hash>`,
+/// `t.me/c/<id>`, `t.me/+<phone number>` and `t.me/<username>` links
+pub(super) fn parse_chat_link(query: &str) -> Option<ChatLink> {
+    let query = query.trim();
+    if let Some(hash) = query.strip_prefix("tg://join?invite=") {
+        return invite_link(hash);
     }
+    let path = ["https://", "http://", ""].iter().find_map(|scheme| {
+        ["t.me/", "telegram.me/", "telegram.dog/"]
+            .iter()
+            .find_map(|host| query.strip_prefix(&format!("{}{}", scheme, host)))
+    })?;
+    let path = path
+        .split(|c| c == '?' || c == '#')
+        .next()
+        .unwrap_or_default();
+    let mut segments = path.split('/').filter(|s| !s.is_empty());
+    match segments.next()? {
+        "joinchat" => invite_link(segments.next()?),
+        segment if segment.starts_with('+') => match &segment[1..] {
+            digits if !digits.is_empty() && digits.chars().all(|c| c.is_ascii_digit()) => {
+                Some(ChatLink::Phone(segment.to_string()))
+            }
+            hash => invite_link(hash),
+        },
+        // private channels are linked by their id without the `-100` prefix
+        "c" => segments
+            .next()?
+            .parse::<i64>()
+            .ok()
+            .filter(|id| *id > 0)
+            .and_then(|id| (-1_000_000_000_000i64).checked_sub(id))
+            .map(ChatLink::ChatId),
+        // t.me/s/<username> is a web preview of the channel
+        "s" => Some(ChatLink::Username(segments.next()?.to_string())),
+        username => Some(ChatLink::Username(username.to_string())),
+    }
+}
+
+fn invite_link(hash: &str) -> Option<ChatLink> {
+    if hash.is_empty() {
+        return None;
+    }
+    Some(ChatLink::Invite(format!("https://t.me/joinchat/{}", hash)))
+}
+
+/// Returns chat id of the telegram source
+pub(super) fn source_chat_id(source: &crate::models::Source) -> Result<i64> {
+    source
+        .origin
+        .parse::<i64>()
+        .map_err(|_| Error::SourceKindConflict(format!("not a chat: {}", source.origin)))
+}
+
+/// Returns the chat photo file of the channel, if any
+pub(super) fn channel_photo(channel: &Channel) -> Option<FilePath> {
+    channel.photo.as_ref().map(|p| FilePath::new(p.big()))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::updates::tg::parsers::parse_formatted_text;
-    use tg_collector::FormattedText;
+    use crate::config::TextFormat;
+    use crate::updates::tg::parsers::{
+        parse_auth_state, parse_chat_link, parse_formatted_text, parse_forward_info, parse_update,
+        ChatLink,
+    };
+    use crate::updates::tg::{AuthState, ForwardOrigin, TelegramUpdate};
+    use tg_collector::tg_client::TgUpdate;
+    use tg_collector::{
+        FormattedText, MessageForwardInfo, UpdateAuthorizationState, UpdateNewMessage,
+    };
+
+    #[test]
+    fn test_parse_forward_info() {
+        let tests = vec![
+            (
+                r#"{"@type":"messageForwardInfo","@extra":"","origin":{"@type":"messageForwardOriginChannel","@extra":"","chat_id":-1001146915409,"message_id":4194304,"author_signature":""},"date":1607000000,"public_service_announcement_type":"","from_chat_id":0,"from_message_id":0}"#,
+                ForwardOrigin::Channel {
+                    chat_id: -1001146915409,
+                    message_id: 4194304,
+                    author_signature: None,
+                },
+            ),
+            (
+                r#"{"@type":"messageForwardInfo","@extra":"","origin":{"@type":"messageForwardOriginHiddenUser","@extra":"","sender_name":"John"},"date":1607000000,"public_service_announcement_type":"","from_chat_id":0,"from_message_id":0}"#,
+                ForwardOrigin::HiddenUser {
+                    sender_name: "John".to_string(),
+                },
+            ),
+        ];
+        for (json_data, expected) in tests {
+            let forward_info = MessageForwardInfo::from_json(json_data).unwrap();
+            let parsed = parse_forward_info(&Some(forward_info)).unwrap();
+            assert_eq!(parsed.origin, expected);
+            assert_eq!(parsed.date, 1607000000);
+        }
+        assert!(parse_forward_info(&None).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_parse_reply_to() {
+        let message = |reply_to_message_id: i64, message_thread_id: i64| {
+            UpdateNewMessage::from_json(&format!(
+                r#"{{"@type":"updateNewMessage","@extra":"","message":{{"@type":"message","id":3145728,"chat_id":-1001146915409,"date":1607000000,"reply_in_chat_id":-1001146915409,"reply_to_message_id":{},"message_thread_id":{},"content":{{"@type":"messageText","text":{{"@type":"formattedText","text":"reply","entities":[]}}}}}}}}"#,
+                reply_to_message_id, message_thread_id
+            ))
+            .unwrap()
+        };
+        let tests = vec![
+            // reply to another reply of the thread
+            (message(2097152, 1048576), Some(2097152)),
+            // top-level reply starts the thread
+            (message(1048576, 1048576), Some(1048576)),
+            // message without reply has zero id
+            (message(0, 0), None),
+        ];
+        for (update, expected) in tests {
+            match parse_update(&TgUpdate::NewMessage(update)).await.unwrap() {
+                Some(TelegramUpdate::Message(message)) => {
+                    assert_eq!(message.message_id, 3145728);
+                    assert_eq!(message.reply_to_message_id, expected);
+                }
+                parsed => panic!("not a message: {:?}", parsed),
+            }
+        }
+    }
 
     #[test]
     fn test_parse_formatted_text() {
@@ -295,8 +470,289 @@ https://t.me/joinchat/IqlQqUGyZpI1-0Zu8ChAmA"#,
         ];
         for (json_data, expected) in tests {
             let formatted_text = FormattedText::from_json(json_data).unwrap();
-            let t = parse_formatted_text(&formatted_text);
+            let t = parse_formatted_text(&formatted_text, TextFormat::Html);
             assert_eq!(t, expected);
         }
     }
+
+    fn formatted_text(text: &str, entities: &[(i64, i64, &str)]) -> FormattedText {
+        let entities = entities
+            .iter()
+            .map(|(offset, length, type_)| {
+                format!(
+                    r#"{{"@type":"textEntity","@extra":"","offset":{},"length":{},"type":{{{},"@extra":""}}}}"#,
+                    offset, length, type_
+                )
+            })
+            .collect::<Vec<String>>()
+            .join(",");
+        let json_data = format!(
+            r#"{{"@type":"formattedText","@extra":"","text":{},"entities":[{}]}}"#,
+            serde_json::to_string(text).unwrap(),
+            entities
+        );
+        FormattedText::from_json(&json_data).unwrap()
+    }
+
+    #[test]
+    fn test_parse_formatted_text_entities() {
+        // offsets are in UTF-16 code units
+        let tests = vec![
+            (
+                "👍 good",
+                vec![(3, 4, r#""@type":"textEntityTypeBold""#)],
+                "👍 <b>good</b>",
+            ),
+            (
+                "🔥🔥 hot 🔥",
+                vec![(0, 4, r#""@type":"textEntityTypeItalic""#)],
+                "<i>🔥🔥</i> hot 🔥",
+            ),
+            (
+                "Привет 🌍 мир",
+                vec![(
+                    10,
+                    3,
+                    r#""@type":"textEntityTypeTextUrl","url":"https://example.com""#,
+                )],
+                r#"Привет 🌍 <a href="https://example.com">мир</a>"#,
+            ),
+            (
+                "🇷🇺 flag",
+                vec![(0, 4, r#""@type":"textEntityTypeBold""#)],
+                "<b>🇷🇺</b> flag",
+            ),
+            (
+                "a 😀 b 😀 c 😀 d",
+                vec![
+                    (5, 1, r#""@type":"textEntityTypeBold""#),
+                    (10, 1, r#""@type":"textEntityTypeItalic""#),
+                    (15, 1, r#""@type":"textEntityTypeUnderline""#),
+                ],
+                "a 😀 <b>b</b> 😀 <i>c</i> 😀 <u>d</u>",
+            ),
+            (
+                "bold italic",
+                vec![
+                    (0, 11, r#""@type":"textEntityTypeBold""#),
+                    (5, 6, r#""@type":"textEntityTypeItalic""#),
+                ],
+                "<b>bold <i>italic</i></b>",
+            ),
+            (
+                "abcdef",
+                vec![
+                    (0, 4, r#""@type":"textEntityTypeBold""#),
+                    (2, 4, r#""@type":"textEntityTypeItalic""#),
+                ],
+                "<b>ab<i>cd</i></b><i>ef</i>",
+            ),
+            (
+                "x",
+                vec![
+                    (0, 1, r#""@type":"textEntityTypeBold""#),
+                    (0, 1, r#""@type":"textEntityTypeItalic""#),
+                ],
+                "<b><i>x</i></b>",
+            ),
+            (
+                "one two three",
+                vec![
+                    (0, 7, r#""@type":"textEntityTypeBold""#),
+                    (4, 9, r#""@type":"textEntityTypeItalic""#),
+                    (8, 5, r#""@type":"textEntityTypeUnderline""#),
+                ],
+                "<b>one <i>two</i></b><i> <u>three</u></i>",
+            ),
+            (
+                r#"a < b && c > "d" 'e'"#,
+                vec![],
+                "a &lt; b &amp;&amp; c &gt; &quot;d&quot; &#39;e&#39;",
+            ),
+            (
+                "if a < b { return; }",
+                vec![(
+                    0,
+                    20,
+                    r#""@type":"textEntityTypePreCode","language":"rust""#,
+                )],
+                r#"<pre><code class="language-rust">if a &lt; b { return; }</code></pre>"#,
+            ),
+            (
+                "run x<y now",
+                vec![(4, 3, r#""@type":"textEntityTypeCode""#)],
+                "run <code>x&lt;y</code> now",
+            ),
+            (
+                "see example.com/a?b=1&c=2",
+                vec![(4, 21, r#""@type":"textEntityTypeUrl""#)],
+                r#"see <a href="http://example.com/a?b=1&amp;c=2">example.com/a?b=1&amp;c=2</a>"#,
+            ),
+            (
+                "ask @agg_r or Bob",
+                vec![
+                    (4, 6, r#""@type":"textEntityTypeMention""#),
+                    (14, 3, r#""@type":"textEntityTypeMentionName","user_id":42"#),
+                ],
+                r#"ask <a href="https://t.me/agg_r">@agg_r</a> or <a href="tg://user?id=42">Bob</a>"#,
+            ),
+            (
+                "mail me@example.com or +1 555 0100",
+                vec![
+                    (5, 14, r#""@type":"textEntityTypeEmailAddress""#),
+                    (23, 11, r#""@type":"textEntityTypePhoneNumber""#),
+                ],
+                r#"mail <a href="mailto:me@example.com">me@example.com</a> or <a href="tel:+1 555 0100">+1 555 0100</a>"#,
+            ),
+            (
+                "#news today",
+                vec![(0, 5, r#""@type":"textEntityTypeHashtag""#)],
+                "#news today",
+            ),
+            (
+                "broken",
+                vec![
+                    (2, 100, r#""@type":"textEntityTypeBold""#),
+                    (-1, 2, r#""@type":"textEntityTypeItalic""#),
+                    (1, 0, r#""@type":"textEntityTypeUnderline""#),
+                ],
+                "broken",
+            ),
+            (
+                "🎉<b>🎉",
+                vec![(2, 3, r#""@type":"textEntityTypeStrikethrough""#)],
+                "🎉<s>&lt;b&gt;</s>🎉",
+            ),
+        ];
+        for (text, entities, expected) in tests {
+            let formatted_text = formatted_text(text, &entities);
+            let t = parse_formatted_text(&formatted_text, TextFormat::Html);
+            assert_eq!(t, expected, "text: {}", text);
+        }
+    }
+
+    #[test]
+    fn test_parse_formatted_text_formats() {
+        let formatted_text = formatted_text(
+            "one two three 🎉",
+            &[
+                (0, 7, r#""@type":"textEntityTypeBold""#),
+                (4, 9, r#""@type":"textEntityTypeItalic""#),
+                (
+                    14,
+                    2,
+                    r#""@type":"textEntityTypeTextUrl","url":"https://example.com""#,
+                ),
+            ],
+        );
+        assert_eq!(
+            parse_formatted_text(&formatted_text, TextFormat::Markdown),
+            "**one _two_**_ three_ [🎉](https://example.com)"
+        );
+        assert_eq!(
+            parse_formatted_text(&formatted_text, TextFormat::Plain),
+            "one two three 🎉 (https://example.com)"
+        );
+    }
+
+    #[test]
+    fn test_parse_chat_link() {
+        let invite = |hash: &str| Some(ChatLink::Invite(format!("https://t.me/joinchat/{}", hash)));
+        let username = |name: &str| Some(ChatLink::Username(name.to_string()));
+        let tests = vec![
+            (
+                "https://t.me/joinchat/AAAAAEkk2WdoDrB4-Q8-gg",
+                invite("AAAAAEkk2WdoDrB4-Q8-gg"),
+            ),
+            (
+                "t.me/joinchat/AAAAAEkk2WdoDrB4-Q8-gg/",
+                invite("AAAAAEkk2WdoDrB4-Q8-gg"),
+            ),
+            ("https://t.me/+AbCdEf0123456789", invite("AbCdEf0123456789")),
+            (
+                "https://t.me/+15551234567",
+                Some(ChatLink::Phone("+15551234567".to_string())),
+            ),
+            (
+                "https://t.me/c/1146915409/4194304",
+                Some(ChatLink::ChatId(-1001146915409)),
+            ),
+            ("t.me/c/1146915409", Some(ChatLink::ChatId(-1001146915409))),
+            ("https://t.me/c/", None),
+            ("https://t.me/c/agg_r", None),
+            ("https://t.me/c/9223372036854775807", None),
+            (
+                "tg://join?invite=AbCdEf0123456789",
+                invite("AbCdEf0123456789"),
+            ),
+            ("https://telegram.me/joinchat/AbCd", invite("AbCd")),
+            ("https://t.me/agg_r", username("agg_r")),
+            ("http://t.me/agg_r/123?single", username("agg_r")),
+            ("t.me/s/agg_r", username("agg_r")),
+            ("https://t.me/joinchat/", None),
+            ("https://t.me/", None),
+            ("agg_r", None),
+            ("https://example.com/t.me/agg_r", None),
+        ];
+        for (query, expected) in tests {
+            assert_eq!(parse_chat_link(query), expected, "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_parse_auth_state_transitions() {
+        let state = |json: &str| {
+            let update = UpdateAuthorizationState::from_json(&format!(
+                r#"{{"@type":"updateAuthorizationState","@extra":"","authorization_state":{}}}"#,
+                json
+            ))
+            .unwrap();
+            parse_auth_state(update.authorization_state())
+        };
+        // session expires, then the restarted client logs in again
+        let tests = vec![
+            (r#"{"@type":"authorizationStateReady"}"#, AuthState::Ready),
+            (
+                r#"{"@type":"authorizationStateLoggingOut"}"#,
+                AuthState::LoggingOut,
+            ),
+            (
+                r#"{"@type":"authorizationStateClosing"}"#,
+                AuthState::Closing,
+            ),
+            (r#"{"@type":"authorizationStateClosed"}"#, AuthState::Closed),
+            (
+                r#"{"@type":"authorizationStateWaitTdlibParameters"}"#,
+                AuthState::WaitTdlibParameters,
+            ),
+            (
+                r#"{"@type":"authorizationStateWaitPhoneNumber"}"#,
+                AuthState::WaitPhoneNumber,
+            ),
+            (
+                r#"{"@type":"authorizationStateWaitCode","code_info":{"@type":"authenticationCodeInfo","phone_number":"+10000000000","type":{"@type":"authenticationCodeTypeSms","length":5},"timeout":0}}"#,
+                AuthState::WaitCode {
+                    phone_number: "+10000000000".to_string(),
+                },
+            ),
+            (
+                r#"{"@type":"authorizationStateWaitPassword","password_hint":"pet","has_recovery_email_address":false,"recovery_email_address_pattern":""}"#,
+                AuthState::WaitPassword {
+                    hint: "pet".to_string(),
+                },
+            ),
+            (r#"{"@type":"authorizationStateReady"}"#, AuthState::Ready),
+        ];
+        for (json, expected) in tests {
+            let parsed = state(json);
+            assert_eq!(parsed.needs_restart(), expected == AuthState::Closed);
+            assert_eq!(parsed, expected, "{}", json);
+        }
+        assert_eq!(
+            state(
+                r#"{"@type":"authorizationStateWaitRegistration","terms_of_service":{"@type":"termsOfService","text":{"@type":"formattedText","text":"","entities":[]},"min_user_age":0,"show_popup":false}}"#
+            ),
+            AuthState::WaitRegistration
+        );
+    }
 }
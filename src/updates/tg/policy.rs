@@ -0,0 +1,147 @@
+use super::structs::{FileType, TelegramFileWithMeta};
+use crate::config::{DownloadFileType, DownloadPolicy, PhotoSizesPolicy};
+
+/// Applies download policy to the files of one message.
+///
+/// Returns skip reason for every file, `None` means the file must be downloaded.
+pub(super) fn skip_reasons(
+    policy: &DownloadPolicy,
+    files: &[TelegramFileWithMeta],
+) -> Vec<Option<String>> {
+    let photo_areas = files
+        .iter()
+        .filter_map(|f| match &f.file_type {
+            FileType::Image(meta) => Some(meta.width * meta.height),
+            _ => None,
+        })
+        .collect::<Vec<i64>>();
+    let largest = photo_areas.iter().max();
+    let smallest = photo_areas.iter().min();
+
+    files
+        .iter()
+        .map(|file| {
+            if policy.skip_all {
+                return Some("downloads disabled".to_string());
+            }
+            if !policy.file_types.is_empty()
+                && !policy
+                    .file_types
+                    .contains(&download_file_type(&file.file_type))
+            {
+                return Some("file type not allowed".to_string());
+            }
+            if let Some(max_file_size) = policy.max_file_size {
+                if file.path.size > max_file_size {
+                    return Some(format!("file size exceeds {} bytes", max_file_size));
+                }
+            }
+            if let FileType::Image(meta) = &file.file_type {
+                let area = meta.width * meta.height;
+                match policy.photo_sizes {
+                    PhotoSizesPolicy::All => {}
+                    PhotoSizesPolicy::LargestOnly if Some(&area) != largest => {
+                        return Some("not the largest photo size".to_string())
+                    }
+                    PhotoSizesPolicy::ThumbnailsOnly if Some(&area) != smallest => {
+                        return Some("not a thumbnail".to_string())
+                    }
+                    _ => {}
+                }
+            }
+            None
+        })
+        .collect()
+}
+
+fn download_file_type(file_type: &FileType) -> DownloadFileType {
+    match file_type {
+        FileType::Document => DownloadFileType::Document,
+        FileType::Animation(_) => DownloadFileType::Animation,
+        FileType::Image(_) => DownloadFileType::Image,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::skip_reasons;
+    use crate::config::{DownloadFileType, DownloadPolicy, PhotoSizesPolicy};
+    use crate::updates::tg::{FilePath, FileType, ImageMeta, TelegramFileWithMeta};
+
+    fn photo(width: i64, size: i64) -> TelegramFileWithMeta {
+        TelegramFileWithMeta {
+            path: FilePath {
+                local_path: None,
+                remote_file: width.to_string(),
+                remote_id: width.to_string(),
+                size,
+            },
+            file_type: FileType::Image(ImageMeta {
+                width,
+                height: width,
+            }),
+            file_name: None,
+        }
+    }
+
+    fn document(size: i64) -> TelegramFileWithMeta {
+        TelegramFileWithMeta {
+            path: FilePath {
+                local_path: None,
+                remote_file: "document".to_string(),
+                remote_id: "document".to_string(),
+                size,
+            },
+            file_type: FileType::Document,
+            file_name: Some("document.pdf".to_string()),
+        }
+    }
+
+    fn downloaded(policy: &DownloadPolicy, files: &[TelegramFileWithMeta]) -> Vec<bool> {
+        skip_reasons(policy, files)
+            .iter()
+            .map(|r| r.is_none())
+            .collect()
+    }
+
+    #[test]
+    fn test_skip_reasons() {
+        let photos = vec![photo(90, 1000), photo(320, 10000), photo(1280, 100000)];
+
+        let policy = DownloadPolicy::default();
+        assert_eq!(downloaded(&policy, &photos), vec![true, true, true]);
+
+        let policy = DownloadPolicy {
+            photo_sizes: PhotoSizesPolicy::LargestOnly,
+            ..Default::default()
+        };
+        assert_eq!(downloaded(&policy, &photos), vec![false, false, true]);
+
+        let policy = DownloadPolicy {
+            photo_sizes: PhotoSizesPolicy::ThumbnailsOnly,
+            ..Default::default()
+        };
+        assert_eq!(downloaded(&policy, &photos), vec![true, false, false]);
+
+        let policy = DownloadPolicy {
+            max_file_size: Some(50000),
+            ..Default::default()
+        };
+        assert_eq!(downloaded(&policy, &photos), vec![true, true, false]);
+
+        let policy = DownloadPolicy {
+            file_types: vec![DownloadFileType::Image],
+            ..Default::default()
+        };
+        assert_eq!(downloaded(&policy, &[document(10)]), vec![false]);
+
+        let policy = DownloadPolicy {
+            skip_all: true,
+            ..Default::default()
+        };
+        assert_eq!(
+            skip_reasons(&policy, &[document(10)]),
+            vec![Some("downloads disabled".to_string())]
+        );
+    }
+}
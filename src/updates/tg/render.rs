@@ -0,0 +1,263 @@
+use tg_collector::{FormattedText, TextEntityType};
+
+/// Renders `FormattedText` entities to the particular markup
+pub trait Renderer {
+    /// Returns markup to put around the entity; `text` is the raw text of the entity
+    fn tags(&self, entity_type: &TextEntityType, text: &str) -> Option<(String, String)>;
+    /// Escapes raw text; `verbatim` is set for the text inside code entities
+    fn escape(&self, text: &str, verbatim: bool) -> String;
+}
+
+pub struct HtmlRenderer;
+
+pub struct MarkdownRenderer;
+
+pub struct PlainRenderer;
+
+impl Renderer for HtmlRenderer {
+    fn tags(&self, entity_type: &TextEntityType, text: &str) -> Option<(String, String)> {
+        let simple = |tag: &str| Some((format!("<{}>", tag), format!("</{}>", tag)));
+        let link = |href: String| {
+            Some((
+                format!(r#"<a href="{}">"#, escape_html(&href)),
+                "</a>".to_string(),
+            ))
+        };
+        match entity_type {
+            TextEntityType::Bold(_) => simple("b"),
+            TextEntityType::Italic(_) => simple("i"),
+            TextEntityType::Underline(_) => simple("u"),
+            TextEntityType::Strikethrough(_) => simple("s"),
+            TextEntityType::Code(_) => simple("code"),
+            TextEntityType::Pre(_) => simple("pre"),
+            TextEntityType::PreCode(pre_code) => Some((
+                format!(
+                    r#"<pre><code class="language-{}">"#,
+                    escape_html(pre_code.language())
+                ),
+                "</code></pre>".to_string(),
+            )),
+            TextEntityType::TextUrl(u) => link(u.url().clone()),
+            TextEntityType::Url(_) => link(url_href(text)),
+            TextEntityType::Mention(_) => link(mention_href(text)),
+            TextEntityType::MentionName(m) => link(mention_name_href(m.user_id())),
+            TextEntityType::EmailAddress(_) => link(format!("mailto:{}", text)),
+            TextEntityType::PhoneNumber(_) => link(format!("tel:{}", text)),
+            TextEntityType::Hashtag(_)
+            | TextEntityType::BotCommand(_)
+            | TextEntityType::Cashtag(_)
+            | TextEntityType::_Default(_) => None,
+        }
+    }
+
+    fn escape(&self, text: &str, _verbatim: bool) -> String {
+        escape_html(text)
+    }
+}
+
+impl Renderer for MarkdownRenderer {
+    fn tags(&self, entity_type: &TextEntityType, text: &str) -> Option<(String, String)> {
+        let simple = |tag: &str| Some((tag.to_string(), tag.to_string()));
+        let link = |href: String| {
+            Some((
+                "[".to_string(),
+                format!("]({})", href.replace(' ', "%20").replace(')', "%29")),
+            ))
+        };
+        match entity_type {
+            TextEntityType::Bold(_) => simple("**"),
+            TextEntityType::Italic(_) => simple("_"),
+            TextEntityType::Strikethrough(_) => simple("~~"),
+            TextEntityType::Code(_) => simple("`"),
+            TextEntityType::Pre(_) => Some(("```\n".to_string(), "\n```".to_string())),
+            TextEntityType::PreCode(pre_code) => {
+                Some((format!("```{}\n", pre_code.language()), "\n```".to_string()))
+            }
+            TextEntityType::TextUrl(u) => link(u.url().clone()),
+            TextEntityType::Url(_) => link(url_href(text)),
+            TextEntityType::Mention(_) => link(mention_href(text)),
+            TextEntityType::MentionName(m) => link(mention_name_href(m.user_id())),
+            TextEntityType::EmailAddress(_) => link(format!("mailto:{}", text)),
+            TextEntityType::PhoneNumber(_) => link(format!("tel:{}", text)),
+            // markdown has no underline
+            TextEntityType::Underline(_)
+            | TextEntityType::Hashtag(_)
+            | TextEntityType::BotCommand(_)
+            | TextEntityType::Cashtag(_)
+            | TextEntityType::_Default(_) => None,
+        }
+    }
+
+    fn escape(&self, text: &str, verbatim: bool) -> String {
+        if verbatim {
+            return text.to_string();
+        }
+        let mut escaped = String::with_capacity(text.len());
+        for ch in text.chars() {
+            if let '\\' | '`' | '*' | '_' | '[' | ']' | '<' | '>' | '~' = ch {
+                escaped.push('\\');
+            }
+            escaped.push(ch);
+        }
+        escaped
+    }
+}
+
+impl Renderer for PlainRenderer {
+    fn tags(&self, entity_type: &TextEntityType, _text: &str) -> Option<(String, String)> {
+        match entity_type {
+            // keep link target, it's not a part of the text
+            TextEntityType::TextUrl(u) => Some(("".to_string(), format!(" ({})", u.url()))),
+            _ => None,
+        }
+    }
+
+    fn escape(&self, text: &str, _verbatim: bool) -> String {
+        text.to_string()
+    }
+}
+
+struct Span {
+    start: usize,
+    end: usize,
+    verbatim: bool,
+    open: String,
+    close: String,
+}
+
+/// Renders `FormattedText` with the specified renderer.
+///
+/// Entity offsets are measured in UTF-16 code units. Overlapping entities are split,
+/// so markup is always properly nested.
+pub fn render(formatted_text: &FormattedText, renderer: &dyn Renderer) -> String {
+    let units = formatted_text.text().encode_utf16().collect::<Vec<u16>>();
+    let mut spans = vec![];
+    for entity in formatted_text.entities() {
+        if entity.offset() < 0 || entity.length() <= 0 {
+            warn!("skip empty text entity: {:?}", entity);
+            continue;
+        }
+        let start = entity.offset() as usize;
+        let end = start + entity.length() as usize;
+        if end > units.len() {
+            warn!("skip text entity out of text bounds: {:?}", entity);
+            continue;
+        }
+        let text = String::from_utf16_lossy(&units[start..end]);
+        if let Some((open, close)) = renderer.tags(entity.type_(), &text) {
+            spans.push(Span {
+                start,
+                end,
+                verbatim: is_verbatim(entity.type_()),
+                open,
+                close,
+            });
+        }
+    }
+    // outer entities go first
+    spans.sort_by(|a, b| a.start.cmp(&b.start).then(b.end.cmp(&a.end)));
+    let mut boundaries = spans
+        .iter()
+        .flat_map(|s| vec![s.start, s.end])
+        .collect::<Vec<usize>>();
+    boundaries.sort_unstable();
+    boundaries.dedup();
+
+    let mut result = String::new();
+    // indexes of opened spans
+    let mut opened: Vec<usize> = vec![];
+    let mut next_span = 0;
+    let mut position = 0;
+    for boundary in boundaries {
+        let verbatim = opened.iter().any(|i| spans[*i].verbatim);
+        let text = String::from_utf16_lossy(&units[position..boundary]);
+        result.push_str(&renderer.escape(&text, verbatim));
+        position = boundary;
+
+        // close everything above the lowest ending span, then reopen spans which continue
+        if let Some(lowest) = opened.iter().position(|i| spans[*i].end == boundary) {
+            let mut reopen = vec![];
+            while opened.len() > lowest {
+                let i = opened.pop().unwrap();
+                result.push_str(&spans[i].close);
+                if spans[i].end > boundary {
+                    reopen.push(i);
+                }
+            }
+            for i in reopen.into_iter().rev() {
+                result.push_str(&spans[i].open);
+                opened.push(i);
+            }
+        }
+        while next_span < spans.len() && spans[next_span].start == boundary {
+            result.push_str(&spans[next_span].open);
+            opened.push(next_span);
+            next_span += 1;
+        }
+    }
+    let text = String::from_utf16_lossy(&units[position..]);
+    result.push_str(&renderer.escape(&text, false));
+    result
+}
+
+fn is_verbatim(entity_type: &TextEntityType) -> bool {
+    matches!(
+        entity_type,
+        TextEntityType::Code(_) | TextEntityType::Pre(_) | TextEntityType::PreCode(_)
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(ch),
+        }
+    }
+    escaped
+}
+
+fn url_href(text: &str) -> String {
+    if text.contains("://") {
+        text.to_string()
+    } else {
+        format!("http://{}", text)
+    }
+}
+
+fn mention_href(text: &str) -> String {
+    format!("https://t.me/{}", text.trim_start_matches('@'))
+}
+
+fn mention_name_href(user_id: i64) -> String {
+    format!("tg://user?id={}", user_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{render, HtmlRenderer, MarkdownRenderer, PlainRenderer};
+    use tg_collector::FormattedText;
+
+    #[test]
+    fn test_render() {
+        let json_data = r#"{"@type":"formattedText","@extra":"","text":"Read <this> at example.com, ask @agg_r or Bob & run x = 1","entities":[{"@type":"textEntity","@extra":"","offset":0,"length":4,"type":{"@type":"textEntityTypeTextUrl","@extra":"","url":"https://example.com/a?b=1&c=2"}},{"@type":"textEntity","@extra":"","offset":15,"length":11,"type":{"@type":"textEntityTypeUrl","@extra":""}},{"@type":"textEntity","@extra":"","offset":32,"length":6,"type":{"@type":"textEntityTypeMention","@extra":""}},{"@type":"textEntity","@extra":"","offset":42,"length":3,"type":{"@type":"textEntityTypeMentionName","@extra":"","user_id":42}},{"@type":"textEntity","@extra":"","offset":52,"length":5,"type":{"@type":"textEntityTypeCode","@extra":""}}]}"#;
+        let formatted_text = FormattedText::from_json(json_data).unwrap();
+        assert_eq!(
+            render(&formatted_text, &HtmlRenderer),
+            r#"<a href="https://example.com/a?b=1&amp;c=2">Read</a> &lt;this&gt; at <a href="http://example.com">example.com</a>, ask <a href="https://t.me/agg_r">@agg_r</a> or <a href="tg://user?id=42">Bob</a> &amp; run <code>x = 1</code>"#
+        );
+        assert_eq!(
+            render(&formatted_text, &MarkdownRenderer),
+            r#"[Read](https://example.com/a?b=1&c=2) \<this\> at [example.com](http://example.com), ask [@agg\_r](https://t.me/agg_r) or [Bob](tg://user?id=42) & run `x = 1`"#
+        );
+        assert_eq!(
+            render(&formatted_text, &PlainRenderer),
+            "Read (https://example.com/a?b=1&c=2) <this> at example.com, ask @agg_r or Bob & run x = 1"
+        );
+    }
+}
@@ -1,13 +1,25 @@
+use super::downloads::{self, DownloadRetry};
+use super::handler::Handler;
+use super::parsers;
+use super::policy;
 use super::structs::*;
+use crate::config::{DownloadPolicy, SanitizeConfig, TextFormat, ThumbnailsConfig};
+use crate::file_store::{self, FileStore, LocalFileStore};
 use crate::models;
 use crate::result::{Error, Result};
+use crate::sanitize::Sanitizer;
 use crate::storage::Storage;
+use crate::thumbnails::{self, Thumbnail};
+use chrono::NaiveDateTime;
+use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
 use tg_collector::tg_client::TgClient;
-use tokio::sync::RwLock;
+use tg_collector::FormattedText;
+use tokio::stream::StreamExt;
+use tokio::sync::{watch, Mutex, RwLock};
 
-pub(super) const TELEGRAM: &str = "TELEGRAM";
+pub(crate) const TELEGRAM: &str = "TELEGRAM";
 
 pub struct TelegramSourceBuilder<S>
 where
@@ -18,9 +30,19 @@ where
     phone_number: String,
     log_verbosity_level: i32,
     database_directory: String,
+    account: Option<String>,
     max_download_queue_size: usize,
     log_download_state_secs_interval: u64,
     files_directory: String,
+    skip_known_forwards: bool,
+    comments_channels: Vec<String>,
+    text_format: TextFormat,
+    download_policy: DownloadPolicy,
+    source_download_policies: HashMap<String, DownloadPolicy>,
+    download_retry: DownloadRetry,
+    thumbnails: ThumbnailsConfig,
+    sanitize: SanitizeConfig,
+    file_store: Option<Arc<dyn FileStore + Send + Sync>>,
     storage: Option<S>,
 }
 
@@ -45,6 +67,16 @@ where
             api_hash: api_hash.to_string(),
             log_verbosity_level: 0,
             database_directory: "tdlib".to_string(),
+            account: None,
+            skip_known_forwards: false,
+            comments_channels: vec![],
+            text_format: TextFormat::default(),
+            download_policy: DownloadPolicy::default(),
+            source_download_policies: HashMap::new(),
+            download_retry: DownloadRetry::default(),
+            thumbnails: ThumbnailsConfig::default(),
+            sanitize: SanitizeConfig::default(),
+            file_store: None,
             storage: None,
         }
     }
@@ -64,6 +96,72 @@ where
         self
     }
 
+    /// Name of the account to tag sources with, phone number by default
+    pub fn with_account(mut self, account: &str) -> Self {
+        self.account = Some(account.to_string());
+        self
+    }
+
+    /// Don't save forwarded messages if the original message is already saved
+    pub fn with_skip_known_forwards(mut self, skip: bool) -> Self {
+        self.skip_known_forwards = skip;
+        self
+    }
+
+    /// Ingest discussion comments for channels with specified chat ids or usernames
+    pub fn with_comments_channels(mut self, channels: &[String]) -> Self {
+        self.comments_channels = channels.to_vec();
+        self
+    }
+
+    pub fn with_text_format(mut self, text_format: TextFormat) -> Self {
+        self.text_format = text_format;
+        self
+    }
+
+    /// Sets default download policy and its overrides by chat id or username
+    pub fn with_download_policy(
+        mut self,
+        policy: &DownloadPolicy,
+        source_policies: &HashMap<String, DownloadPolicy>,
+    ) -> Self {
+        self.download_policy = policy.clone();
+        self.source_download_policies = source_policies.clone();
+        self
+    }
+
+    /// Failed downloads are retried after `retry_secs_interval` doubled with every attempt,
+    /// downloads not finished during `timeout_secs` are failed
+    pub fn with_download_retry(
+        mut self,
+        retry_secs_interval: i32,
+        max_attempts: i32,
+        timeout_secs: i32,
+    ) -> Self {
+        self.download_retry = DownloadRetry {
+            retry_secs_interval,
+            max_attempts,
+            timeout_secs,
+        };
+        self
+    }
+
+    pub fn with_sanitize(mut self, sanitize: &SanitizeConfig) -> Self {
+        self.sanitize = sanitize.clone();
+        self
+    }
+
+    pub fn with_thumbnails(mut self, thumbnails: &ThumbnailsConfig) -> Self {
+        self.thumbnails = thumbnails.clone();
+        self
+    }
+
+    /// Store for downloaded files, local `files_directory` by default
+    pub fn with_file_store(mut self, file_store: Arc<dyn FileStore + Send + Sync>) -> Self {
+        self.file_store = Some(file_store);
+        self
+    }
+
     pub fn build(self) -> TelegramSource<S> {
         if self.storage.is_none() {
             panic!("storage not set")
@@ -77,11 +175,28 @@ where
             phone_number: self.phone_number.clone(),
             max_download_queue_size: self.max_download_queue_size,
         };
+        let (auth_state, auth_state_receiver) = watch::channel(AuthState::default());
         TelegramSource {
             collector: Arc::new(RwLock::new(tg_collector::tg_client::TgClient::new(
                 &tg_conf,
             ))),
-            files_directory: self.files_directory.clone(),
+            tg_conf,
+            handler: Mutex::new(None),
+            account: self.account.unwrap_or(self.phone_number),
+            auth_state,
+            auth_state_receiver,
+            file_store: self
+                .file_store
+                .unwrap_or_else(|| Arc::new(LocalFileStore::new(&self.files_directory))),
+            pending_source_images: Mutex::new(HashMap::new()),
+            skip_known_forwards: self.skip_known_forwards,
+            comments_channels: self.comments_channels,
+            text_format: self.text_format,
+            download_policy: self.download_policy,
+            source_download_policies: self.source_download_policies,
+            download_retry: self.download_retry,
+            thumbnails: self.thumbnails,
+            sanitizer: Sanitizer::new(&self.sanitize),
             storage: self.storage.unwrap(),
         }
     }
@@ -91,7 +206,23 @@ where
     S: Storage + Send + Sync,
 {
     pub(super) collector: Arc<RwLock<TgClient>>,
-    pub(super) files_directory: String,
+    tg_conf: tg_collector::config::Config,
+    // set when the source runs: forwarder of the client updates and the client thread
+    pub(super) handler: Mutex<Option<(Handler, std::thread::JoinHandle<()>)>>,
+    pub(super) account: String,
+    pub(super) auth_state: watch::Sender<AuthState>,
+    auth_state_receiver: watch::Receiver<AuthState>,
+    pub(super) file_store: Arc<dyn FileStore + Send + Sync>,
+    // chat photos requested for download: remote file id -> source id
+    pub(super) pending_source_images: Mutex<HashMap<String, i32>>,
+    pub(super) skip_known_forwards: bool,
+    pub(super) comments_channels: Vec<String>,
+    pub(super) text_format: TextFormat,
+    pub(super) download_policy: DownloadPolicy,
+    pub(super) source_download_policies: HashMap<String, DownloadPolicy>,
+    pub(super) download_retry: DownloadRetry,
+    pub(super) thumbnails: ThumbnailsConfig,
+    sanitizer: Sanitizer,
     pub(super) storage: S,
 }
 
@@ -117,26 +248,151 @@ where
         )
     }
 
+    pub fn account(&self) -> &str {
+        &self.account
+    }
+
+    pub fn auth_state(&self) -> AuthState {
+        self.auth_state_receiver.borrow().clone()
+    }
+
+    /// Returns stream of the auth state changes, starting with the current state
+    pub fn auth_state_stream(&self) -> watch::Receiver<AuthState> {
+        self.auth_state_receiver.clone()
+    }
+
+    pub async fn submit_auth_code(&self, code: &str) -> Result<()> {
+        Ok(self
+            .collector
+            .read()
+            .await
+            .check_authentication_code(code)
+            .await?)
+    }
+
+    pub async fn submit_auth_password(&self, password: &str) -> Result<()> {
+        Ok(self
+            .collector
+            .read()
+            .await
+            .check_authentication_password(password)
+            .await?)
+    }
+
+    pub async fn log_out(&self) -> Result<()> {
+        Ok(self.collector.read().await.log_out().await?)
+    }
+
+    /// Replaces closed client with a new one, which goes through the login states again:
+    /// `WaitPhoneNumber`, `WaitCode` and `WaitPassword` if the account has 2FA enabled.
+    ///
+    /// Download worker and other users of the collector switch to the new client.
+    /// Closed client is stopped, updates of the new one go to the running forwarder.
+    pub async fn restart_client(&self) -> Result<()> {
+        let state = self.auth_state();
+        if !state.needs_restart() {
+            return Err(Error::AuthStateConflict(format!(
+                "client of {} isn't closed: {:?}",
+                self.account, state
+            )));
+        }
+        let mut handler = self.handler.lock().await;
+        let (tg_handler, client_thread) = match handler.take() {
+            Some(handler) => handler,
+            None => {
+                return Err(Error::AuthStateConflict(format!(
+                    "client of {} isn't started",
+                    self.account
+                )))
+            }
+        };
+        let mut closed = std::mem::replace(
+            &mut *self.collector.write().await,
+            TgClient::new(&self.tg_conf),
+        );
+        closed.stop();
+        if tokio::task::spawn_blocking(move || client_thread.join())
+            .await
+            .map_or(true, |joined| joined.is_err())
+        {
+            warn!("closed client of {} panicked", self.account);
+        }
+        if self.auth_state.broadcast(AuthState::Unknown).is_err() {
+            debug!("no auth state listeners");
+        }
+        let client_thread = tg_handler.start_client().await;
+        *handler = Some((tg_handler, client_thread));
+        Ok(())
+    }
+
+    /// Whether updates of the source are handled by this account.
+    ///
+    /// When several accounts are in the same chat, only the account which owns the source
+    /// saves its records; sources without account are owned by everyone.
+    pub(super) fn owns(&self, source: &models::Source) -> bool {
+        match &source.account {
+            None => true,
+            Some(account) => account == &self.account,
+        }
+    }
+
+    /// Joins the chat of the source and makes this account the source owner
+    pub(super) async fn join_source(&self, source: &models::Source) -> Result<()> {
+        let chat_id = parsers::source_chat_id(source)?;
+        self.collector.read().await.join_chat(&chat_id).await?;
+        self.storage
+            .set_source_account(source.id, Some(self.account.clone()))
+            .await
+    }
+
+    /// Renders message text to the configured format.
+    ///
+    /// Returns rendered content and raw content to keep with the record.
+    pub(super) fn render_content(&self, text: &FormattedText) -> (String, Option<String>) {
+        let content = parsers::parse_formatted_text(text, self.text_format);
+        let content = match self.text_format {
+            TextFormat::Html => self.sanitizer.clean(&content, None),
+            TextFormat::Markdown | TextFormat::Plain => content,
+        };
+        (content, serde_json::to_string(text).ok())
+    }
+
+    pub(super) fn download_policy(&self, source: &models::Source) -> &DownloadPolicy {
+        self.source_download_policies
+            .get(&source.origin)
+            .or_else(|| self.source_download_policies.get(&source.external_link))
+            .unwrap_or(&self.download_policy)
+    }
+
     /// Handles new `TelegramFile`.
     ///
     /// Here is `TelegramFile` lifecycle:
     ///     1. new file found during `TelegramUpdate::Message` parse
-    ///     2. file downloads with `handle_file_update`
+    ///     2. file downloads with `handle_file_update` unless download policy of the source
+    ///        skips it; skipped files are saved with the reason
     ///     3. `TelegramUpdate::File` update received when download finished
-    ///     4. file moves to `files_directory`
+    ///     4. thumbnails of images and previews of animations are generated,
+    ///        file and its thumbnails move to `file_store`
+    ///
+    /// Download state is kept in storage, so `downloads::run_download_worker` resumes
+    /// interrupted downloads and retries failed ones.
+    ///
+    /// Chat photos follow the same lifecycle, see `handle_source_photo`.
 
     pub(super) async fn handle_new_files(
         &self,
         files: &[TelegramFileWithMeta],
         record_id: i32,
+        source: &models::Source,
     ) -> Result<()> {
+        let skip_reasons = policy::skip_reasons(self.download_policy(source), files);
         let db_files = files
             .iter()
-            .map(|file| {
+            .zip(skip_reasons.iter())
+            .map(|(file, skipped)| {
                 let meta: Option<String>;
                 let type_: String;
 
-                // TODO add posibility to disable particular types from config
                 match &file.file_type {
                     FileType::Document => {
                         type_ = "DOCUMENT".to_string();
@@ -160,26 +416,99 @@ where
                     record_id,
                     type_,
                     meta,
+                    download_state: match skipped {
+                        None => models::DOWNLOAD_QUEUED.to_string(),
+                        Some(_) => models::DOWNLOAD_SKIPPED.to_string(),
+                    },
+                    skipped: skipped.clone(),
+                    original_id: None,
                 }
             })
             .collect();
         self.storage.save_files(db_files).await?;
-        for f in files {
-            match self
-                .collector
-                .write()
-                .await
-                .download_file(f.path.remote_file.parse().unwrap())
-                .await
+        for (f, skipped) in files.iter().zip(skip_reasons.iter()) {
+            if let Some(reason) = skipped {
+                debug!("skip download of {}: {}", f.path.remote_id, reason);
+                continue;
+            }
+            // file stays queued if it's not found, worker will pick it up
+            if let Some(db_file) = self
+                .storage
+                .get_file_by_remote_id(f.path.remote_id.clone())
+                .await?
             {
-                Ok(_) => {}
-                Err(e) => error!("telegram file download failed: {}", e),
+                downloads::request_download(&self.collector, &self.storage, &db_file).await?;
             }
         }
         Ok(())
     }
 
+    /// Handles chat photo of the source.
+    ///
+    /// Photo is requested for download only if it isn't the stored source image already,
+    /// it becomes source image in `handle_file_downloaded`. Download lost on restart
+    /// is requested again on the next photo check, as the image isn't replaced yet.
+    /// Source image is cleared if chat has no photo.
+    pub(super) async fn handle_source_photo(
+        &self,
+        source: &models::Source,
+        photo: Option<&FilePath>,
+    ) -> Result<()> {
+        match photo {
+            None => {
+                self.storage.set_source_image(source.id, None, None).await?;
+                self.remove_unreferenced(source.image.iter().cloned().collect())
+                    .await
+            }
+            Some(photo)
+                if source.image.is_some()
+                    && source.image_remote_id.as_ref() == Some(&photo.remote_id) =>
+            {
+                Ok(())
+            }
+            Some(photo) => {
+                self.pending_source_images
+                    .lock()
+                    .await
+                    .insert(photo.remote_id.clone(), source.id);
+                let remote_file = photo.remote_file.parse().map_err(|_| {
+                    Error::InvalidContent(format!("invalid remote file: {}", photo.remote_file))
+                })?;
+                self.collector
+                    .write()
+                    .await
+                    .download_file(remote_file)
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+
     pub(super) async fn handle_file_downloaded(&self, file: &TelegramFile) -> Result<()> {
+        let source_id = self
+            .pending_source_images
+            .lock()
+            .await
+            .remove(&file.remote_id);
+        if let Some(source_id) = source_id {
+            let source = self
+                .storage
+                .get_source(source_id)
+                .await?
+                .ok_or(Error::SourceNotFound)?;
+            let (local_path, _) = self.put_to_file_store(&file.local_path, None).await?;
+            self.storage
+                .set_source_image(
+                    source_id,
+                    Some(local_path.clone()),
+                    Some(file.remote_id.clone()),
+                )
+                .await?;
+            // replaced image is removed if nothing else references its location
+            return self
+                .remove_unreferenced(source.image.into_iter().collect())
+                .await;
+        }
         let db_file = self
             .storage
             .get_file_by_remote_id(file.remote_id.clone())
@@ -187,23 +516,318 @@ where
         match db_file {
             None => warn!("unknown telegram file: {:?}", file),
             Some(mut db_file) => {
-                let file_name = Path::new(file.local_path.as_str()).file_name().unwrap();
-                let new_path = Path::new(self.files_directory.as_str()).join(&file_name);
-                tokio::fs::rename(&file.local_path, &new_path).await?;
-                // TODO: cross-platform?
-                db_file.local_path = Some(new_path.into_os_string().into_string().unwrap());
-                match db_file.file_name {
-                    None => {
-                        db_file.file_name = Some(file_name.to_os_string().into_string().unwrap())
+                let thumbnails = self.generate_thumbnails(&db_file, &file.local_path).await;
+                let (local_path, file_name) = match self
+                    .put_to_file_store(&file.local_path, db_file.file_name.as_deref())
+                    .await
+                {
+                    Ok(moved) => moved,
+                    Err(e) => {
+                        remove_thumbnails(thumbnails).await;
+                        self.storage
+                            .set_file_download_state(
+                                db_file.id,
+                                models::DOWNLOAD_FAILED.to_string(),
+                                Some(e.to_string()),
+                            )
+                            .await?;
+                        return Err(e);
                     }
+                };
+                db_file.local_path = Some(local_path);
+                db_file.download_state = models::DOWNLOAD_DONE.to_string();
+                db_file.download_error = None;
+                match db_file.file_name {
+                    None => db_file.file_name = Some(file_name),
                     Some(_) => {}
                 }
-                self.storage.save_file(db_file).await?;
+                if let Err(e) = self.storage.save_file(db_file.clone()).await {
+                    remove_thumbnails(thumbnails).await;
+                    return Err(e);
+                }
+                if !thumbnails.is_empty() {
+                    self.save_thumbnails(&db_file, thumbnails).await?;
+                }
             }
         }
         Ok(())
     }
 
+    /// Generates thumbnails of the downloaded image or preview of the animation.
+    ///
+    /// Generation failures don't fail the download, they are logged only.
+    async fn generate_thumbnails(
+        &self,
+        db_file: &models::File,
+        local_path: &str,
+    ) -> Vec<Thumbnail> {
+        if self.thumbnails.sizes.is_empty() {
+            return vec![];
+        }
+        let local_path = Path::new(local_path);
+        let result = match db_file.type_.as_str() {
+            "IMAGE" => thumbnails::image_thumbnails(local_path, &self.thumbnails).await,
+            "ANIMATION" => thumbnails::animation_thumbnails(local_path, &self.thumbnails).await,
+            _ => return vec![],
+        };
+        match result {
+            Ok(thumbnails) => thumbnails,
+            Err(e) => {
+                warn!("thumbnails of file {} not generated: {}", db_file.id, e);
+                vec![]
+            }
+        }
+    }
+
+    /// Saves thumbnails as files derived from the original and updates record image
+    async fn save_thumbnails(
+        &self,
+        original: &models::File,
+        thumbnails: Vec<Thumbnail>,
+    ) -> Result<()> {
+        let type_ = match original.type_.as_str() {
+            "ANIMATION" => models::FILE_PREVIEW,
+            _ => models::FILE_THUMBNAIL,
+        };
+        let mut db_files = vec![];
+        let mut thumbnails = thumbnails.into_iter();
+        while let Some(thumbnail) = thumbnails.next() {
+            let (local_path, file_name) = match self
+                .put_to_file_store(&thumbnail.path.to_string_lossy(), None)
+                .await
+            {
+                Ok(moved) => moved,
+                Err(e) => {
+                    remove_thumbnails(std::iter::once(thumbnail).chain(thumbnails)).await;
+                    self.remove_stored_thumbnails(&db_files).await;
+                    return Err(e);
+                }
+            };
+            let meta = ImageMeta {
+                width: thumbnail.width as i64,
+                height: thumbnail.height as i64,
+            };
+            db_files.push(models::NewFile {
+                record_id: original.record_id,
+                kind: TELEGRAM.to_string(),
+                local_path: Some(local_path),
+                remote_path: original.remote_path.clone(),
+                remote_id: Some(format!(
+                    "{}_{}x{}",
+                    original.remote_id.clone().unwrap_or_default(),
+                    meta.width,
+                    meta.height
+                )),
+                file_name: Some(file_name),
+                type_: type_.to_string(),
+                meta: serde_json::to_string(&meta).ok(),
+                skipped: None,
+                download_state: models::DOWNLOAD_DONE.to_string(),
+                original_id: Some(original.id),
+            });
+        }
+        if let Err(e) = self.storage.save_files(db_files.clone()).await {
+            self.remove_stored_thumbnails(&db_files).await;
+            return Err(e);
+        }
+        self.update_record_image(original.record_id).await
+    }
+
+    /// Removes thumbnails moved to the file store, which aren't saved
+    async fn remove_stored_thumbnails(&self, db_files: &[models::NewFile]) {
+        let locations = db_files
+            .iter()
+            .filter_map(|f| f.local_path.clone())
+            .collect();
+        if let Err(e) = self.remove_unreferenced(locations).await {
+            warn!("thumbnails not removed: {}", e);
+        }
+    }
+
+    /// Removes stored files which aren't referenced by files or source images
+    pub(super) async fn remove_unreferenced(&self, locations: Vec<String>) -> Result<()> {
+        file_store::remove_unreferenced(&self.storage, self.file_store.as_ref(), locations).await
+    }
+
+    /// Sets the largest thumbnail or preview of the record as its image
+    async fn update_record_image(&self, record_id: i32) -> Result<()> {
+        let best = self
+            .storage
+            .get_record_files(record_id)
+            .await?
+            .into_iter()
+            .filter(|f| f.original_id.is_some() && f.download_state == models::DOWNLOAD_DONE)
+            .filter_map(|f| {
+                let meta: ImageMeta = serde_json::from_str(f.meta.as_ref()?).ok()?;
+                Some((meta.width * meta.height, f.local_path?))
+            })
+            .max_by_key(|(area, _)| *area);
+        match best {
+            Some((_, image)) => self.storage.set_record_image(record_id, Some(image)).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Moves downloaded file to `file_store`, returns location and file name
+    async fn put_to_file_store(
+        &self,
+        local_path: &str,
+        file_name: Option<&str>,
+    ) -> Result<(String, String)> {
+        let file_name = match file_name {
+            Some(file_name) => file_name.to_string(),
+            None => Path::new(local_path)
+                .file_name()
+                .unwrap()
+                .to_string_lossy()
+                .to_string(),
+        };
+        let location = self
+            .file_store
+            .put(Path::new(local_path), &file_name)
+            .await?;
+        Ok((location, file_name))
+    }
+
+    /// Fills forward attribution of the record.
+    ///
+    /// Record is linked to the source of the original message if this source is known.
+    /// Returns `false` if the original message is already saved and `skip_known_forwards` is set,
+    /// so the record must not be saved.
+    pub(super) async fn set_record_forward(
+        &self,
+        record: &mut models::NewRecord,
+        forward: Option<&TelegramForwardInfo>,
+    ) -> Result<bool> {
+        let forward = match forward {
+            None => return Ok(true),
+            Some(forward) => forward,
+        };
+        record.forward_origin = serde_json::to_string(&forward.origin).ok();
+        record.forward_date = Some(NaiveDateTime::from_timestamp(forward.date, 0));
+        if let ForwardOrigin::Channel {
+            chat_id,
+            message_id,
+            ..
+        } = &forward.origin
+        {
+            record.forward_source_record_id = Some(message_id.to_string());
+            let source = self
+                .storage
+                .get_source_by_origin(TELEGRAM.to_string(), chat_id.to_string())
+                .await?;
+            if let Some(source) = source {
+                record.forward_source_id = Some(source.id);
+                if self.skip_known_forwards
+                    && self
+                        .storage
+                        .get_record(source.id, message_id.to_string())
+                        .await?
+                        .is_some()
+                {
+                    return Ok(false);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    /// Links record to the record it replies to.
+    ///
+    /// Returns `false` if the replied record isn't saved.
+    pub(super) async fn link_reply(
+        &self,
+        record: &models::Record,
+        parent_source_id: i32,
+        parent_source_record_id: String,
+    ) -> Result<bool> {
+        match self
+            .storage
+            .get_record(parent_source_id, parent_source_record_id)
+            .await?
+        {
+            None => Ok(false),
+            Some(parent) => {
+                self.storage.set_record_parent(record.id, parent.id).await?;
+                Ok(true)
+            }
+        }
+    }
+
+    pub(super) fn comments_enabled(&self, source: &models::Source) -> bool {
+        self.comments_channels
+            .iter()
+            .any(|c| c == &source.origin || c == &source.external_link)
+    }
+
+    /// Saves discussion comments of the channel posts.
+    ///
+    /// Comments are saved as records of the channel source with the post as a parent,
+    /// replies between comments are kept as well.
+    pub(super) async fn synchronize_comments(
+        &self,
+        source: &models::Source,
+        chat_id: i64,
+        post_ids: &[i64],
+        until: i64,
+    ) -> Result<usize> {
+        let mut saved = 0;
+        for post_id in post_ids {
+            let post = match self
+                .storage
+                .get_record(source.id, post_id.to_string())
+                .await?
+            {
+                None => continue,
+                Some(post) => post,
+            };
+            let mut comments_stream = Box::pin(TgClient::get_message_thread_history_stream(
+                self.collector.clone(),
+                chat_id,
+                *post_id,
+                until,
+            ));
+            let mut comments = vec![];
+            let mut replies = HashMap::new();
+            while let Some(comment) = comments_stream.next().await {
+                let comment = comment?;
+                let (content, raw_content) =
+                    match parsers::parse_message_content(comment.content()).await {
+                        Ok((Some(text), _)) => self.render_content(&text),
+                        Ok((None, _)) | Err(Error::UpdateNotSupported(_)) => continue,
+                        Err(e) => return Err(e),
+                    };
+                let source_record_id = comment_record_id(comment.chat_id(), comment.id());
+                if let Some(reply_to) = parsers::parse_reply_to(comment.reply_to_message_id()) {
+                    replies.insert(
+                        source_record_id.clone(),
+                        comment_record_id(comment.chat_id(), reply_to),
+                    );
+                }
+                comments.push(models::NewRecord {
+                    title: None,
+                    source_record_id,
+                    source_id: source.id,
+                    content,
+                    date: Some(NaiveDateTime::from_timestamp(comment.date(), 0)),
+                    image: None,
+                    parent_id: Some(post.id),
+                    raw_content,
+                    ..Default::default()
+                });
+            }
+            let comments = self.storage.save_records(comments).await?;
+            saved += comments.len();
+            for comment in &comments {
+                if let Some(reply_to) = replies.get(&comment.source_record_id) {
+                    self.link_reply(comment, source.id, reply_to.clone())
+                        .await?;
+                }
+            }
+        }
+        Ok(saved)
+    }
+
     pub(super) async fn handle_record_inserted(
         &self,
         chat_id: i64,
@@ -232,3 +856,20 @@ where
         }
     }
 }
+
+/// Comments live in the discussion group, so chat id is a part of their record id
+fn comment_record_id(chat_id: i64, message_id: i64) -> String {
+    format!("{}_{}", chat_id, message_id)
+}
+
+/// Removes temp files of the thumbnails which aren't moved to the file store
+async fn remove_thumbnails(thumbnails: impl IntoIterator<Item = Thumbnail>) {
+    for thumbnail in thumbnails {
+        match tokio::fs::remove_file(&thumbnail.path).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                warn!("{} not removed: {}", thumbnail.path.display(), e)
+            }
+            _ => {}
+        }
+    }
+}
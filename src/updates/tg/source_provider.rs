@@ -1,9 +1,12 @@
+use super::downloads;
 use super::handler::Handler;
-use super::parsers;
+use super::parsers::{self, ChatLink};
 use super::TelegramSource;
+use super::TELEGRAM;
 use crate::models;
 use crate::result::{Error, Result};
 use crate::storage::Storage;
+use crate::tools;
 use crate::updates::{Source, SourceData, SourceProvider};
 use async_trait::async_trait;
 use chrono::NaiveDateTime;
@@ -11,39 +14,174 @@ use std::collections::HashMap;
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tg_collector::tg_client::TgClient;
+use tg_collector::FormattedText;
 use tokio::stream::StreamExt;
 use tokio::sync::{mpsc, Mutex};
 
+/// Number of messages in the source candidate preview
+const SAMPLE_ITEMS: usize = 3;
+/// Sample messages are searched during this period
+const SAMPLE_ITEMS_DEPTH: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
 #[async_trait]
 impl<S> SourceProvider for TelegramSource<S>
 where
-    S: Storage + Send + Sync,
+    S: Storage + Send + Sync + Clone + 'static,
 {
     fn get_source(&self) -> Source {
         Source::Telegram
     }
 
     async fn run(&self, updates_sender: Arc<Mutex<mpsc::Sender<Result<SourceData>>>>) {
-        let mut tg_handler = Handler::new(updates_sender, self.collector.clone());
-        tg_handler.run().await;
+        let mut tg_handler = Handler::new(updates_sender, self.collector.clone(), &self.account);
+        let client_thread = tg_handler.run().await;
+        *self.handler.lock().await = Some((tg_handler, client_thread));
+        let collector = self.collector.clone();
+        let storage = self.storage.clone();
+        let account = self.account.clone();
+        let retry = self.download_retry.clone();
+        tokio::spawn(async move {
+            downloads::run_download_worker(collector, storage, account, retry).await
+        });
+    }
+
+    async fn join(&self, source: &models::Source) -> Result<()> {
+        self.join_source(source).await
+    }
+
+    async fn leave(&self, source: &models::Source) -> Result<()> {
+        let chat_id = parsers::source_chat_id(source)?;
+        self.collector.read().await.leave_chat(&chat_id).await?;
+        Ok(())
+    }
+
+    async fn remove_files(&self, source: &models::Source, files: &[models::File]) -> Result<()> {
+        let locations = files
+            .iter()
+            .filter_map(|f| f.local_path.clone())
+            .chain(source.image.clone())
+            .collect();
+        self.remove_unreferenced(locations).await
+    }
+
+    async fn discover_sources(&self, query: &str) -> Result<Vec<models::SourceCandidate>> {
+        let link = parsers::parse_chat_link(query);
+        let channels = match &link {
+            Some(ChatLink::Invite(link)) => return Ok(vec![self.invite_candidate(link).await?]),
+            // users aren't sources
+            Some(ChatLink::Phone(_)) => return Ok(vec![]),
+            // private channel is found only if the account is its member
+            Some(ChatLink::ChatId(chat_id)) => self
+                .collector
+                .read()
+                .await
+                .get_channel(*chat_id)
+                .await?
+                .into_iter()
+                .collect::<Vec<_>>(),
+            Some(ChatLink::Username(username)) => {
+                self.collector
+                    .read()
+                    .await
+                    .search_public_chats(username)
+                    .await?
+            }
+            None => {
+                self.collector
+                    .read()
+                    .await
+                    .search_public_chats(query)
+                    .await?
+            }
+        };
+        let username = match link {
+            Some(ChatLink::Username(username)) => Some(username),
+            _ => None,
+        };
+        let mut candidates = vec![];
+        for ch in channels {
+            if let Some(username) = &username {
+                if !ch.username.eq_ignore_ascii_case(username) {
+                    continue;
+                }
+            }
+            let sample_items = match self.sample_items(ch.chat_id).await {
+                Ok(items) => items,
+                Err(e) => {
+                    warn!("can't get sample items of {}: {}", ch.chat_id, e);
+                    vec![]
+                }
+            };
+            candidates.push(parsers::channel_to_candidate(
+                ch,
+                &self.account,
+                sample_items,
+            ));
+        }
+        Ok(candidates)
     }
 
-    async fn search_source(&self, query: &str) -> Result<Vec<models::Source>> {
-        let channels = self
+    async fn subscribe(&self, candidate: &models::SourceCandidate) -> Result<models::Source> {
+        let invite_link = match parsers::parse_chat_link(&candidate.external_link) {
+            Some(ChatLink::Invite(link)) => Some(link),
+            _ => None,
+        };
+        let chat_id = match &invite_link {
+            Some(link) => self
+                .collector
+                .read()
+                .await
+                .join_chat_by_invite_link(link)
+                .await?
+                .id(),
+            None => {
+                let chat_id = candidate.origin.parse::<i64>().map_err(|_| {
+                    Error::SourceKindConflict(format!("not a chat: {}", candidate.origin))
+                })?;
+                let known = self
+                    .storage
+                    .get_source_by_origin(TELEGRAM.to_string(), candidate.origin.clone())
+                    .await?;
+                if let Some(source) = known.filter(|s| !self.owns(s)) {
+                    return Err(Error::SourceAccountConflict(format!(
+                        "{} is subscribed by {}, use join_source to move it",
+                        source.name,
+                        source.account.unwrap_or_default()
+                    )));
+                }
+                self.collector.read().await.join_chat(&chat_id).await?;
+                chat_id
+            }
+        };
+        let channel = self
             .collector
             .read()
             .await
-            .search_public_chats(query)
-            .await?;
-        let mut sources = vec![];
-        for ch in channels {
-            let source = parsers::channel_to_new_source(ch);
-            match self.storage.save_sources(vec![source]).await {
-                Ok(s) => sources.extend(s),
-                Err(e) => error!("{:?}", e),
-            }
+            .get_channel(chat_id)
+            .await?
+            .ok_or(Error::SourceNotFound)?;
+        let photo = parsers::channel_photo(&channel);
+        let mut new_source = parsers::channel_to_new_source(channel, &self.account);
+        // private chats have no username, invite link is the only way to reach them
+        if let (true, Some(link)) = (new_source.external_link.is_empty(), invite_link) {
+            new_source.external_link = link;
+        }
+        let source = self
+            .storage
+            .save_sources(vec![new_source])
+            .await?
+            .pop()
+            .unwrap();
+        // source may be known already, e.g. found by another account which keeps it
+        if source.account.is_none() {
+            self.storage
+                .set_source_account(source.id, Some(self.account.clone()))
+                .await?;
         }
-        Ok(sources)
+        if let Err(e) = self.handle_source_photo(&source, photo.as_ref()).await {
+            error!("{:?}", e)
+        }
+        Ok(source)
     }
 
     async fn synchronize(&self, secs_depth: i32) -> Result<()> {
@@ -54,13 +192,25 @@ where
         for channel in channels {
             debug!("going to sync {}", channel.title);
             let chat_id = channel.chat_id;
-            let source = parsers::channel_to_new_source(channel);
+            let photo = parsers::channel_photo(&channel);
+            let source = parsers::channel_to_new_source(channel, &self.account);
             let source = self
                 .storage
                 .save_sources(vec![source])
                 .await?
                 .pop()
                 .unwrap();
+            if !self.owns(&source) {
+                debug!("skip {}: synchronized by {:?}", source.name, source.account);
+                continue;
+            }
+            if source.state != models::SOURCE_ACTIVE {
+                debug!("skip {}: source is {}", source.name, source.state);
+                continue;
+            }
+            if let Err(e) = self.handle_source_photo(&source, photo.as_ref()).await {
+                error!("{:?}", e)
+            }
             let mut messages_stream = Box::pin(TgClient::get_chat_history_stream(
                 self.collector.clone(),
                 chat_id,
@@ -68,19 +218,29 @@ where
             ));
             let mut parsed_records = vec![];
             let mut files_by_rec = HashMap::new();
+            let mut replies_by_rec = HashMap::new();
             while let Some(message) = messages_stream.next().await {
                 match message {
                     Ok(message) => {
-                        let mut on_content = |c| {
+                        let mut on_content = |c: FormattedText| {
+                            let (content, raw_content) = self.render_content(&c);
                             let record = models::NewRecord {
                                 title: None,
                                 source_record_id: message.id().to_string(),
                                 source_id: source.id,
-                                content: c,
+                                content,
+                                raw_content,
                                 date: Some(NaiveDateTime::from_timestamp(message.date(), 0)),
                                 image: None,
+                                ..Default::default()
                             };
-                            parsed_records.push(record);
+                            let forward = parsers::parse_forward_info(message.forward_info());
+                            parsed_records.push((record, forward));
+                            if let Some(reply_to) =
+                                parsers::parse_reply_to(message.reply_to_message_id())
+                            {
+                                replies_by_rec.insert((message.id(), source.id), reply_to);
+                            }
                         };
                         let mut on_file = |f| {
                             files_by_rec.insert((message.id(), source.id), f);
@@ -100,21 +260,116 @@ where
                     Err(e) => return Err(Error::TgCollectorError(e)),
                 }
             }
-            debug!("get {} records for {}", parsed_records.len(), source.name);
-            let records = self.storage.save_records(parsed_records).await?;
+            let mut records_to_save = vec![];
+            for (mut record, forward) in parsed_records {
+                if self
+                    .set_record_forward(&mut record, forward.as_ref())
+                    .await?
+                {
+                    records_to_save.push(record);
+                }
+            }
+            debug!("get {} records for {}", records_to_save.len(), source.name);
+            let post_ids = records_to_save
+                .iter()
+                .map(|r| r.source_record_id.parse().unwrap())
+                .collect::<Vec<i64>>();
+            let records = self.storage.save_records(records_to_save).await?;
             for rec in &records {
+                let key: (i64, i32) = (rec.source_record_id.parse().unwrap(), rec.source_id);
+                if let Some(reply_to) = replies_by_rec.get(&key) {
+                    if let Err(e) = self
+                        .link_reply(rec, rec.source_id, reply_to.to_string())
+                        .await
+                    {
+                        error!("{:?}", e)
+                    }
+                }
                 let rec_files =
                     files_by_rec.get(&(rec.source_record_id.parse().unwrap(), rec.source_id));
                 match rec_files {
                     None => {}
                     Some(f) => {
-                        if let Err(e) = self.handle_new_files(f, rec.id).await {
+                        if let Err(e) = self.handle_new_files(f, rec.id, &source).await {
                             error!("{:?}", e)
                         };
                     }
                 }
             }
+            if self.comments_enabled(&source) {
+                match self
+                    .synchronize_comments(&source, chat_id, &post_ids, until.as_secs() as i64)
+                    .await
+                {
+                    Ok(saved) => debug!("got {} comments for {}", saved, source.name),
+                    Err(e) => error!("{:?}", e),
+                }
+            }
         }
         Ok(())
     }
 }
+
+impl<S> TelegramSource<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    /// Previews the chat by invite link without joining it.
+    ///
+    /// Invite link is kept as `external_link`, so `subscribe` joins by the link.
+    async fn invite_candidate(&self, link: &str) -> Result<models::SourceCandidate> {
+        let info = self
+            .collector
+            .read()
+            .await
+            .check_chat_invite_link(link)
+            .await?;
+        // chat id is known only if the chat is already accessible, e.g. it's public
+        let sample_items = match info.chat_id() {
+            0 => vec![],
+            chat_id => self.sample_items(chat_id).await.unwrap_or_default(),
+        };
+        Ok(models::SourceCandidate {
+            kind: TELEGRAM.to_string(),
+            origin: match info.chat_id() {
+                0 => link.to_string(),
+                chat_id => chat_id.to_string(),
+            },
+            name: info.title().clone(),
+            description: None,
+            image: info
+                .photo()
+                .as_ref()
+                .and_then(|p| tools::empty_string_as_option(p.small().local().path())),
+            external_link: link.to_string(),
+            account: Some(self.account.clone()),
+            sample_items,
+        })
+    }
+
+    /// Returns latest messages of the chat for preview
+    async fn sample_items(&self, chat_id: i64) -> Result<Vec<models::SampleItem>> {
+        let until = SystemTime::now().duration_since(UNIX_EPOCH).unwrap() - SAMPLE_ITEMS_DEPTH;
+        let mut messages_stream = Box::pin(TgClient::get_chat_history_stream(
+            self.collector.clone(),
+            chat_id,
+            until.as_secs() as i64,
+        ));
+        let mut items = vec![];
+        while let Some(message) = messages_stream.next().await {
+            let message = message?;
+            if let Ok((Some(content), _)) = parsers::parse_message_content(message.content()).await
+            {
+                items.push(models::SampleItem {
+                    title: None,
+                    content: self.render_content(&content).0,
+                    date: Some(NaiveDateTime::from_timestamp(message.date(), 0)),
+                });
+            }
+            if items.len() == SAMPLE_ITEMS {
+                break;
+            }
+        }
+        Ok(items)
+    }
+}
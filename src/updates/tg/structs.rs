@@ -1,11 +1,55 @@
 use crate::tools;
-use serde::Serialize;
-use tg_collector::{Animation, PhotoSize, Poll, PollOption, Sticker};
+use serde::{Deserialize, Serialize};
+use tg_collector::{Animation, FormattedText, PhotoSize, Poll, PollOption, Sticker};
 
 #[derive(Debug)]
 pub enum TelegramUpdate {
     FileDownloadFinished(TelegramFile),
     Message(TelegramMessage),
+    ChatTitle(TelegramChatTitle),
+    ChatPhoto(TelegramChatPhoto),
+    AuthState(AuthState),
+}
+
+/// Authorization state of the account, see tdlib `AuthorizationState`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum AuthState {
+    /// Client isn't started yet
+    Unknown,
+    WaitTdlibParameters,
+    WaitPhoneNumber,
+    /// Login code is sent, submit it with `submit_auth_code`
+    WaitCode {
+        phone_number: String,
+    },
+    /// Account has 2FA enabled, submit password with `submit_auth_password`
+    WaitPassword {
+        hint: String,
+    },
+    /// Phone number isn't registered, the account must be signed up before login
+    WaitRegistration,
+    WaitOtherDeviceConfirmation {
+        link: String,
+    },
+    Ready,
+    LoggingOut,
+    Closing,
+    /// Session is closed, e.g. expired or logged out; log in again with `restart_client`
+    Closed,
+}
+
+impl AuthState {
+    /// Whether the client is closed and must be replaced to log in again
+    pub fn needs_restart(&self) -> bool {
+        matches!(self, Self::Closed)
+    }
+}
+
+impl Default for AuthState {
+    fn default() -> Self {
+        Self::Unknown
+    }
 }
 
 #[derive(Debug)]
@@ -13,11 +57,47 @@ pub struct TelegramMessage {
     pub message_id: i64,
     pub chat_id: i64,
     pub date: Option<i64>,
-    pub content: Option<String>,
+    pub content: Option<FormattedText>,
     pub files: Option<Vec<TelegramFileWithMeta>>,
+    pub forward: Option<TelegramForwardInfo>,
+    pub reply_to_message_id: Option<i64>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug)]
+pub struct TelegramForwardInfo {
+    pub origin: ForwardOrigin,
+    pub date: i64,
+}
+
+#[derive(Debug, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ForwardOrigin {
+    Channel {
+        chat_id: i64,
+        message_id: i64,
+        author_signature: Option<String>,
+    },
+    User {
+        user_id: i64,
+    },
+    HiddenUser {
+        sender_name: String,
+    },
+}
+
+#[derive(Debug)]
+pub struct TelegramChatTitle {
+    pub chat_id: i64,
+    pub title: String,
+}
+
+#[derive(Debug)]
+pub struct TelegramChatPhoto {
+    pub chat_id: i64,
+    pub photo: Option<FilePath>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
 pub struct ImageMeta {
     pub width: i64,
     pub height: i64,
@@ -112,6 +192,7 @@ pub struct FilePath {
     pub local_path: Option<String>,
     pub remote_file: String,
     pub remote_id: String,
+    pub size: i64,
 }
 
 impl FilePath {
@@ -120,6 +201,10 @@ impl FilePath {
             local_path: tools::empty_string_as_option(file.local().path().as_str()),
             remote_file: file.id().to_string(),
             remote_id: file.remote().unique_id().clone(),
+            size: match file.size() {
+                0 => file.expected_size(),
+                size => size,
+            },
         }
     }
 }
@@ -1,6 +1,7 @@
-use super::parsers::channel_to_new_source;
+use super::parsers::{channel_photo, channel_to_new_source};
 use super::TelegramSource;
 use super::TelegramUpdate;
+use super::TELEGRAM;
 use crate::models;
 use crate::result::{Error, Result};
 use crate::storage::Storage;
@@ -17,6 +18,9 @@ where
             TelegramUpdate::FileDownloadFinished(_) => Err(Error::UpdateNotSupported(
                 "FileDownloadFinished".to_string(),
             )),
+            TelegramUpdate::ChatTitle(_) => Err(Error::UpdateNotSupported("ChatTitle".to_string())),
+            TelegramUpdate::ChatPhoto(_) => Err(Error::UpdateNotSupported("ChatPhoto".to_string())),
+            TelegramUpdate::AuthState(_) => Err(Error::UpdateNotSupported("AuthState".to_string())),
             TelegramUpdate::Message(message) => {
                 self.collector
                     .read()
@@ -32,8 +36,14 @@ where
                 if chann.is_none() {
                     return Err(Error::SourceNotFound);
                 }
-                let s = channel_to_new_source(chann.unwrap());
-                Ok(self.storage.save_sources(vec![s]).await?.pop().unwrap())
+                let chann = chann.unwrap();
+                let photo = channel_photo(&chann);
+                let s = channel_to_new_source(chann, &self.account);
+                let source = self.storage.save_sources(vec![s]).await?.pop().unwrap();
+                if let Err(e) = self.handle_source_photo(&source, photo.as_ref()).await {
+                    error!("{:?}", e)
+                }
+                Ok(source)
             }
         }
     }
@@ -44,6 +54,51 @@ where
                 self.handle_file_downloaded(file).await?;
                 Ok(1)
             }
+            TelegramUpdate::AuthState(auth_state) => {
+                info!(
+                    "telegram account {} auth state: {:?}",
+                    self.account, auth_state
+                );
+                if self.auth_state.broadcast(auth_state.clone()).is_err() {
+                    debug!("no auth state listeners");
+                }
+                Ok(0)
+            }
+            TelegramUpdate::ChatTitle(chat_title) => {
+                let source = self
+                    .storage
+                    .get_source_by_origin(TELEGRAM.to_string(), chat_title.chat_id.to_string())
+                    .await?;
+                match source {
+                    None => {
+                        debug!("skip title update for unknown chat {}", chat_title.chat_id);
+                        Ok(0)
+                    }
+                    Some(source) => {
+                        self.storage
+                            .set_source_name(source.id, chat_title.title.clone())
+                            .await?;
+                        Ok(1)
+                    }
+                }
+            }
+            TelegramUpdate::ChatPhoto(chat_photo) => {
+                let source = self
+                    .storage
+                    .get_source_by_origin(TELEGRAM.to_string(), chat_photo.chat_id.to_string())
+                    .await?;
+                match source {
+                    None => {
+                        debug!("skip photo update for unknown chat {}", chat_photo.chat_id);
+                        Ok(0)
+                    }
+                    Some(source) => {
+                        self.handle_source_photo(&source, chat_photo.photo.as_ref())
+                            .await?;
+                        Ok(1)
+                    }
+                }
+            }
             TelegramUpdate::Message(message) => {
                 let mut sources = self
                     .storage
@@ -53,21 +108,55 @@ where
                     0 => self.create_source(updates).await?,
                     _ => sources.pop().unwrap(),
                 };
+                if !self.owns(&source) {
+                    trace!(
+                        "skip message of {}: handled by {:?}",
+                        source.name,
+                        source.account
+                    );
+                    return Ok(0);
+                }
+                if source.state != models::SOURCE_ACTIVE {
+                    trace!(
+                        "skip message of {}: source is {}",
+                        source.name,
+                        source.state
+                    );
+                    return Ok(0);
+                }
                 let message_id = message.message_id;
-                let created = self
-                    .storage
-                    .save_records(vec![models::NewRecord {
-                        title: None,
-                        image: None,
-                        date: message
-                            .date
-                            .map(|d| chrono::NaiveDateTime::from_timestamp(d, 0)),
-                        source_record_id: message_id.to_string(),
-                        source_id: source.id,
-                        content: message.content.clone().unwrap_or_default(),
-                    }])
+                let (content, raw_content) = match &message.content {
+                    None => (String::new(), None),
+                    Some(text) => self.render_content(text),
+                };
+                let mut record = models::NewRecord {
+                    title: None,
+                    image: None,
+                    date: message
+                        .date
+                        .map(|d| chrono::NaiveDateTime::from_timestamp(d, 0)),
+                    source_record_id: message_id.to_string(),
+                    source_id: source.id,
+                    content,
+                    raw_content,
+                    ..Default::default()
+                };
+                if !self
+                    .set_record_forward(&mut record, message.forward.as_ref())
                     .await?
-                    .pop();
+                {
+                    debug!("skip forward of known message: {:?}", message.forward);
+                    return Ok(0);
+                }
+                let created = self.storage.save_records(vec![record]).await?.pop();
+                if let (Some(rec), Some(reply_to)) = (&created, message.reply_to_message_id) {
+                    if let Err(e) = self
+                        .link_reply(rec, rec.source_id, reply_to.to_string())
+                        .await
+                    {
+                        error!("{}", e);
+                    }
+                }
                 match created {
                     None => {
                         if message.files.is_some() {
@@ -81,7 +170,7 @@ where
                     Some(rec) if message.files.is_some() => {
                         let files = message.files.as_ref().unwrap();
                         let (handle_file, handle_record) = tokio::join!(
-                            self.handle_new_files(files, rec.id),
+                            self.handle_new_files(files, rec.id, &source),
                             self.handle_record_inserted(
                                 message.chat_id,
                                 message_id,
@@ -0,0 +1,579 @@
+use super::{SourceData, SourceProvider, UpdatesHandler};
+use crate::config::SanitizeConfig;
+use crate::models;
+use crate::result::{Error, Result};
+use crate::sanitize::Sanitizer;
+use crate::storage::Storage;
+use crate::updates::Source;
+use async_trait::async_trait;
+use chrono::{DateTime, NaiveDateTime};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+use url::Url;
+
+mod server;
+
+pub(crate) const WEBHOOK: &str = "WEBHOOK";
+
+/// Length of the source key in the endpoint path
+const KEY_LEN: usize = 16;
+const TOKEN_LEN: usize = 32;
+/// Max number of items in one request
+const MAX_ITEMS: usize = 100;
+const MAX_ID_LEN: usize = 256;
+
+/// Request body of the webhook endpoint
+#[derive(Debug, Deserialize)]
+pub(crate) struct WebhookPayload {
+    pub items: Vec<WebhookItem>,
+}
+
+/// Item pushed to the webhook source
+#[derive(Debug, Deserialize)]
+pub(crate) struct WebhookItem {
+    /// Id of the item in the pushing system, records of the pushed again ids are updated
+    pub id: String,
+    pub title: Option<String>,
+    #[serde(default)]
+    pub content: String,
+    /// RFC 3339 date, time of receiving if not set
+    pub date: Option<String>,
+    pub link: Option<String>,
+    #[serde(default)]
+    pub attachments: Vec<WebhookAttachment>,
+}
+
+#[derive(Debug, Deserialize)]
+pub(crate) struct WebhookAttachment {
+    pub url: String,
+    pub name: Option<String>,
+    pub mime_type: Option<String>,
+}
+
+/// Invalid field of the pushed item, reported back to the caller
+#[derive(Debug, PartialEq, Serialize)]
+pub(crate) struct ValidationError {
+    /// Index of the item, `None` if the request itself is invalid
+    pub item: Option<usize>,
+    pub field: String,
+    pub message: String,
+}
+
+impl ValidationError {
+    fn new(item: Option<usize>, field: &str, message: &str) -> Self {
+        Self {
+            item,
+            field: field.to_string(),
+            message: message.to_string(),
+        }
+    }
+}
+
+/// Validated items of the webhook source
+#[derive(Debug)]
+pub struct WebhookUpdate {
+    pub source_id: i32,
+    pub items: Vec<WebhookRecord>,
+}
+
+#[derive(Debug)]
+pub struct WebhookRecord {
+    pub id: String,
+    pub title: Option<String>,
+    pub content: String,
+    pub date: NaiveDateTime,
+    pub link: Option<String>,
+    pub attachments: Vec<WebhookFile>,
+}
+
+#[derive(Debug)]
+pub struct WebhookFile {
+    pub url: String,
+    pub name: Option<String>,
+    /// File type as in Telegram records: `IMAGE`, `VIDEO`, `AUDIO` or `DOCUMENT`
+    pub type_: String,
+}
+
+pub struct WebhookSourceBuilder<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    listen_address: String,
+    public_url: String,
+    max_body_size: usize,
+    sanitize: SanitizeConfig,
+    storage: Option<S>,
+}
+
+impl<S> Default for WebhookSourceBuilder<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    fn default() -> Self {
+        Self {
+            listen_address: "0.0.0.0:8091".to_string(),
+            public_url: "http://localhost:8091".to_string(),
+            max_body_size: 1024 * 1024,
+            sanitize: SanitizeConfig::default(),
+            storage: None,
+        }
+    }
+}
+
+impl<S> WebhookSourceBuilder<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    /// Endpoints are served at `listen_address` and reachable at `public_url`
+    pub fn with_listen_address(mut self, listen_address: &str, public_url: &str) -> Self {
+        self.listen_address = listen_address.to_string();
+        self.public_url = public_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    pub fn with_max_body_size(mut self, max_body_size: usize) -> Self {
+        self.max_body_size = max_body_size;
+        self
+    }
+
+    pub fn with_sanitize(mut self, sanitize: &SanitizeConfig) -> Self {
+        self.sanitize = sanitize.clone();
+        self
+    }
+
+    pub fn with_storage(mut self, storage: S) -> Self {
+        self.storage = Some(storage);
+        self
+    }
+
+    pub fn build(self) -> WebhookSource<S> {
+        if self.storage.is_none() {
+            panic!("storage not passed");
+        }
+        WebhookSource {
+            listen_address: self
+                .listen_address
+                .parse()
+                .expect("invalid webhook listen address"),
+            public_url: self.public_url,
+            max_body_size: self.max_body_size,
+            sanitizer: Sanitizer::new(&self.sanitize),
+            storage: self.storage.unwrap(),
+        }
+    }
+}
+
+/// Sources which records are pushed by other systems to `/webhooks/{origin}`,
+/// requests are authorized by the source token
+pub struct WebhookSource<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    listen_address: SocketAddr,
+    public_url: String,
+    max_body_size: usize,
+    sanitizer: Sanitizer,
+    storage: S,
+}
+
+impl<S> WebhookSource<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    pub fn builder() -> WebhookSourceBuilder<S> {
+        WebhookSourceBuilder::default()
+    }
+
+    /// Adds source with a random endpoint, returns it with the token, which isn't kept
+    pub async fn add_source(&self, name: &str) -> Result<(models::Source, String)> {
+        let key = random_string(KEY_LEN);
+        let source = self
+            .storage
+            .save_sources(vec![models::NewSource {
+                name: name.to_string(),
+                external_link: format!("{}/webhooks/{}", self.public_url, key),
+                origin: key,
+                kind: WEBHOOK.to_string(),
+                image: None,
+                account: None,
+                feed_kind: None,
+            }])
+            .await?
+            .pop()
+            .ok_or(Error::SourceCreationError)?;
+        let token = self.reset_token(&source).await?;
+        Ok((source, token))
+    }
+
+    /// Replaces token of the source, requests with the previous one are rejected
+    pub async fn reset_token(&self, source: &models::Source) -> Result<String> {
+        let token = random_string(TOKEN_LEN);
+        self.storage
+            .save_webhook_token(models::NewWebhookToken {
+                source_id: source.id,
+                token_hash: token_hash(&token),
+            })
+            .await?;
+        Ok(token)
+    }
+}
+
+#[async_trait]
+impl<S> UpdatesHandler<WebhookUpdate> for WebhookSource<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    async fn create_source(&self, updates: &WebhookUpdate) -> Result<models::Source> {
+        // sources are added with `add_source`, updates are received only for existing ones
+        self.storage
+            .get_source(updates.source_id)
+            .await?
+            .ok_or(Error::SourceNotFound)
+    }
+
+    async fn process_updates(&self, updates: &WebhookUpdate) -> Result<usize> {
+        let source = self.create_source(updates).await?;
+        // source may be paused after the items were accepted
+        if source.state != models::SOURCE_ACTIVE {
+            debug!("skip updates of {} source {}", source.state, source.id);
+            return Ok(0);
+        }
+        let affected = self
+            .storage
+            .save_records(
+                updates
+                    .items
+                    .iter()
+                    .map(|i| models::NewRecord {
+                        date: Some(i.date),
+                        title: i.title.clone(),
+                        source_record_id: i.id.clone(),
+                        source_id: source.id,
+                        content: self.sanitizer.clean(&i.content, i.link.as_deref()),
+                        image: i
+                            .attachments
+                            .iter()
+                            .find(|a| a.type_ == "IMAGE")
+                            .map(|a| a.url.clone()),
+                        external_link: i.link.clone(),
+                        ..Default::default()
+                    })
+                    .collect::<Vec<models::NewRecord>>(),
+            )
+            .await?;
+        for record in &affected {
+            let item = match updates
+                .items
+                .iter()
+                .find(|i| i.id == record.source_record_id)
+            {
+                Some(item) => item,
+                None => continue,
+            };
+            let known = self.storage.get_record_files(record.id).await?;
+            let files = item
+                .attachments
+                .iter()
+                .filter(|a| !known.iter().any(|f| f.remote_path == a.url))
+                .map(|a| models::NewFile {
+                    record_id: record.id,
+                    kind: WEBHOOK.to_string(),
+                    local_path: None,
+                    remote_path: a.url.clone(),
+                    remote_id: None,
+                    file_name: a.name.clone(),
+                    type_: a.type_.clone(),
+                    meta: None,
+                    skipped: Some("attachments of the webhook records are linked".to_string()),
+                    download_state: models::DOWNLOAD_SKIPPED.to_string(),
+                    original_id: None,
+                })
+                .collect::<Vec<models::NewFile>>();
+            if !files.is_empty() {
+                self.storage.save_files(files).await?;
+            }
+        }
+        self.storage.set_source_scraped_now(source).await?;
+        Ok(affected.len())
+    }
+}
+
+#[async_trait]
+impl<S> SourceProvider for WebhookSource<S>
+where
+    S: Storage + Send + Sync + Clone + 'static,
+{
+    fn get_source(&self) -> Source {
+        Source::Webhook
+    }
+
+    async fn run(&self, updates_sender: Arc<Mutex<mpsc::Sender<Result<SourceData>>>>) {
+        let endpoint =
+            server::Endpoint::new(self.storage.clone(), updates_sender, self.max_body_size);
+        tokio::spawn(server::serve(self.listen_address, Arc::new(endpoint)));
+    }
+
+    async fn discover_sources(&self, _query: &str) -> Result<Vec<models::SourceCandidate>> {
+        // webhook sources are added by name, there is nothing to discover
+        Ok(vec![])
+    }
+
+    async fn subscribe(&self, candidate: &models::SourceCandidate) -> Result<models::Source> {
+        Err(Error::SourceKindConflict(format!(
+            "webhook source {} should be added with a token",
+            candidate.name
+        )))
+    }
+
+    async fn synchronize(&self, _secs_depth: i32) -> Result<()> {
+        // nothing to sync, items are pushed
+        Ok(())
+    }
+
+    async fn join(&self, _source: &models::Source) -> Result<()> {
+        Ok(())
+    }
+
+    async fn leave(&self, _source: &models::Source) -> Result<()> {
+        // disabled sources reject pushed items
+        Ok(())
+    }
+
+    async fn remove_files(&self, _source: &models::Source, _files: &[models::File]) -> Result<()> {
+        // attachments aren't downloaded
+        Ok(())
+    }
+}
+
+/// Checks pushed items, all errors are collected to report them at once
+pub(crate) fn validate_items(
+    items: Vec<WebhookItem>,
+    now: NaiveDateTime,
+) -> std::result::Result<Vec<WebhookRecord>, Vec<ValidationError>> {
+    let mut errors = vec![];
+    if items.is_empty() {
+        errors.push(ValidationError::new(None, "items", "no items"));
+    }
+    if items.len() > MAX_ITEMS {
+        errors.push(ValidationError::new(
+            None,
+            "items",
+            &format!("more than {} items", MAX_ITEMS),
+        ));
+    }
+    let mut records = vec![];
+    for (index, item) in items.into_iter().enumerate() {
+        let index = Some(index);
+        let id = item.id.trim().to_string();
+        if id.is_empty() || id.len() > MAX_ID_LEN {
+            errors.push(ValidationError::new(
+                index,
+                "id",
+                &format!("id should be 1 to {} bytes", MAX_ID_LEN),
+            ));
+        }
+        let title = item.title.filter(|t| !t.trim().is_empty());
+        if title.is_none() && item.content.trim().is_empty() {
+            errors.push(ValidationError::new(
+                index,
+                "content",
+                "title or content is required",
+            ));
+        }
+        let date = match item.date.as_deref().map(DateTime::parse_from_rfc3339) {
+            None => now,
+            Some(Ok(date)) => date.naive_utc(),
+            Some(Err(e)) => {
+                errors.push(ValidationError::new(index, "date", &e.to_string()));
+                now
+            }
+        };
+        if let Some(link) = &item.link {
+            if let Err(message) = validate_link(link) {
+                errors.push(ValidationError::new(index, "link", &message));
+            }
+        }
+        let mut attachments = vec![];
+        for (attachment_index, attachment) in item.attachments.into_iter().enumerate() {
+            if let Err(message) = validate_link(&attachment.url) {
+                let field = format!("attachments[{}].url", attachment_index);
+                errors.push(ValidationError::new(index, &field, &message));
+            }
+            attachments.push(WebhookFile {
+                type_: file_type(attachment.mime_type.as_deref()).to_string(),
+                url: attachment.url,
+                name: attachment.name,
+            });
+        }
+        records.push(WebhookRecord {
+            id,
+            title,
+            content: item.content,
+            date,
+            link: item.link,
+            attachments,
+        });
+    }
+    match errors.is_empty() {
+        true => Ok(records),
+        false => Err(errors),
+    }
+}
+
+fn validate_link(link: &str) -> std::result::Result<(), String> {
+    match Url::parse(link) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => Ok(()),
+        Ok(url) => Err(format!("{} links aren't supported", url.scheme())),
+        Err(e) => Err(e.to_string()),
+    }
+}
+
+fn file_type(mime_type: Option<&str>) -> &'static str {
+    match mime_type.and_then(|m| m.split('/').next()) {
+        Some("image") => "IMAGE",
+        Some("video") => "VIDEO",
+        Some("audio") => "AUDIO",
+        _ => "DOCUMENT",
+    }
+}
+
+fn random_string(len: usize) -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(len)
+        .collect::<String>()
+}
+
+pub(crate) fn token_hash(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_items, ValidationError, WebhookItem, WebhookPayload};
+    use chrono::NaiveDate;
+
+    fn parse(json: &str) -> Vec<WebhookItem> {
+        serde_json::from_str::<WebhookPayload>(json).unwrap().items
+    }
+
+    #[test]
+    fn test_validate_items() {
+        let now = NaiveDate::from_ymd(2021, 1, 22).and_hms(12, 0, 0);
+        let items = parse(
+            r#"{"items": [
+                {
+                    "id": "deploy-42",
+                    "title": "Deployed",
+                    "date": "2021-01-22T10:00:00+02:00",
+                    "link": "https://ci.example.com/deploys/42",
+                    "attachments": [
+                        {"url": "https://ci.example.com/42.png", "mime_type": "image/png"},
+                        {"url": "https://ci.example.com/42.log", "name": "42.log"}
+                    ]
+                },
+                {"id": "deploy-43", "content": "<p>Started</p>"}
+            ]}"#,
+        );
+        let records = validate_items(items, now).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(
+            records[0].date,
+            NaiveDate::from_ymd(2021, 1, 22).and_hms(8, 0, 0)
+        );
+        assert_eq!(records[0].attachments[0].type_, "IMAGE");
+        assert_eq!(records[0].attachments[1].type_, "DOCUMENT");
+        assert_eq!(records[0].attachments[1].name.as_deref(), Some("42.log"));
+        assert_eq!(records[1].date, now);
+        assert_eq!(records[1].title, None);
+    }
+
+    #[test]
+    fn test_validation_errors() {
+        let now = NaiveDate::from_ymd(2021, 1, 22).and_hms(12, 0, 0);
+        let items = parse(
+            r#"{"items": [
+                {"id": "ok", "title": "Valid"},
+                {
+                    "id": " ",
+                    "title": "",
+                    "date": "yesterday",
+                    "link": "javascript:alert(1)",
+                    "attachments": [{"url": "/relative.png"}]
+                }
+            ]}"#,
+        );
+        let errors = validate_items(items, now).unwrap_err();
+        assert_eq!(
+            errors
+                .iter()
+                .map(|e| e.field.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["id", "content", "date", "link", "attachments[0].url"]
+        );
+        assert!(errors.iter().all(|e| e.item == Some(1)));
+        assert_eq!(
+            validate_items(vec![], now).unwrap_err(),
+            vec![ValidationError::new(None, "items", "no items")]
+        );
+    }
+
+    #[cfg(feature = "pg-storage")]
+    #[tokio::test]
+    #[ignore]
+    async fn test_repush_updates_record() {
+        use super::{WebhookFile, WebhookRecord, WebhookSource, WebhookUpdate};
+        use crate::models;
+        use crate::storage::pg::tests::test_storage;
+        use crate::storage::Storage;
+        use crate::updates::UpdatesHandler;
+
+        let storage = test_storage();
+        let webhook = WebhookSource::builder()
+            .with_storage(storage.clone())
+            .build();
+        let (source, _) = webhook.add_source("test").await.unwrap();
+        let update = |content: &str| WebhookUpdate {
+            source_id: source.id,
+            items: vec![WebhookRecord {
+                id: "1".to_string(),
+                title: None,
+                content: content.to_string(),
+                date: NaiveDate::from_ymd(2021, 1, 22).and_hms(12, 0, 0),
+                link: None,
+                attachments: vec![WebhookFile {
+                    url: "https://example.com/1.png".to_string(),
+                    name: None,
+                    type_: "IMAGE".to_string(),
+                }],
+            }],
+        };
+        assert_eq!(webhook.process_updates(&update("first")).await.unwrap(), 1);
+        assert_eq!(webhook.process_updates(&update("second")).await.unwrap(), 1);
+        let record = storage
+            .get_record(source.id, "1".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.content, "second");
+        assert_eq!(storage.get_record_files(record.id).await.unwrap().len(), 1);
+
+        storage
+            .set_source_state(source.id, models::SOURCE_PAUSED.to_string())
+            .await
+            .unwrap();
+        assert_eq!(webhook.process_updates(&update("third")).await.unwrap(), 0);
+        let record = storage
+            .get_record(source.id, "1".to_string())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(record.content, "second");
+        storage.delete_source(source.id).await.unwrap();
+    }
+}
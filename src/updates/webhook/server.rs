@@ -0,0 +1,213 @@
+use super::{token_hash, validate_items, ValidationError, WebhookPayload, WebhookUpdate, WEBHOOK};
+use crate::models;
+use crate::result::Result;
+use crate::storage::Storage;
+use crate::tools::read_body;
+use crate::updates::SourceData;
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use serde_json::json;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tokio::sync::{mpsc, Mutex};
+
+/// Receives items of the webhook sources and passes them to the updates pipeline
+pub(crate) struct Endpoint<S> {
+    storage: S,
+    updates_sender: Arc<Mutex<mpsc::Sender<Result<SourceData>>>>,
+    max_body_size: usize,
+}
+
+impl<S> Endpoint<S>
+where
+    S: Storage + Send + Sync,
+{
+    pub fn new(
+        storage: S,
+        updates_sender: Arc<Mutex<mpsc::Sender<Result<SourceData>>>>,
+        max_body_size: usize,
+    ) -> Self {
+        Self {
+            storage,
+            updates_sender,
+            max_body_size,
+        }
+    }
+
+    /// Returns source of the key if the bearer token is its token
+    async fn authorize(
+        &self,
+        key: &str,
+        authorization: Option<&str>,
+    ) -> Result<std::result::Result<models::Source, StatusCode>> {
+        let source = match self
+            .storage
+            .get_source_by_origin(WEBHOOK.to_string(), key.to_string())
+            .await?
+        {
+            Some(source) => source,
+            None => return Ok(Err(StatusCode::NOT_FOUND)),
+        };
+        let token = match authorization.and_then(|a| a.strip_prefix("Bearer ")) {
+            Some(token) => token.trim(),
+            None => return Ok(Err(StatusCode::UNAUTHORIZED)),
+        };
+        match self.storage.get_webhook_token(source.id).await? {
+            Some(saved) if saved.token_hash == token_hash(token) => {}
+            _ => return Ok(Err(StatusCode::UNAUTHORIZED)),
+        }
+        match source.state.as_str() {
+            models::SOURCE_DISABLED => return Ok(Err(StatusCode::FORBIDDEN)),
+            // items of paused sources aren't saved, caller should retry after resume
+            models::SOURCE_PAUSED => return Ok(Err(StatusCode::CONFLICT)),
+            _ => {}
+        }
+        Ok(Ok(source))
+    }
+
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        let key = match request.uri().path().strip_prefix("/webhooks/") {
+            Some(key) if !key.is_empty() && !key.contains('/') => key.to_string(),
+            _ => return status(StatusCode::NOT_FOUND),
+        };
+        if request.method() != Method::POST {
+            return status(StatusCode::METHOD_NOT_ALLOWED);
+        }
+        let authorization = request
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .map(String::from);
+        let source = match self.authorize(&key, authorization.as_deref()).await {
+            Ok(Ok(source)) => source,
+            Ok(Err(code)) => return status(code),
+            Err(e) => {
+                error!("{}", e);
+                return status(StatusCode::INTERNAL_SERVER_ERROR);
+            }
+        };
+        let body = match read_body(request.into_body(), self.max_body_size).await {
+            Ok(body) => body,
+            Err(code) => return status(code),
+        };
+        let payload = match serde_json::from_slice::<WebhookPayload>(&body) {
+            Ok(payload) => payload,
+            Err(e) => {
+                let errors = vec![ValidationError::new(None, "body", &e.to_string())];
+                return json_response(StatusCode::BAD_REQUEST, json!({ "errors": errors }));
+            }
+        };
+        let now = chrono::Utc::now().naive_utc();
+        let items = match validate_items(payload.items, now) {
+            Ok(items) => items,
+            Err(errors) => {
+                return json_response(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    json!({ "errors": errors }),
+                )
+            }
+        };
+        let accepted = items.len();
+        let update = WebhookUpdate {
+            source_id: source.id,
+            items,
+        };
+        if let Err(e) = self
+            .updates_sender
+            .lock()
+            .await
+            .send(Ok(SourceData::Webhook(update)))
+            .await
+        {
+            error!("{}", e);
+            return status(StatusCode::SERVICE_UNAVAILABLE);
+        }
+        json_response(StatusCode::ACCEPTED, json!({ "accepted": accepted }))
+    }
+}
+
+/// Serves endpoints of the webhook sources at `/webhooks/{origin}`
+pub(crate) async fn serve<S>(address: SocketAddr, endpoint: Arc<Endpoint<S>>)
+where
+    S: Storage + Send + Sync + 'static,
+{
+    let make_service = make_service_fn(move |_| {
+        let endpoint = endpoint.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let endpoint = endpoint.clone();
+                async move { Ok::<_, Infallible>(endpoint.handle(request).await) }
+            }))
+        }
+    });
+    info!("webhooks are served at {}", address);
+    if let Err(e) = Server::bind(&address).serve(make_service).await {
+        error!("webhook server failed: {}", e);
+    }
+}
+
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+    let mut response = Response::new(Body::from(body.to_string()));
+    *response.status_mut() = status;
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    response
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}
+
+#[cfg(all(test, feature = "pg-storage"))]
+mod tests {
+    use super::Endpoint;
+    use crate::models;
+    use crate::storage::pg::tests::test_storage;
+    use crate::storage::Storage;
+    use crate::updates::webhook::WebhookSource;
+    use hyper::{Body, Request, StatusCode};
+    use std::sync::Arc;
+    use tokio::sync::{mpsc, Mutex};
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_paused_source_rejected() {
+        let storage = test_storage();
+        let webhook = WebhookSource::builder()
+            .with_storage(storage.clone())
+            .build();
+        let (source, token) = webhook.add_source("test").await.unwrap();
+        let (sender, mut receiver) = mpsc::channel(10);
+        let endpoint = Endpoint::new(storage.clone(), Arc::new(Mutex::new(sender)), 1024);
+        let request = |token: &str| {
+            Request::post(format!("/webhooks/{}", source.origin))
+                .header("Authorization", format!("Bearer {}", token))
+                .body(Body::from(r#"{"items": [{"id": "1", "title": "Pushed"}]}"#))
+                .unwrap()
+        };
+        assert_eq!(
+            endpoint.handle(request("wrong")).await.status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            endpoint.handle(request(&token)).await.status(),
+            StatusCode::ACCEPTED
+        );
+        assert!(receiver.try_recv().is_ok());
+        storage
+            .set_source_state(source.id, models::SOURCE_PAUSED.to_string())
+            .await
+            .unwrap();
+        assert_eq!(
+            endpoint.handle(request(&token)).await.status(),
+            StatusCode::CONFLICT
+        );
+        assert!(receiver.try_recv().is_err());
+        storage.delete_source(source.id).await.unwrap();
+    }
+}
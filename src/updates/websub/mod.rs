@@ -0,0 +1,572 @@
+use super::http::{FeedUpdate, Update, WEB};
+use super::schedule;
+use crate::config::WebSubConfig;
+use crate::models;
+use crate::result::{Error, Result};
+use crate::storage::Storage;
+use crate::tools::resolve_link;
+use chrono::NaiveDateTime;
+use hmac::{Hmac, Mac, NewMac};
+use rand::distributions::Alphanumeric;
+use rand::Rng;
+use regex::Regex;
+use std::collections::HashMap;
+use tokio::sync::mpsc;
+
+mod server;
+
+pub(crate) use server::serve;
+
+lazy_static! {
+    static ref LINK_TAG: Regex = Regex::new(r#"(?i)<(?:atom:)?link\s[^>]*>"#).unwrap();
+    static ref LINK_ATTR: Regex =
+        Regex::new(r#"(?i)\b(rel|href)\s*=\s*(?:"([^"]*)"|'([^']*)')"#).unwrap();
+    static ref LINK_HEADER: Regex =
+        Regex::new(r#"(?i)<([^>]*)>\s*;\s*rel\s*=\s*"?([^";,]*)"?"#).unwrap();
+}
+
+const SECRET_LEN: usize = 32;
+/// Hubs may grant a longer lease than requested, but not longer than this many times
+const MAX_LEASE_FACTOR: i64 = 10;
+
+/// Hub and topic advertised by the feed
+#[derive(Debug, PartialEq)]
+struct HubLinks {
+    hub: String,
+    topic: String,
+}
+
+/// Content distribution of the hub
+#[derive(Debug)]
+pub(crate) struct Notification {
+    pub source_id: i32,
+    /// Pushed feed, `None` if the hub sent no content, so the source must be scraped
+    pub feed: Option<FeedUpdate>,
+}
+
+/// WebSub subscriber of the web feeds.
+///
+/// Feeds advertising a hub are subscribed with the callback served by `serve`.
+/// Feeds pushed by the hub are processed as scraped ones.
+pub(crate) struct WebSub<S> {
+    client: reqwest::Client,
+    storage: S,
+    config: WebSubConfig,
+    notifications: mpsc::Sender<Notification>,
+}
+
+impl<S> WebSub<S>
+where
+    S: Storage + Send + Sync,
+{
+    /// Notifications of the hubs are sent to `notifications`
+    pub fn new(
+        config: &WebSubConfig,
+        storage: S,
+        notifications: mpsc::Sender<Notification>,
+    ) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            storage,
+            config: config.clone(),
+            notifications,
+        }
+    }
+
+    /// Whether updates of the source are pushed by the hub
+    pub async fn is_active(&self, source_id: i32) -> bool {
+        match self.storage.get_websub_subscription(source_id).await {
+            Ok(Some(subscription)) => subscription.state == models::WEBSUB_ACTIVE,
+            Ok(None) => false,
+            Err(e) => {
+                error!("{}", e);
+                false
+            }
+        }
+    }
+
+    /// Subscribes to the hub of the source feed; source is polled if there is no hub
+    /// or subscription fails
+    pub async fn subscribe(&self, source: &models::Source) -> Result<()> {
+        let links = match self.discover_hub(&source.origin).await? {
+            Some(links) => links,
+            None => {
+                debug!("{} doesn't advertise a hub", source.origin);
+                self.storage
+                    .save_websub_subscription(models::NewWebSubSubscription {
+                        source_id: source.id,
+                        hub: String::new(),
+                        topic: source.origin.clone(),
+                        secret: String::new(),
+                        state: models::WEBSUB_UNSUPPORTED.to_string(),
+                    })
+                    .await?;
+                return Ok(());
+            }
+        };
+        let secret = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(SECRET_LEN)
+            .collect::<String>();
+        let subscription = self
+            .storage
+            .save_websub_subscription(models::NewWebSubSubscription {
+                source_id: source.id,
+                hub: links.hub,
+                topic: links.topic,
+                secret,
+                state: models::WEBSUB_PENDING.to_string(),
+            })
+            .await?;
+        if let Err(e) = self.request(&subscription, "subscribe").await {
+            self.storage
+                .set_websub_subscription_state(source.id, models::WEBSUB_FAILED.to_string(), None)
+                .await?;
+            return Err(e);
+        }
+        debug!("subscription to {} is requested", subscription.topic);
+        Ok(())
+    }
+
+    /// Unsubscribes from the hub, subscription is removed after the hub verifies it
+    pub async fn unsubscribe(&self, source_id: i32) -> Result<()> {
+        let subscription = match self.storage.get_websub_subscription(source_id).await? {
+            Some(subscription) => subscription,
+            None => return Ok(()),
+        };
+        if subscription.state != models::WEBSUB_ACTIVE
+            && subscription.state != models::WEBSUB_PENDING
+        {
+            return self.storage.delete_websub_subscription(source_id).await;
+        }
+        self.storage
+            .set_websub_subscription_state(
+                source_id,
+                models::WEBSUB_UNSUBSCRIBING.to_string(),
+                None,
+            )
+            .await?;
+        if let Err(e) = self.request(&subscription, "unsubscribe").await {
+            warn!("can't unsubscribe from {}: {}", subscription.topic, e);
+            self.storage.delete_websub_subscription(source_id).await?;
+        }
+        Ok(())
+    }
+
+    /// Renews expiring subscriptions, retries failed and unverified ones and subscribes
+    /// sources which weren't subscribed yet; unverified unsubscriptions are dropped
+    pub async fn renew(&self) -> Result<()> {
+        let until =
+            schedule::utc_now() + chrono::Duration::seconds(self.config.renew_before_secs as i64);
+        let subscriptions = self
+            .storage
+            .get_websub_subscriptions_for_renewal(
+                until,
+                &self.config.retry_secs_interval,
+                &self.config.verify_timeout_secs,
+            )
+            .await?;
+        let mut sources = vec![];
+        for subscription in subscriptions {
+            if subscription.state == models::WEBSUB_UNSUBSCRIBING {
+                debug!("unsubscription from {} isn't verified", subscription.topic);
+                self.storage
+                    .delete_websub_subscription(subscription.source_id)
+                    .await?;
+                continue;
+            }
+            if let Some(source) = self.storage.get_source(subscription.source_id).await? {
+                if source.state == models::SOURCE_ACTIVE {
+                    sources.push(source);
+                }
+            }
+        }
+        sources.extend(
+            self.storage
+                .get_sources_without_websub(WEB.to_string())
+                .await?,
+        );
+        debug!("{} sources to subscribe with websub", sources.len());
+        for source in sources {
+            if let Err(e) = self.subscribe(&source).await {
+                warn!("can't subscribe to {}: {}", source.origin, e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Verifies intent of the hub, returns the challenge to respond with if the intent
+    /// is requested by the subscriber
+    pub async fn verify_intent(
+        &self,
+        source_id: i32,
+        params: &HashMap<String, String>,
+    ) -> Result<Option<String>> {
+        let subscription = match self.storage.get_websub_subscription(source_id).await? {
+            Some(subscription) => subscription,
+            None => return Ok(None),
+        };
+        let param = |name: &str| params.get(name).map(String::as_str).unwrap_or_default();
+        let topic_matches = param("hub.topic") == subscription.topic;
+        match param("hub.mode") {
+            "subscribe"
+                if topic_matches
+                    && (subscription.state == models::WEBSUB_PENDING
+                        || subscription.state == models::WEBSUB_ACTIVE) =>
+            {
+                let lease_secs = lease_secs(param("hub.lease_seconds"), self.config.lease_secs);
+                let expires_at = schedule::utc_now()
+                    .checked_add_signed(chrono::Duration::seconds(lease_secs))
+                    .ok_or_else(|| {
+                        Error::WebSubError(format!("invalid lease of {}s", lease_secs))
+                    })?;
+                self.storage
+                    .set_websub_subscription_state(
+                        source_id,
+                        models::WEBSUB_ACTIVE.to_string(),
+                        Some(expires_at),
+                    )
+                    .await?;
+                info!("subscribed to {} for {}s", subscription.topic, lease_secs);
+                Ok(Some(param("hub.challenge").to_string()))
+            }
+            "unsubscribe"
+                if topic_matches && subscription.state == models::WEBSUB_UNSUBSCRIBING =>
+            {
+                self.storage.delete_websub_subscription(source_id).await?;
+                info!("unsubscribed from {}", subscription.topic);
+                Ok(Some(param("hub.challenge").to_string()))
+            }
+            // hub denies only pending requests, active subscription isn't failed by anyone
+            "denied" if topic_matches && subscription.state == models::WEBSUB_PENDING => {
+                warn!(
+                    "subscription to {} is denied: {}",
+                    subscription.topic,
+                    param("hub.reason")
+                );
+                self.storage
+                    .set_websub_subscription_state(
+                        source_id,
+                        models::WEBSUB_FAILED.to_string(),
+                        None,
+                    )
+                    .await?;
+                Ok(Some(String::new()))
+            }
+            mode => {
+                debug!("unexpected {} intent for {}", mode, subscription.topic);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Handles content distribution: notification with invalid signature is ignored,
+    /// body which isn't a feed is rejected
+    pub async fn notify(&self, source_id: i32, signature: Option<&str>, body: &[u8]) -> Result<()> {
+        let subscription = self
+            .storage
+            .get_websub_subscription(source_id)
+            .await?
+            .ok_or(Error::SourceNotFound)?;
+        if subscription.state != models::WEBSUB_ACTIVE {
+            debug!("skip notification of inactive {}", subscription.topic);
+            return Ok(());
+        }
+        if !verify_signature(&subscription.secret, signature, body) {
+            warn!("invalid notification signature of {}", subscription.topic);
+            return Ok(());
+        }
+        let feed = match body.iter().all(u8::is_ascii_whitespace) {
+            true => None,
+            false => {
+                let source = self
+                    .storage
+                    .get_source(source_id)
+                    .await?
+                    .ok_or(Error::SourceNotFound)?;
+                Some(parse_feed(&source, body, schedule::utc_now())?)
+            }
+        };
+        self.notifications
+            .clone()
+            .send(Notification { source_id, feed })
+            .await
+            .map_err(|e| Error::WebSubError(e.to_string()))
+    }
+
+    async fn request(&self, subscription: &models::WebSubSubscription, mode: &str) -> Result<()> {
+        let callback = format!(
+            "{}/websub/{}",
+            self.config.callback_url.trim_end_matches('/'),
+            subscription.source_id
+        );
+        let lease_secs = self.config.lease_secs.to_string();
+        let mut params = vec![
+            ("hub.callback", callback.as_str()),
+            ("hub.mode", mode),
+            ("hub.topic", subscription.topic.as_str()),
+        ];
+        if mode == "subscribe" {
+            params.push(("hub.lease_seconds", lease_secs.as_str()));
+            params.push(("hub.secret", subscription.secret.as_str()));
+        }
+        let response = self
+            .client
+            .post(&subscription.hub)
+            .form(&params)
+            .send()
+            .await
+            .map_err(|e| Error::WebSubError(e.to_string()))?;
+        match response.status().is_success() {
+            true => Ok(()),
+            false => Err(Error::WebSubError(format!(
+                "hub {} responded with {}",
+                subscription.hub,
+                response.status()
+            ))),
+        }
+    }
+
+    async fn discover_hub(&self, feed_url: &str) -> Result<Option<HubLinks>> {
+        let response = self
+            .client
+            .get(feed_url)
+            .send()
+            .await
+            .map_err(|e| Error::WebSubError(e.to_string()))?;
+        let link_headers = response
+            .headers()
+            .get_all(reqwest::header::LINK)
+            .iter()
+            .filter_map(|h| h.to_str().ok())
+            .map(String::from)
+            .collect::<Vec<String>>();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| Error::WebSubError(e.to_string()))?;
+        Ok(hub_links(feed_url, &link_headers, &body))
+    }
+}
+
+/// Finds hub and self links in `Link` headers, then in the feed links
+fn hub_links(feed_url: &str, link_headers: &[String], body: &str) -> Option<HubLinks> {
+    let mut links = vec![];
+    for header in link_headers {
+        for cap in LINK_HEADER.captures_iter(header) {
+            links.push((cap[2].to_string(), cap[1].to_string()));
+        }
+    }
+    for tag in LINK_TAG.find_iter(body) {
+        let mut rel = None;
+        let mut href = None;
+        for cap in LINK_ATTR.captures_iter(tag.as_str()) {
+            let value = cap
+                .get(2)
+                .or_else(|| cap.get(3))
+                .unwrap()
+                .as_str()
+                .to_string();
+            match cap[1].to_lowercase().as_str() {
+                "rel" => rel = Some(value),
+                _ => href = Some(value),
+            }
+        }
+        if let (Some(rel), Some(href)) = (rel, href) {
+            links.push((rel, href));
+        }
+    }
+    let find = |name: &str| {
+        links
+            .iter()
+            .find(|(rel, _)| rel.split_whitespace().any(|r| r.eq_ignore_ascii_case(name)))
+            .and_then(|(_, href)| resolve_link(feed_url, href))
+            .map(|url| url.to_string())
+    };
+    Some(HubLinks {
+        hub: find("hub")?,
+        topic: find("self").unwrap_or_else(|| feed_url.to_string()),
+    })
+}
+
+/// Parses feed pushed by the hub, entries without a date get the notification time
+fn parse_feed(source: &models::Source, body: &[u8], now: NaiveDateTime) -> Result<FeedUpdate> {
+    let feed = feed_rs::parser::parse(body).map_err(|e| Error::InvalidContent(e.to_string()))?;
+    Ok(FeedUpdate {
+        link: source.origin.clone(),
+        kind: None,
+        name: source.name.clone(),
+        image: source.image.clone(),
+        ttl: None,
+        update_period: None,
+        update_frequency: None,
+        updates: feed
+            .entries
+            .into_iter()
+            .map(|e| Update {
+                title: e.title.map(|t| t.content),
+                content: e
+                    .content
+                    .and_then(|c| c.body)
+                    .or_else(|| e.summary.map(|s| s.content))
+                    .unwrap_or_default(),
+                pub_date: e
+                    .published
+                    .or(e.updated)
+                    .map(|d| d.naive_utc())
+                    .unwrap_or(now),
+                link: e
+                    .links
+                    .iter()
+                    .find(|l| l.rel.as_deref().unwrap_or("alternate") == "alternate")
+                    .map(|l| l.href.clone()),
+                guid: e.id,
+                image_link: None,
+            })
+            .collect(),
+    })
+}
+
+/// Lease granted by the hub, missing lease is the requested one, lease out of
+/// `0..=requested * MAX_LEASE_FACTOR` is clamped
+fn lease_secs(lease_seconds: &str, requested_secs: i32) -> i64 {
+    let max_secs = requested_secs as i64 * MAX_LEASE_FACTOR;
+    lease_seconds
+        .parse::<i64>()
+        .unwrap_or(requested_secs as i64)
+        .min(max_secs)
+        .max(0)
+}
+
+/// Checks `X-Hub-Signature` of the notification: `method=hex(hmac(secret, body))`
+fn verify_signature(secret: &str, signature: Option<&str>, body: &[u8]) -> bool {
+    let (method, signature) = match signature.and_then(|s| {
+        let mut parts = s.splitn(2, '=');
+        Some((parts.next()?, hex::decode(parts.next()?).ok()?))
+    }) {
+        Some(signature) => signature,
+        None => return false,
+    };
+    macro_rules! verify {
+        ($hash:ty) => {
+            match Hmac::<$hash>::new_varkey(secret.as_bytes()) {
+                Ok(mut mac) => {
+                    mac.update(body);
+                    mac.verify(&signature).is_ok()
+                }
+                Err(_) => false,
+            }
+        };
+    }
+    match method {
+        "sha1" => verify!(sha1::Sha1),
+        "sha256" => verify!(sha2::Sha256),
+        "sha384" => verify!(sha2::Sha384),
+        "sha512" => verify!(sha2::Sha512),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hub_links, lease_secs, parse_feed, verify_signature, HubLinks};
+    use crate::models;
+    use chrono::NaiveDate;
+
+    #[test]
+    fn test_hub_links() {
+        let feed = "https://example.com/feed.xml";
+        let body = r#"<feed xmlns="http://www.w3.org/2005/Atom">
+            <link rel="alternate" href="https://example.com/"/>
+            <link rel="hub" href="https://hub.example.com/"/>
+            <atom:link href='/feed' rel='self' type="application/atom+xml"/>"#;
+        assert_eq!(
+            hub_links(feed, &[], body),
+            Some(HubLinks {
+                hub: "https://hub.example.com/".to_string(),
+                topic: "https://example.com/feed".to_string(),
+            })
+        );
+        let headers = vec![
+            r#"<https://hub.example.com/>; rel="hub", <https://example.com/feed.xml>; rel="self""#
+                .to_string(),
+        ];
+        assert_eq!(
+            hub_links(feed, &headers, ""),
+            Some(HubLinks {
+                hub: "https://hub.example.com/".to_string(),
+                topic: feed.to_string(),
+            })
+        );
+        assert_eq!(hub_links(feed, &[], "<rss><channel></channel></rss>"), None);
+    }
+
+    #[test]
+    fn test_lease_secs() {
+        assert_eq!(lease_secs("3600", 86400), 3600);
+        assert_eq!(lease_secs("", 86400), 86400);
+        assert_eq!(lease_secs("-1", 86400), 0);
+        assert_eq!(lease_secs("9223372036854775807", 86400), 864000);
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let sha1 = "sha1=a18991ff7e4513a1c2d2ee51e3a8e99ca891d9cd";
+        let sha256 = "sha256=dc46983557fea127b43af721467eb9b3fde2338fe3e14f51952aa8478c13d355";
+        assert!(verify_signature("secret", Some(sha1), b"body"));
+        assert!(verify_signature("secret", Some(sha256), b"body"));
+        assert!(!verify_signature("secret", Some(sha1), b"changed body"));
+        assert!(!verify_signature("other secret", Some(sha1), b"body"));
+        assert!(!verify_signature("secret", None, b"body"));
+        assert!(!verify_signature("secret", Some("md5=00"), b"body"));
+    }
+
+    #[test]
+    fn test_parse_feed() {
+        let now = NaiveDate::from_ymd(2021, 1, 22).and_hms(12, 0, 0);
+        let source = models::Source {
+            id: 1,
+            name: "Example".to_string(),
+            origin: "https://example.com/feed".to_string(),
+            kind: "WEB".to_string(),
+            image: None,
+            last_scrape_time: now,
+            external_link: "https://example.com/".to_string(),
+            account: None,
+            state: models::SOURCE_ACTIVE.to_string(),
+            scrape_interval: None,
+            adaptive_interval: None,
+            next_scrape_time: now,
+            feed_kind: Some("atom".to_string()),
+            fetch_full_content: false,
+            image_remote_id: None,
+        };
+        let body = br#"<?xml version="1.0" encoding="utf-8"?>
+            <feed xmlns="http://www.w3.org/2005/Atom">
+                <title>Example</title>
+                <id>https://example.com/</id>
+                <updated>2021-01-22T10:00:00Z</updated>
+                <entry>
+                    <id>https://example.com/1</id>
+                    <title>First</title>
+                    <link rel="alternate" href="https://example.com/1"/>
+                    <updated>2021-01-22T10:00:00Z</updated>
+                    <content type="html">&lt;p&gt;Pushed&lt;/p&gt;</content>
+                </entry>
+            </feed>"#;
+        let feed = parse_feed(&source, body, now).unwrap();
+        assert_eq!(feed.link, source.origin);
+        assert!(feed.kind.is_none());
+        assert_eq!(feed.updates.len(), 1);
+        let update = &feed.updates[0];
+        assert_eq!(update.guid, "https://example.com/1");
+        assert_eq!(update.title.as_deref(), Some("First"));
+        assert_eq!(update.content, "<p>Pushed</p>");
+        assert_eq!(update.link.as_deref(), Some("https://example.com/1"));
+        assert_eq!(
+            update.pub_date,
+            NaiveDate::from_ymd(2021, 1, 22).and_hms(10, 0, 0)
+        );
+        assert!(parse_feed(&source, b"not a feed", now).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+use super::WebSub;
+use crate::storage::Storage;
+use crate::tools::read_body;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+/// Serves callbacks of the hubs at `/websub/{source_id}`:
+/// `GET` verifies intents, `POST` receives notifications
+pub(crate) async fn serve<S>(address: SocketAddr, websub: Arc<WebSub<S>>)
+where
+    S: Storage + Send + Sync + 'static,
+{
+    let make_service = make_service_fn(move |_| {
+        let websub = websub.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request| {
+                let websub = websub.clone();
+                async move { Ok::<_, Infallible>(handle(&websub, request).await) }
+            }))
+        }
+    });
+    info!("websub callbacks are served at {}", address);
+    if let Err(e) = Server::bind(&address).serve(make_service).await {
+        error!("websub server failed: {}", e);
+    }
+}
+
+async fn handle<S>(websub: &WebSub<S>, request: Request<Body>) -> Response<Body>
+where
+    S: Storage + Send + Sync,
+{
+    let source_id = match request
+        .uri()
+        .path()
+        .strip_prefix("/websub/")
+        .and_then(|id| id.parse::<i32>().ok())
+    {
+        Some(source_id) => source_id,
+        None => return status(StatusCode::NOT_FOUND),
+    };
+    match *request.method() {
+        Method::GET => {
+            let params = url::form_urlencoded::parse(request.uri().query().unwrap_or_default())
+                .into_owned()
+                .collect::<HashMap<String, String>>();
+            match websub.verify_intent(source_id, &params).await {
+                Ok(Some(challenge)) => Response::new(Body::from(challenge)),
+                Ok(None) => status(StatusCode::NOT_FOUND),
+                Err(e) => {
+                    error!("{}", e);
+                    status(StatusCode::INTERNAL_SERVER_ERROR)
+                }
+            }
+        }
+        Method::POST => {
+            let signature = request
+                .headers()
+                .get("X-Hub-Signature")
+                .and_then(|h| h.to_str().ok())
+                .map(String::from);
+            let body = match read_body(request.into_body(), websub.config.max_body_size).await {
+                Ok(body) => body,
+                Err(code) => return status(code),
+            };
+            // notifications are acknowledged even if they are ignored, as the spec requires
+            if let Err(e) = websub.notify(source_id, signature.as_deref(), &body).await {
+                warn!("can't handle notification of {}: {}", source_id, e);
+            }
+            status(StatusCode::ACCEPTED)
+        }
+        _ => status(StatusCode::METHOD_NOT_ALLOWED),
+    }
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    let mut response = Response::new(Body::empty());
+    *response.status_mut() = status;
+    response
+}